@@ -14,12 +14,13 @@ after=${SECONDS}
 printf "souffle: %s\n" "$((after-before))s"
 
 
-cargo build --quiet --release --example tc
+cargo build --quiet --release
+mkdir -p duckalog-out
 before=${SECONDS}
-./target/release/examples/tc  < edge.csv > path2.csv
+./target/release/duckalog -D duckalog-out examples/tc.dl
 after=${SECONDS}
 printf "duckalog: %s\n" "$((after-before))s"
 
 sort path.csv > path.sorted.csv
-sort path2.csv > path2.sorted.csv
+sort duckalog-out/path.csv > path2.sorted.csv
 diff -u path.sorted.csv path2.sorted.csv
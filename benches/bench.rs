@@ -3,10 +3,11 @@ use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 
 #[cfg(feature = "duckdb")]
 use duckdb::Connection;
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(feature = "duckdb")))]
 use rusqlite::Connection;
 
 use duckalog::ast::{Ast, Atom, Const, Rel, Rule, Term, Var};
+use duckalog::backend::Source;
 use duckalog::eval::Eval;
 use duckalog::mir::Mir;
 
@@ -74,6 +75,14 @@ pub fn tc_line_30(c: &mut Criterion) {
     tc_line(c, 30);
 }
 
+pub fn tc_line_100(c: &mut Criterion) {
+    tc_line(c, 100);
+}
+
+pub fn tc_line_200(c: &mut Criterion) {
+    tc_line(c, 200);
+}
+
 /// Transitive closure on a complete graph
 pub fn tc_complete(c: &mut Criterion, n: usize) {
     let (mut mir, edge, path) = tc();
@@ -105,11 +114,50 @@ pub fn tc_complete_30(c: &mut Criterion) {
     tc_complete(c, 30);
 }
 
+/// Loading a CSV file of `n` edges, without evaluating
+pub fn load(c: &mut Criterion, n: usize) {
+    let (mir, edge, _) = tc();
+    let path = std::env::temp_dir().join(format!("duckalog-bench-load-{n}.csv"));
+    let mut csv = String::new();
+    for i in 0..n {
+        csv.push_str(&format!("c{i},c{}\n", i * 7919 % n));
+    }
+    std::fs::write(&path, csv).unwrap();
+    let source = Source::csv(&path);
+    c.bench_function(&format!("load_{n}"), |b| {
+        b.iter(|| {
+            let conn = Connection::open_in_memory().unwrap();
+            let eval = Eval::new(conn, mir.clone()).unwrap();
+            eval.load(&edge, black_box(&source)).unwrap();
+            eval
+        })
+    });
+    std::fs::remove_file(path).unwrap();
+}
+
+pub fn load_100000(c: &mut Criterion) {
+    load(c, 100000);
+}
+
+/// Takes seconds per iteration, so only runs if `DUCKALOG_BENCH_LARGE` is set
+pub fn load_1000000(c: &mut Criterion) {
+    if std::env::var_os("DUCKALOG_BENCH_LARGE").is_some() {
+        load(c, 1000000);
+    }
+}
+
 criterion_group! {
     name = benches;
     config = Criterion::default();
     targets =
-      tc_line_10, tc_line_20, tc_line_30,
+      tc_line_10, tc_line_20, tc_line_30, tc_line_100, tc_line_200,
       tc_complete_10, tc_complete_20, tc_complete_30,
 }
-criterion_main!(benches);
+
+// Each iteration takes most of a second or more, so take fewer samples
+criterion_group! {
+    name = slow_benches;
+    config = Criterion::default().sample_size(10);
+    targets = load_100000, load_1000000,
+}
+criterion_main!(benches, slow_benches);
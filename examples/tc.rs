@@ -2,34 +2,25 @@
 
 #[cfg(feature = "duckdb")]
 use duckdb::Connection;
-#[cfg(feature = "sqlite")]
+#[cfg(all(feature = "sqlite", not(feature = "duckdb")))]
 use rusqlite::Connection;
 
 use duckalog::{
-    ast::{Ast, Atom, Const, Rel, Rule, Term, Var},
+    ast::{Const, Rel},
     eval::Eval,
     mir::Mir,
+    parse,
 };
 
 fn main() {
-    let x = Term::Var(Var::new("X".to_string()).unwrap());
-    let y = Term::Var(Var::new("Y".to_string()).unwrap());
-    let z = Term::Var(Var::new("Z".to_string()).unwrap());
     let edge = Rel::new("edge".to_string());
     let path = Rel::new("path".to_string());
-    let ast = Ast::new(vec![
-        Rule::new(
-            Atom::new(path.clone(), vec![x.clone(), y.clone()]),
-            vec![Atom::new(edge.clone(), vec![x.clone(), y.clone()])],
-        ),
-        Rule::new(
-            Atom::new(path.clone(), vec![x.clone(), z.clone()]),
-            vec![
-                Atom::new(edge.clone(), vec![x.clone(), y.clone()]),
-                Atom::new(path.clone(), vec![y.clone(), z.clone()]),
-            ],
-        ),
-    ])
+    let ast = parse::parse(
+        r"
+        path(X, Y) :- edge(X, Y).
+        path(X, Z) :- edge(X, Y), path(Y, Z).
+        ",
+    )
     .unwrap();
 
     let mut mir = Mir::new(ast).unwrap();
@@ -45,8 +36,8 @@ fn main() {
         mir.add_fact(
             &edge,
             vec![
-                Const::new(rec[0].to_string()).unwrap(),
-                Const::new(rec[1].to_string()).unwrap(),
+                Const::Sym(rec[0].to_string()),
+                Const::Sym(rec[1].to_string()),
             ],
         );
     }
@@ -1,65 +1,408 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::Display;
+use std::hash::BuildHasher;
 
 #[cfg(feature = "duckdb")]
 use duckdb::types::{ToSqlOutput, ValueRef};
 
+use crate::diag::Diagnostic;
+use crate::span::{Loc, Span};
+use crate::strat::{Cycle, Graph};
+
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, thiserror::Error)]
 pub enum Error {
+    #[error("aggregate `{aggregate}` outside of a rule head")]
+    AggregateBody {
+        aggregate: Aggregate,
+        span: Option<Span>,
+    },
+    #[error("aggregation through recursion: {}", fmt_cycle(.cycle))]
+    AggregateCycle {
+        /// Starts and ends with the same relation
+        cycle: Vec<Rel>,
+        /// The body atom of the aggregating rule
+        span: Option<Span>,
+    },
+    #[error("cannot compute `{aggregate}` of values of type `{ty}`")]
+    AggregateType {
+        aggregate: Aggregate,
+        ty: Type,
+        span: Option<Span>,
+    },
+    #[error("arithmetic on `{ty}` in `{expr}`")]
+    Arith {
+        expr: Expr,
+        ty: Type,
+        span: Option<Span>,
+    },
     #[error("relation `{relation}` used with multiple arities: `{arity1}`, `{arity2}`")]
     Arity {
         relation: Rel,
         arity1: usize,
         arity2: usize,
+        /// The declaration or first use of `relation`, which had arity `arity1`
+        span1: Option<Span>,
+        /// The conflicting use of `relation`, which had arity `arity2`
+        span2: Option<Span>,
+    },
+    #[error("comparison `{cmp}` between `{type1}` and `{type2}`")]
+    Cmp {
+        cmp: Box<Comparison>,
+        type1: Type,
+        type2: Type,
+        span: Option<Span>,
+    },
+    #[error("invalid column name `{name}` in declaration of relation `{relation}`")]
+    Column {
+        relation: Rel,
+        name: String,
+        span: Option<Span>,
     },
-    #[error("ungrounded variable `{var}` in rule `{rule}`")]
-    Ungrounded { rule: Rule, var: Var },
+    #[error("negation through recursion: {}", fmt_cycle(.cycle))]
+    Negation {
+        /// Starts and ends with the same relation
+        cycle: Vec<Rel>,
+        /// The negated atom
+        span: Option<Span>,
+    },
+    #[error("relation `{relation}` declared more than once")]
+    Redeclared {
+        relation: Rel,
+        span1: Option<Span>,
+        span2: Option<Span>,
+    },
+    #[error(
+        "column {column} of relation `{relation}` used with multiple types: `{type1}`, `{type2}`"
+    )]
+    Type {
+        relation: Rel,
+        column: usize,
+        type1: Type,
+        type2: Type,
+        /// The declaration or use that first determined the type of the column
+        span1: Option<Span>,
+        /// The conflicting use
+        span2: Option<Span>,
+    },
+    #[error("ungrounded {} in rule `{rule}`", fmt_vars(.vars))]
+    Ungrounded { rule: Box<Rule>, vars: Vec<Var> },
+}
+
+/// `` `p` -> `q` -> `p` ``
+pub(crate) fn fmt_cycle(cycle: &[Rel]) -> String {
+    let names: Vec<_> = cycle.iter().map(|r| format!("`{r}`")).collect();
+    names.join(" -> ")
+}
+
+/// `variable `X`` or `variables `X`, `Y``
+pub(crate) fn fmt_vars(vars: &[Var]) -> String {
+    let names: Vec<_> = vars.iter().map(|v| format!("`{v}`")).collect();
+    format!(
+        "variable{} {}",
+        if vars.len() == 1 { "" } else { "s" },
+        names.join(", ")
+    )
+}
+
+impl Error {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let d = Diagnostic::new(self.to_string());
+        match self {
+            Error::AggregateBody { span, .. } => {
+                d.with_primary(*span, String::from("aggregate in rule body"))
+            }
+            Error::AggregateCycle { cycle, span } => d.with_primary(
+                *span,
+                format!("`{}` aggregates over itself through this atom", cycle[0]),
+            ),
+            Error::AggregateType { span, .. } => {
+                d.with_primary(*span, String::from("aggregated here"))
+            }
+            Error::Arith { ty, span, .. } => {
+                d.with_primary(*span, format!("operand has type `{ty}`"))
+            }
+            Error::Arity {
+                arity1,
+                arity2,
+                span1,
+                span2,
+                ..
+            } => d
+                .with_primary(*span2, format!("used with arity {arity2}"))
+                .with_note(*span1, format!("first used with arity {arity1}")),
+            Error::Cmp {
+                type1, type2, span, ..
+            } => d.with_primary(*span, format!("`{type1}` compared with `{type2}`")),
+            Error::Column { name, span, .. } => {
+                let msg = if RESERVED_COLUMNS.contains(&name.to_lowercase().as_str()) {
+                    "reserved for internal use"
+                } else {
+                    "declared more than once"
+                };
+                d.with_primary(*span, String::from(msg))
+            }
+            Error::Negation { cycle, span } => d.with_primary(
+                *span,
+                format!("`{}` depends on itself through this negation", cycle[0]),
+            ),
+            Error::Redeclared { span1, span2, .. } => d
+                .with_primary(*span2, String::from("declared again"))
+                .with_note(*span1, String::from("first declared")),
+            Error::Type {
+                type1,
+                type2,
+                span1,
+                span2,
+                ..
+            } => d
+                .with_primary(*span2, format!("used as `{type2}`"))
+                .with_note(*span1, format!("first used as `{type1}`")),
+            Error::Ungrounded { rule, vars } => {
+                // The first literal with one of the variables
+                let span = if rule.head.terms.iter().any(|t| t.var() == Some(&vars[0])) {
+                    rule.head.span()
+                } else {
+                    rule.body
+                        .iter()
+                        .find(|l| l.positive().is_none() && l.vars().contains(&&vars[0]))
+                        .and_then(Literal::span)
+                };
+                d.with_primary(
+                    span.or(rule.span()),
+                    format!("{} not bound in the body", fmt_vars(vars)),
+                )
+            }
+        }
+    }
+}
+
+impl From<Cycle<'_>> for Error {
+    fn from(c: Cycle<'_>) -> Self {
+        if c.aggregate {
+            Error::AggregateCycle {
+                cycle: c.rels,
+                span: c.atom.span(),
+            }
+        } else {
+            Error::Negation {
+                cycle: c.rels,
+                span: c.atom.span(),
+            }
+        }
+    }
+}
+
+// ------------------------------------------------------------------
+
+/// The type of a [`Const`], and of each column of a relation.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Type {
+    Bool,
+    Int,
+    Float,
+    Sym,
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Bool => write!(f, "bool"),
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Sym => write!(f, "symbol"),
+        }
+    }
+}
+
+impl Type {
+    /// The SQL type of columns of this type
+    pub fn sql(&self) -> &'static str {
+        match self {
+            Type::Bool => "BOOLEAN",
+            Type::Int => "BIGINT",
+            Type::Float => "DOUBLE",
+            Type::Sym => "TEXT",
+        }
+    }
+}
+
+// ------------------------------------------------------------------
+
+/// An `f64` that is [`Eq`], [`Hash`], and [`Ord`], via [`f64::total_cmp`].
+/// Like in SQL, `-0.0` equals `0.0`.
+#[derive(Clone, Copy, Debug)]
+pub struct Float(pub f64);
+
+impl Float {
+    /// `self`, with `-0.0` replaced by `0.0`
+    fn key(self) -> f64 {
+        if self.0 == 0.0 {
+            0.0
+        } else {
+            self.0
+        }
+    }
+}
+
+impl PartialEq for Float {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == std::cmp::Ordering::Equal
+    }
+}
+
+impl Eq for Float {}
+
+impl PartialOrd for Float {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Float {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.key().total_cmp(&other.key())
+    }
+}
+
+impl std::hash::Hash for Float {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.key().to_bits().hash(state)
+    }
+}
+
+impl Display for Float {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Always print a `.` or `e`, so that floats don't look like integers,
+        // and use an exponent for very large and very small magnitudes
+        let x = self.0;
+        if !x.is_finite() {
+            write!(f, "{x}")
+        } else if x != 0.0 && !(1e-5..1e16).contains(&x.abs()) {
+            write!(f, "{x:e}")
+        } else if x.fract() == 0.0 {
+            write!(f, "{x:.1}")
+        } else {
+            write!(f, "{x}")
+        }
+    }
 }
 
 // ------------------------------------------------------------------
 
-// TODO(lb, low): other types
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct Const(String);
+pub enum Const {
+    Bool(bool),
+    Int(i64),
+    Float(Float),
+    // TODO(lb, low): small string optimization, or hash-consing
+    Sym(String),
+}
 
 impl Display for Const {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        match self {
+            Const::Bool(b) => write!(f, "{b}"),
+            Const::Int(i) => write!(f, "{i}"),
+            Const::Float(x) => write!(f, "{x}"),
+            Const::Sym(s) if Self::bare(s) => write!(f, "{s}"),
+            Const::Sym(s) => write!(f, "{s:?}"),
+        }
     }
 }
 
-#[cfg(features = "duckdb")]
+#[cfg(feature = "duckdb")]
 impl duckdb::ToSql for Const {
     fn to_sql(&self) -> duckdb::Result<ToSqlOutput<'_>> {
-        Ok(ToSqlOutput::Borrowed(ValueRef::Text(self.0.as_bytes())))
+        Ok(ToSqlOutput::Borrowed(match self {
+            Const::Bool(b) => ValueRef::Boolean(*b),
+            Const::Int(i) => ValueRef::BigInt(*i),
+            Const::Float(x) => ValueRef::Double(x.0),
+            Const::Sym(s) => ValueRef::Text(s.as_bytes()),
+        }))
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl rusqlite::ToSql for Const {
+    fn to_sql(&self) -> rusqlite::Result<rusqlite::types::ToSqlOutput<'_>> {
+        use rusqlite::types::{ToSqlOutput, ValueRef};
+        Ok(ToSqlOutput::Borrowed(match self {
+            Const::Bool(b) => ValueRef::Integer(i64::from(*b)),
+            Const::Int(i) => ValueRef::Integer(*i),
+            Const::Float(x) => ValueRef::Real(x.0),
+            Const::Sym(s) => ValueRef::Text(s.as_bytes()),
+        }))
     }
 }
 
 impl From<Const> for String {
     fn from(c: Const) -> Self {
-        c.0
+        match c {
+            Const::Sym(s) => s,
+            c => c.to_string(),
+        }
     }
 }
 
 impl Const {
+    /// A symbol
     pub fn new(s: String) -> Option<Self> {
         if Self::valid(&s) {
-            Some(Self(s))
+            Some(Self::Sym(s))
         } else {
             None
         }
     }
 
+    /// A symbol
     pub fn new_unchecked(s: String) -> Self {
-        Self(s)
+        Self::Sym(s)
+    }
+
+    /// Read a value of type `ty` from plain text, e.g., a field of a CSV file.
+    /// Symbols are taken verbatim.
+    pub fn parse(ty: Type, s: &str) -> Option<Self> {
+        match ty {
+            Type::Bool => s.parse().ok().map(Const::Bool),
+            Type::Int => s.parse().ok().map(Const::Int),
+            Type::Float => s.parse().ok().map(|x| Const::Float(Float(x))),
+            Type::Sym => Some(Const::Sym(String::from(s))),
+        }
     }
 
+    /// 0 of a numeric type, the `count` or `sum` of nothing
+    pub(crate) fn zero(ty: Type) -> Self {
+        debug_assert!(matches!(ty, Type::Int | Type::Float));
+        match ty {
+            Type::Float => Const::Float(Float(0.0)),
+            _ => Const::Int(0),
+        }
+    }
+
+    /// Is this string a valid unquoted symbol?
     pub fn valid(s: &str) -> bool {
         match s.chars().next() {
             None => false,
             Some(c) => c.is_lowercase(),
         }
     }
+
+    /// Can this symbol be printed without quotes?
+    fn bare(s: &str) -> bool {
+        Self::valid(s)
+            && s.chars().all(|c| c.is_alphanumeric() || c == '_')
+            && s != "true"
+            && s != "false"
+    }
+
+    pub fn ty(&self) -> Type {
+        match self {
+            Const::Bool(_) => Type::Bool,
+            Const::Int(_) => Type::Int,
+            Const::Float(_) => Type::Float,
+            Const::Sym(_) => Type::Sym,
+        }
+    }
 }
 
 // ------------------------------------------------------------------
@@ -71,7 +414,12 @@ pub struct Var(String);
 
 impl Display for Var {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        // Each `_` is a fresh variable, see [`Var::is_wildcard`]
+        if self.is_wildcard() {
+            write!(f, "_")
+        } else {
+            write!(f, "{}", self.0)
+        }
     }
 }
 
@@ -100,12 +448,93 @@ impl Var {
             Some(c) => c.is_uppercase(),
         }
     }
+
+    /// Is this a `_`? The parser gives each one a distinct name that isn't
+    /// [`Var::valid`].
+    pub fn is_wildcard(&self) -> bool {
+        self.0.starts_with('_')
+    }
+}
+
+// ------------------------------------------------------------------
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AggFunc {
+    Count,
+    Sum,
+    Min,
+    Max,
+}
+
+impl Display for AggFunc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl AggFunc {
+    pub fn new(name: &str) -> Option<Self> {
+        match name {
+            "count" => Some(AggFunc::Count),
+            "sum" => Some(AggFunc::Sum),
+            "min" => Some(AggFunc::Min),
+            "max" => Some(AggFunc::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AggFunc::Count => "count",
+            AggFunc::Sum => "sum",
+            AggFunc::Min => "min",
+            AggFunc::Max => "max",
+        }
+    }
+}
+
+/// An aggregate over the matches of a rule body, e.g., `count(Y)` in
+/// `deg(X, count(Y)) :- edge(X, Y).` Only allowed in rule heads, where the
+/// other terms form the groups. The parser rewrites aggregates in rule
+/// bodies, like `C = count : { edge(X, _) }`, into rules with aggregates in
+/// their heads (see [`Rel::is_aggregate`]).
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Aggregate {
+    pub(crate) func: AggFunc,
+    pub(crate) var: Var,
+}
+
+impl Display for Aggregate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({})", self.func, self.var)
+    }
+}
+
+impl Aggregate {
+    pub fn new(func: AggFunc, var: Var) -> Self {
+        Self { func, var }
+    }
+
+    pub fn func(&self) -> AggFunc {
+        self.func
+    }
+
+    pub fn var(&self) -> &Var {
+        &self.var
+    }
+
+    /// Whether the result has the same type as the variable (otherwise, it's
+    /// an `int`)
+    fn preserves_type(&self) -> bool {
+        self.func != AggFunc::Count
+    }
 }
 
 // ------------------------------------------------------------------
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub enum Term {
+    Aggregate(Aggregate),
     Const(Const),
     Var(Var),
 }
@@ -113,12 +542,24 @@ pub enum Term {
 impl Display for Term {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
+            Term::Aggregate(a) => write!(f, "{}", a),
             Term::Const(c) => write!(f, "{}", c),
             Term::Var(v) => write!(f, "{}", v),
         }
     }
 }
 
+impl Term {
+    /// The variable in this term, if any
+    pub fn var(&self) -> Option<&Var> {
+        match self {
+            Term::Aggregate(a) => Some(&a.var),
+            Term::Const(_) => None,
+            Term::Var(v) => Some(v),
+        }
+    }
+}
+
 // ------------------------------------------------------------------
 
 // TODO(lb, low): small string optimization, or:
@@ -136,6 +577,32 @@ impl Rel {
     pub fn new(name: String) -> Self {
         Self(name)
     }
+
+    /// The relation that holds the results of an aggregate in a rule body,
+    /// like `C = count : { edge(X, _) }`. The parser replaces such aggregates
+    /// with atoms of these relations, defined by rules with aggregates in
+    /// their heads. `id` identifies the defining rule.
+    ///
+    /// The last column is the result, the others are the groups. Rules read
+    /// these relations like functions of the groups, which must be bound by
+    /// the rest of the body (see [`Rule::bound`]).
+    pub(crate) fn aggregate(func: AggFunc, id: u64) -> Self {
+        Self(format!("@agg:{func}:{id:016x}"))
+    }
+
+    /// Whether this relation holds the results of an aggregate in a rule
+    /// body. Such relations can't be named in source text.
+    pub fn is_aggregate(&self) -> bool {
+        self.0.starts_with("@agg:")
+    }
+
+    /// Whether this relation holds the results of a `count` or `sum` in a
+    /// rule body (see [`Rel::aggregate`]). As in Soufflé, the result for
+    /// groups without matches is 0, rather than nothing: reading the relation
+    /// in a rule gives 0 for groups that have no fact.
+    pub fn is_total(&self) -> bool {
+        self.0.starts_with("@agg:count:") || self.0.starts_with("@agg:sum:")
+    }
 }
 
 // ------------------------------------------------------------------
@@ -144,6 +611,7 @@ impl Rel {
 pub struct Atom {
     pub(crate) rel: Rel,
     pub(crate) terms: Vec<Term>, // TODO(lb, low): small vec optimization
+    pub(crate) loc: Loc,
 }
 
 impl Display for Atom {
@@ -162,7 +630,28 @@ impl Display for Atom {
 
 impl Atom {
     pub fn new(rel: Rel, terms: Vec<Term>) -> Self {
-        Self { rel, terms }
+        Self {
+            rel,
+            terms,
+            loc: Loc::default(),
+        }
+    }
+
+    pub fn rel(&self) -> &Rel {
+        &self.rel
+    }
+
+    pub fn terms(&self) -> &[Term] {
+        &self.terms
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.loc.0
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.loc = Loc(Some(span));
+        self
     }
 
     pub fn ground(self) -> Option<GroundAtom> {
@@ -194,151 +683,954 @@ impl GroundAtom {
 
 // ------------------------------------------------------------------
 
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ArithOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Display for ArithOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArithOp::Add => write!(f, "+"),
+            ArithOp::Sub => write!(f, "-"),
+            ArithOp::Mul => write!(f, "*"),
+            ArithOp::Div => write!(f, "/"),
+        }
+    }
+}
+
+/// An arithmetic expression in a [`Comparison`]
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct Rule {
-    pub(crate) head: Atom,
-    pub(crate) body: Vec<Atom>, // TODO(lb, low): small vec optimization
+pub enum Expr {
+    Const(Const),
+    Var(Var),
+    Binary(ArithOp, Box<Expr>, Box<Expr>),
 }
 
-impl Display for Rule {
+impl Display for Expr {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{} :- ", self.head)?;
-        let mut iter = self.body.iter();
-        if let Some(atom) = iter.next() {
-            write!(f, "{}", atom)?;
-            for atom in iter {
-                write!(f, ", {}", atom)?;
+        match self {
+            Expr::Const(c) => write!(f, "{}", c),
+            Expr::Var(v) => write!(f, "{}", v),
+            Expr::Binary(op, l, r) => {
+                // Parenthesize nested operations, rather than tracking precedence
+                let fmt = |f: &mut std::fmt::Formatter<'_>, e: &Expr| match e {
+                    Expr::Binary(..) => write!(f, "({})", e),
+                    _ => write!(f, "{}", e),
+                };
+                fmt(f, l)?;
+                write!(f, " {op} ")?;
+                fmt(f, r)
             }
         }
-        write!(f, ".")
     }
 }
 
-impl Rule {
-    // TODO: range restriction check
-    pub fn new(head: Atom, body: Vec<Atom>) -> Self {
-        Self { head, body }
+impl Expr {
+    pub fn binary(op: ArithOp, lhs: Expr, rhs: Expr) -> Self {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
     }
 
-    pub fn is_fact(&self) -> bool {
-        self.body.is_empty()
+    /// The variables in this expression, in order, possibly with duplicates
+    pub fn vars(&self) -> Vec<&Var> {
+        match self {
+            Expr::Const(_) => Vec::new(),
+            Expr::Var(v) => vec![v],
+            Expr::Binary(_, l, r) => {
+                let mut vars = l.vars();
+                vars.extend(r.vars());
+                vars
+            }
+        }
     }
 }
 
-// ------------------------------------------------------------------
-
-#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
-pub struct Ast {
-    pub(crate) rules: Vec<Rule>,
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
 }
 
-struct Atoms<'a> {
-    prog: &'a Ast,
-    rule: usize,
-    atom: usize,
+impl Display for CmpOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CmpOp::Eq => write!(f, "="),
+            CmpOp::Ne => write!(f, "!="),
+            CmpOp::Lt => write!(f, "<"),
+            CmpOp::Le => write!(f, "<="),
+            CmpOp::Gt => write!(f, ">"),
+            CmpOp::Ge => write!(f, ">="),
+        }
+    }
 }
 
-impl<'a> Iterator for Atoms<'a> {
-    type Item = &'a Atom;
+/// A built-in comparison, e.g., `X != Y` or `M = N + 1`.
+///
+/// Every variable must be bound elsewhere in the body, except that an
+/// equation between a variable and an expression binds the variable to the
+/// value of the expression, see [`Comparison::binds`].
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Comparison {
+    pub(crate) op: CmpOp,
+    pub(crate) lhs: Expr,
+    pub(crate) rhs: Expr,
+    pub(crate) loc: Loc,
+}
 
-    fn next(&mut self) -> Option<Self::Item> {
-        match self.prog.rules.get(self.rule) {
-            None => None,
-            Some(rule) => {
-                if self.atom == 0 {
-                    if rule.body.is_empty() {
-                        self.rule += 1;
-                    } else {
-                        self.atom += 1;
-                    }
-                    Some(&rule.head)
-                } else {
-                    let atom = self.atom - 1;
-                    if rule.body.len() > atom + 1 {
-                        self.atom += 1;
-                    } else {
-                        self.rule += 1;
-                    }
-                    Some(rule.body.get(atom).unwrap())
-                }
-            }
-        }
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.lhs, self.op, self.rhs)
     }
 }
 
-impl Ast {
-    pub fn atoms(&self) -> impl Iterator<Item = &Atom> {
-        Atoms {
-            prog: self,
-            rule: 0,
-            atom: 0,
+impl Comparison {
+    pub fn new(op: CmpOp, lhs: Expr, rhs: Expr) -> Self {
+        Self {
+            op,
+            lhs,
+            rhs,
+            loc: Loc::default(),
         }
     }
 
-    pub fn arities(&self) -> HashMap<Rel, usize> {
-        let mut arities = HashMap::with_capacity(self.rules.len() / 8); // just a guess
-        for atom in self.atoms() {
-            match arities.get(&atom.rel).copied() {
-                None => {
-                    arities.insert(atom.rel.clone(), atom.terms.len());
-                }
-                Some(arity) => {
-                    debug_assert_eq!(arity, atom.terms.len())
-                }
-            }
-        }
-        arities
+    pub fn op(&self) -> CmpOp {
+        self.op
     }
 
-    pub fn new(rules: Vec<Rule>) -> Result<Self, Error> {
-        let prog = Self { rules };
-        prog.valid()?;
-        Ok(prog)
+    pub fn lhs(&self) -> &Expr {
+        &self.lhs
     }
 
-    pub fn new_unchecked(rules: Vec<Rule>) -> Self {
-        Self { rules }
+    pub fn rhs(&self) -> &Expr {
+        &self.rhs
     }
 
-    pub fn valid(&self) -> Result<(), Error> {
-        let mut arities = HashMap::with_capacity(self.rules.len() / 8); // just a guess
-        let mut check = |atom: &Atom| match arities.get(&atom.rel).copied() {
-            None => {
-                arities.insert(atom.rel.clone(), atom.terms.len());
-                Ok(())
-            }
-            Some(arity) => {
-                if arity == atom.terms.len() {
-                    Ok(())
-                } else {
-                    Err(Error::Arity {
-                        relation: atom.rel.clone(),
-                        arity1: arity,
-                        arity2: atom.terms.len(),
-                    })
-                }
+    pub fn span(&self) -> Option<Span> {
+        self.loc.0
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.loc = Loc(Some(span));
+        self
+    }
+
+    pub fn vars(&self) -> Vec<&Var> {
+        let mut vars = self.lhs.vars();
+        vars.extend(self.rhs.vars());
+        vars
+    }
+
+    /// If this is an equation `V = e` (or `e = V`) where `V` isn't `bound`
+    /// but all the variables of `e` are, returns `V` and `e`.
+    pub fn binds(&self, bound: &HashSet<&Var>) -> Option<(&Var, &Expr)> {
+        if self.op != CmpOp::Eq {
+            return None;
+        }
+        let all_bound = |e: &Expr| e.vars().iter().all(|v| bound.contains(v));
+        match (&self.lhs, &self.rhs) {
+            (Expr::Var(v), e) | (e, Expr::Var(v)) if !bound.contains(v) && all_bound(e) => {
+                Some((v, e))
             }
-        };
-        for atom in self.atoms() {
-            check(atom)?;
+            _ => None,
         }
-        Ok(())
     }
 }
 
 // ------------------------------------------------------------------
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Literal {
+    Atom(Atom),
+    Not(Atom),
+    Cmp(Comparison),
+}
 
-    fn null_atom() -> Atom {
-        Atom::new(Rel::new(String::from("r")), Vec::new())
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::Atom(atom) => write!(f, "{}", atom),
+            Literal::Not(atom) => write!(f, "!{}", atom),
+            Literal::Cmp(cmp) => write!(f, "{}", cmp),
+        }
     }
+}
 
-    fn null_fact() -> Rule {
-        Rule::new(null_atom(), Vec::new())
-    }
+impl Literal {
+    /// The atom of a positive or negative literal
+    pub fn atom(&self) -> Option<&Atom> {
+        match self {
+            Literal::Atom(atom) | Literal::Not(atom) => Some(atom),
+            Literal::Cmp(_) => None,
+        }
+    }
+
+    pub fn cmp(&self) -> Option<&Comparison> {
+        match self {
+            Literal::Cmp(cmp) => Some(cmp),
+            Literal::Atom(_) | Literal::Not(_) => None,
+        }
+    }
+
+    pub fn is_negative(&self) -> bool {
+        matches!(self, Literal::Not(_))
+    }
+
+    pub fn positive(&self) -> Option<&Atom> {
+        match self {
+            Literal::Atom(atom) => Some(atom),
+            Literal::Not(_) | Literal::Cmp(_) => None,
+        }
+    }
+
+    /// The variables of this literal, in order, possibly with duplicates
+    pub fn vars(&self) -> Vec<&Var> {
+        match self {
+            Literal::Atom(atom) | Literal::Not(atom) => {
+                atom.terms.iter().filter_map(Term::var).collect()
+            }
+            Literal::Cmp(cmp) => cmp.vars(),
+        }
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Literal::Atom(atom) | Literal::Not(atom) => atom.span(),
+            Literal::Cmp(cmp) => cmp.span(),
+        }
+    }
+}
+
+// ------------------------------------------------------------------
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Rule {
+    pub(crate) head: Atom,
+    pub(crate) body: Vec<Literal>, // TODO(lb, low): small vec optimization
+    pub(crate) loc: Loc,
+}
+
+impl Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} :- ", self.head)?;
+        let mut iter = self.body.iter();
+        if let Some(lit) = iter.next() {
+            write!(f, "{}", lit)?;
+            for lit in iter {
+                write!(f, ", {}", lit)?;
+            }
+        }
+        write!(f, ".")
+    }
+}
+
+impl Rule {
+    /// A rule with only positive atoms in the body.
+    ///
+    /// See [`Rule::ungrounded`] for the range restriction check.
+    pub fn new(head: Atom, body: Vec<Atom>) -> Self {
+        Self::new_with_literals(head, body.into_iter().map(Literal::Atom).collect())
+    }
+
+    pub fn new_with_literals(head: Atom, body: Vec<Literal>) -> Self {
+        Self {
+            head,
+            body,
+            loc: Loc::default(),
+        }
+    }
+
+    pub fn head(&self) -> &Atom {
+        &self.head
+    }
+
+    pub fn body(&self) -> &[Literal] {
+        &self.body
+    }
+
+    /// The head, then the atoms of the body
+    pub fn atoms(&self) -> impl Iterator<Item = &Atom> {
+        std::iter::once(&self.head).chain(self.body.iter().filter_map(Literal::atom))
+    }
+
+    /// The positive atoms of the body
+    pub fn positive(&self) -> impl Iterator<Item = &Atom> {
+        self.body.iter().filter_map(Literal::positive)
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.loc.0
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.loc = Loc(Some(span));
+        self
+    }
+
+    pub fn is_fact(&self) -> bool {
+        self.body.is_empty()
+    }
+
+    /// The aggregates in the head
+    pub fn aggregates(&self) -> impl Iterator<Item = &Aggregate> {
+        self.head.terms.iter().filter_map(|t| match t {
+            Term::Aggregate(a) => Some(a),
+            _ => None,
+        })
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        self.aggregates().next().is_some()
+    }
+
+    /// Variables in the head, in negated atoms, in comparisons, or in atoms of
+    /// [`Rel::aggregate`] relations that aren't bound by the body (see
+    /// [`Rule::bound`]), in order of first appearance.
+    /// Wildcards in negated atoms (see [`Rule::local`]) are fine. A rule is
+    /// range-restricted when this is empty.
+    pub fn ungrounded(&self) -> Vec<Var> {
+        let bound = self.bound();
+        let local = self.local();
+        let mut vars = Vec::new();
+        let mut check = |v: &Var| {
+            if !bound.contains(v) && !vars.contains(v) {
+                vars.push(v.clone());
+            }
+        };
+        for term in &self.head.terms {
+            term.var().map(&mut check);
+        }
+        for lit in &self.body {
+            match lit {
+                Literal::Atom(atom) if atom.rel.is_aggregate() => {
+                    atom.terms.iter().filter_map(Term::var).for_each(&mut check)
+                }
+                Literal::Atom(_) => (),
+                Literal::Not(atom) => atom
+                    .terms
+                    .iter()
+                    .filter_map(Term::var)
+                    .filter(|v| !local.contains(v))
+                    .for_each(&mut check),
+                Literal::Cmp(cmp) => cmp.vars().into_iter().for_each(&mut check),
+            }
+        }
+        vars
+    }
+
+    /// Wildcards that appear in a single negated atom and nowhere else in the
+    /// rule, e.g., `_` in `s(X) :- n(X), !e(X, _).` They're existentially
+    /// quantified within the negation: `!e(X, _)` holds when there's no
+    /// `e(X, Y)` for any `Y`. As in Soufflé, other variables of negated atoms
+    /// must be bound.
+    pub(crate) fn local(&self) -> HashSet<&Var> {
+        let mut local = HashSet::new();
+        for (i, lit) in self.body.iter().enumerate() {
+            if !lit.is_negative() {
+                continue;
+            }
+            for v in lit.vars().into_iter().filter(|v| v.is_wildcard()) {
+                let elsewhere = self.head.terms.iter().any(|t| t.var() == Some(v))
+                    || self
+                        .body
+                        .iter()
+                        .enumerate()
+                        .any(|(j, l)| j != i && l.vars().contains(&v));
+                if !elsewhere {
+                    local.insert(v);
+                }
+            }
+        }
+        local
+    }
+
+    /// Variables bound by positive atoms, or by equations whose other
+    /// variables are bound (see [`Comparison::binds`]). Atoms of
+    /// [`Rel::aggregate`] relations only bind their results, once their
+    /// groups are bound.
+    pub(crate) fn bound(&self) -> HashSet<&Var> {
+        let mut bound = HashSet::new();
+        let mut aggregates = Vec::new();
+        for atom in self.positive() {
+            if atom.rel.is_aggregate() {
+                aggregates.push(atom);
+                continue;
+            }
+            for term in &atom.terms {
+                if let Term::Var(v) = term {
+                    bound.insert(v);
+                }
+            }
+        }
+        loop {
+            let binds = self.comparisons().find_map(|cmp| cmp.binds(&bound));
+            if let Some((v, _)) = binds {
+                bound.insert(v);
+                continue;
+            }
+            let ready = aggregates.iter().position(|atom| {
+                let (result, groups) = atom.terms.split_last().expect("aggregate result");
+                groups
+                    .iter()
+                    .all(|t| t.var().is_none_or(|v| bound.contains(v)))
+                    && result.var().is_some_and(|v| !bound.contains(v))
+            });
+            match ready {
+                Some(i) => {
+                    let atom = aggregates.swap_remove(i);
+                    bound.insert(atom.terms.last().and_then(Term::var).unwrap());
+                }
+                None => break,
+            }
+        }
+        bound
+    }
+
+    /// The built-in comparisons of the body
+    pub fn comparisons(&self) -> impl Iterator<Item = &Comparison> {
+        self.body.iter().filter_map(Literal::cmp)
+    }
+
+    pub(crate) fn range_restricted(&self) -> Result<(), Error> {
+        let vars = self.ungrounded();
+        if vars.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::Ungrounded {
+                rule: Box::new(self.clone()),
+                vars,
+            })
+        }
+    }
+}
+
+// ------------------------------------------------------------------
+
+/// The parameters of an `.input` or `.output` directive, e.g.,
+/// `filename="edge.csv"`. Their meaning is up to whoever reads or writes the
+/// relation.
+pub type IoParams = BTreeMap<String, String>;
+
+/// Column names used by the evaluator for its own bookkeeping, compared
+/// case-insensitively.
+pub(crate) const RESERVED_COLUMNS: &[&str] = &["id", "it"];
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Column {
+    pub(crate) name: String,
+    pub(crate) ty: Type,
+}
+
+impl Display for Column {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.ty)
+    }
+}
+
+impl Column {
+    pub fn new(name: String, ty: Type) -> Self {
+        Self { name, ty }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn ty(&self) -> Type {
+        self.ty
+    }
+}
+
+/// A relation declaration. Relations don't need to be declared, the arity and
+/// types of undeclared relations are inferred from their uses.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RelDecl {
+    pub(crate) rel: Rel,
+    pub(crate) cols: Vec<Column>,
+    /// The parameters of the `.input` directive, if it's an input
+    pub(crate) input: Option<IoParams>,
+    /// The parameters of the `.output` directive, if it's an output
+    pub(crate) output: Option<IoParams>,
+    pub(crate) loc: Loc,
+}
+
+impl Display for RelDecl {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, ".decl {}(", self.rel)?;
+        let mut iter = self.cols.iter();
+        if let Some(col) = iter.next() {
+            write!(f, "{}", col)?;
+            for col in iter {
+                write!(f, ", {}", col)?;
+            }
+        }
+        write!(f, ")")?;
+        for (directive, params) in [("input", &self.input), ("output", &self.output)] {
+            if let Some(params) = params {
+                write!(f, "\n.{directive} {}", self.rel)?;
+                if !params.is_empty() {
+                    let params: Vec<String> =
+                        params.iter().map(|(k, v)| format!("{k}={v:?}")).collect();
+                    write!(f, "({})", params.join(", "))?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl RelDecl {
+    pub fn new(rel: Rel, cols: Vec<Column>) -> Self {
+        Self {
+            rel,
+            cols,
+            input: None,
+            output: None,
+            loc: Loc::default(),
+        }
+    }
+
+    /// An internal relation with columns named `x0`, `x1`, etc.
+    pub(crate) fn inferred(rel: Rel, types: Vec<Type>) -> Self {
+        let cols = types
+            .into_iter()
+            .enumerate()
+            .map(|(i, ty)| Column::new(format!("x{i}"), ty))
+            .collect();
+        Self::new(rel, cols)
+    }
+
+    pub fn arity(&self) -> usize {
+        self.cols.len()
+    }
+
+    pub fn cols(&self) -> &[Column] {
+        &self.cols
+    }
+
+    pub fn rel(&self) -> &Rel {
+        &self.rel
+    }
+
+    /// The parameters of the `.input` directive, if any
+    pub fn input(&self) -> Option<&IoParams> {
+        self.input.as_ref()
+    }
+
+    /// The parameters of the `.output` directive, if any
+    pub fn output(&self) -> Option<&IoParams> {
+        self.output.as_ref()
+    }
+
+    /// Make this an input, with the parameters of its `.input` directive
+    pub fn with_input(mut self, params: IoParams) -> Self {
+        self.input = Some(params);
+        self
+    }
+
+    /// Make this an output, with the parameters of its `.output` directive
+    pub fn with_output(mut self, params: IoParams) -> Self {
+        self.output = Some(params);
+        self
+    }
+
+    pub fn span(&self) -> Option<Span> {
+        self.loc.0
+    }
+
+    pub fn types(&self) -> Vec<Type> {
+        self.cols.iter().map(|c| c.ty).collect()
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.loc = Loc(Some(span));
+        self
+    }
+
+    fn valid(&self) -> Result<(), Error> {
+        let mut names = HashSet::with_capacity(self.cols.len());
+        for col in &self.cols {
+            let name = col.name.to_lowercase();
+            if RESERVED_COLUMNS.contains(&name.as_str()) || !names.insert(name) {
+                return Err(Error::Column {
+                    relation: self.rel.clone(),
+                    name: col.name.clone(),
+                    span: self.span(),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+// ------------------------------------------------------------------
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Ast {
+    pub(crate) decls: Vec<RelDecl>,
+    pub(crate) rules: Vec<Rule>,
+}
+
+impl Display for Ast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for decl in &self.decls {
+            writeln!(f, "{}", decl)?;
+        }
+        for rule in &self.rules {
+            writeln!(f, "{}", rule)?;
+        }
+        Ok(())
+    }
+}
+
+/// Type inference state: the type of each column, and where it was
+/// determined (for error messages).
+struct Typing<'a> {
+    cols: HashMap<&'a Rel, Vec<Option<Origin>>>,
+}
+
+type Origin = (Type, Option<Span>);
+
+impl<'a> Typing<'a> {
+    /// Returns whether the type of the column was previously unknown.
+    fn assign(&mut self, atom: &'a Atom, col: usize, ty: Type) -> Result<bool, Error> {
+        let cols = self
+            .cols
+            .entry(&atom.rel)
+            .or_insert_with(|| vec![None; atom.terms.len()]);
+        // Arity mismatches are reported elsewhere
+        let slot = match cols.get_mut(col) {
+            None => return Ok(false),
+            Some(slot) => slot,
+        };
+        match *slot {
+            None => {
+                *slot = Some((ty, atom.span()));
+                Ok(true)
+            }
+            Some((ty1, _)) if ty1 == ty => Ok(false),
+            Some((ty1, span1)) => Err(Error::Type {
+                relation: atom.rel.clone(),
+                column: col,
+                type1: ty1,
+                type2: ty,
+                span1,
+                span2: atom.span(),
+            }),
+        }
+    }
+
+    fn get(&self, atom: &Atom, col: usize) -> Option<Type> {
+        self.cols
+            .get(&atom.rel)
+            .and_then(|cols| cols.get(col).copied().flatten())
+            .map(|(ty, _)| ty)
+    }
+
+    /// A variable with the same type as `term`
+    fn var(term: &Term) -> Option<&Var> {
+        match term {
+            Term::Aggregate(a) if a.preserves_type() => Some(&a.var),
+            Term::Aggregate(_) | Term::Const(_) => None,
+            Term::Var(v) => Some(v),
+        }
+    }
+
+    /// Propagate types from constants and between uses of the same variable,
+    /// until nothing changes. Returns whether anything changed.
+    fn rule(&mut self, rule: &'a Rule) -> Result<bool, Error> {
+        let mut changed = false;
+        let atoms = || rule.atoms();
+        for atom in atoms() {
+            self.cols
+                .entry(&atom.rel)
+                .or_insert_with(|| vec![None; atom.terms.len()]);
+            for (i, term) in atom.terms.iter().enumerate() {
+                match term {
+                    Term::Const(c) => changed |= self.assign(atom, i, c.ty())?,
+                    Term::Aggregate(a) if !a.preserves_type() => {
+                        changed |= self.assign(atom, i, Type::Int)?
+                    }
+                    _ => (),
+                }
+            }
+        }
+        let vars = self.vars(rule);
+        for atom in atoms() {
+            for (i, term) in atom.terms.iter().enumerate() {
+                if let Some(ty) = Typing::var(term).and_then(|v| vars.get(v)) {
+                    changed |= self.assign(atom, i, *ty)?;
+                }
+            }
+        }
+        Ok(changed)
+    }
+
+    /// The types of the variables of a rule, from the columns they appear in,
+    /// and from the other sides of comparisons.
+    fn vars(&self, rule: &'a Rule) -> HashMap<&'a Var, Type> {
+        let mut vars: HashMap<&Var, Type> = HashMap::new();
+        for atom in rule.atoms() {
+            for (i, term) in atom.terms.iter().enumerate() {
+                if let (Some(v), Some(ty)) = (Typing::var(term), self.get(atom, i)) {
+                    vars.entry(v).or_insert(ty);
+                }
+            }
+        }
+        loop {
+            let mut changed = false;
+            for cmp in rule.comparisons() {
+                for (e1, e2) in [(&cmp.lhs, &cmp.rhs), (&cmp.rhs, &cmp.lhs)] {
+                    if let (Expr::Var(v), Some(ty)) = (e1, expr_type(&vars, e2)) {
+                        if !vars.contains_key(v) {
+                            vars.insert(v, ty);
+                            changed = true;
+                        }
+                    }
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+        vars
+    }
+}
+
+/// The type of an expression, if the types of enough of its variables are
+/// known. Arithmetic on an `int` and a `float` is a `float`.
+fn expr_type<S: BuildHasher>(vars: &HashMap<&Var, Type, S>, expr: &Expr) -> Option<Type> {
+    match expr {
+        Expr::Const(c) => Some(c.ty()),
+        Expr::Var(v) => vars.get(v).copied(),
+        Expr::Binary(_, l, r) => match (expr_type(vars, l), expr_type(vars, r)) {
+            (Some(Type::Float), _) | (_, Some(Type::Float)) => Some(Type::Float),
+            (Some(ty), _) | (_, Some(ty)) => Some(ty),
+            (None, None) => None,
+        },
+    }
+}
+
+fn check_expr(vars: &HashMap<&Var, Type>, expr: &Expr, span: Option<Span>) -> Result<(), Error> {
+    if let Expr::Binary(_, l, r) = expr {
+        for e in [l, r] {
+            check_expr(vars, e, span)?;
+            if let Some(ty @ (Type::Bool | Type::Sym)) = expr_type(vars, e) {
+                return Err(Error::Arith {
+                    expr: expr.clone(),
+                    ty,
+                    span,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that the operands of a comparison have compatible types, and that
+/// arithmetic is only done on numbers.
+fn check_cmp(vars: &HashMap<&Var, Type>, cmp: &Comparison) -> Result<(), Error> {
+    check_expr(vars, &cmp.lhs, cmp.span())?;
+    check_expr(vars, &cmp.rhs, cmp.span())?;
+    let numeric = |ty| matches!(ty, Type::Int | Type::Float);
+    match (expr_type(vars, &cmp.lhs), expr_type(vars, &cmp.rhs)) {
+        (Some(type1), Some(type2)) if type1 != type2 && !(numeric(type1) && numeric(type2)) => {
+            Err(Error::Cmp {
+                cmp: Box::new(cmp.clone()),
+                type1,
+                type2,
+                span: cmp.span(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Infer the type of each column of each relation. Columns that are never
+/// constrained are symbols.
+pub(crate) fn infer_types<'a>(
+    decls: &'a [RelDecl],
+    rules: impl IntoIterator<Item = &'a Rule>,
+) -> Result<HashMap<Rel, Vec<Type>>, Error> {
+    let rules: Vec<&Rule> = rules.into_iter().collect();
+    let mut typing = Typing {
+        cols: HashMap::with_capacity(decls.len() + rules.len() / 8), // just a guess
+    };
+    for decl in decls {
+        let cols = decl.cols.iter().map(|c| Some((c.ty, decl.span())));
+        typing.cols.insert(&decl.rel, cols.collect());
+    }
+    loop {
+        let mut changed = false;
+        for rule in &rules {
+            changed |= typing.rule(rule)?;
+        }
+        if !changed {
+            break;
+        }
+    }
+    for rule in &rules {
+        let vars = typing.vars(rule);
+        for cmp in rule.comparisons() {
+            check_cmp(&vars, cmp)?;
+        }
+        for (i, term) in rule.head.terms.iter().enumerate() {
+            if let Term::Aggregate(a) = term {
+                let ty = typing.get(&rule.head, i).unwrap_or(Type::Sym);
+                if a.func == AggFunc::Sum && !matches!(ty, Type::Int | Type::Float) {
+                    return Err(Error::AggregateType {
+                        aggregate: a.clone(),
+                        ty,
+                        span: rule.head.span(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(typing
+        .cols
+        .into_iter()
+        .map(|(rel, cols)| {
+            let tys = cols
+                .into_iter()
+                .map(|c| c.map(|(ty, _)| ty).unwrap_or(Type::Sym))
+                .collect();
+            (rel.clone(), tys)
+        })
+        .collect())
+}
+
+fn no_body_aggregates(lit: &Literal) -> Result<(), Error> {
+    if let Some(atom) = lit.atom() {
+        for term in &atom.terms {
+            if let Term::Aggregate(a) = term {
+                return Err(Error::AggregateBody {
+                    aggregate: a.clone(),
+                    span: atom.span(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Ast {
+    pub fn atoms(&self) -> impl Iterator<Item = &Atom> {
+        self.rules.iter().flat_map(Rule::atoms)
+    }
+
+    pub fn arities(&self) -> HashMap<Rel, usize> {
+        let mut arities = HashMap::with_capacity(self.rules.len() / 8); // just a guess
+        for decl in &self.decls {
+            arities.insert(decl.rel.clone(), decl.arity());
+        }
+        for atom in self.atoms() {
+            match arities.get(&atom.rel).copied() {
+                None => {
+                    arities.insert(atom.rel.clone(), atom.terms.len());
+                }
+                Some(arity) => {
+                    debug_assert_eq!(arity, atom.terms.len())
+                }
+            }
+        }
+        arities
+    }
+
+    pub fn decls(&self) -> &[RelDecl] {
+        &self.decls
+    }
+
+    pub fn new(rules: Vec<Rule>) -> Result<Self, Error> {
+        Self::new_with_decls(Vec::new(), rules)
+    }
+
+    pub fn new_unchecked(rules: Vec<Rule>) -> Self {
+        Self {
+            decls: Vec::new(),
+            rules,
+        }
+    }
+
+    pub fn new_with_decls(decls: Vec<RelDecl>, rules: Vec<Rule>) -> Result<Self, Error> {
+        let prog = Self { decls, rules };
+        prog.valid()?;
+        Ok(prog)
+    }
+
+    pub fn valid<'a>(&'a self) -> Result<(), Error> {
+        // Remember the declaration or first use of each relation, for error
+        // messages
+        let mut arities: HashMap<&Rel, (usize, Option<Span>)> =
+            HashMap::with_capacity(self.decls.len() + self.rules.len() / 8); // just a guess
+        for decl in &self.decls {
+            decl.valid()?;
+            if let Some((_, span1)) = arities.insert(&decl.rel, (decl.arity(), decl.span())) {
+                return Err(Error::Redeclared {
+                    relation: decl.rel.clone(),
+                    span1,
+                    span2: decl.span(),
+                });
+            }
+        }
+        let mut check = |atom: &'a Atom| match arities.get(&atom.rel).copied() {
+            None => {
+                arities.insert(&atom.rel, (atom.terms.len(), atom.span()));
+                Ok(())
+            }
+            Some((arity1, span1)) => {
+                if arity1 == atom.terms.len() {
+                    Ok(())
+                } else {
+                    Err(Error::Arity {
+                        relation: atom.rel.clone(),
+                        arity1,
+                        arity2: atom.terms.len(),
+                        span1,
+                        span2: atom.span(),
+                    })
+                }
+            }
+        };
+        for atom in self.atoms() {
+            check(atom)?;
+        }
+        for rule in &self.rules {
+            rule.range_restricted()?;
+            for lit in &rule.body {
+                no_body_aggregates(lit)?;
+            }
+        }
+        self.types()?;
+        Graph::new(&self.rules).stratify()?;
+        Ok(())
+    }
+
+    /// The type of each column of each relation, see also [`Ast::arities`].
+    pub fn types(&self) -> Result<HashMap<Rel, Vec<Type>>, Error> {
+        infer_types(&self.decls, &self.rules)
+    }
+}
+
+// ------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn null_atom() -> Atom {
+        Atom::new(Rel::new(String::from("r")), Vec::new())
+    }
+
+    fn null_fact() -> Rule {
+        Rule::new(null_atom(), Vec::new())
+    }
 
     fn unary_atom() -> Atom {
         Atom::new(
@@ -361,11 +1653,39 @@ mod tests {
         assert_eq!("r(c) :- .", &format!("{}", unary_fact()))
     }
 
+    #[test]
+    fn print_prog() {
+        let ast = Ast::new_unchecked(vec![null_fact(), unary_fact()]);
+        assert_eq!("r() :- .\nr(c) :- .\n", &format!("{}", ast))
+    }
+
+    #[test]
+    fn print_float() {
+        let printed: Vec<String> = [2.0, -0.5, 0.1, 1e-7, 1.5e300, f64::INFINITY]
+            .into_iter()
+            .map(|x| Float(x).to_string())
+            .collect();
+        assert_eq!(
+            vec!["2.0", "-0.5", "0.1", "1e-7", "1.5e300", "inf"],
+            printed
+        );
+    }
+
+    #[test]
+    fn float_zeros_equal() {
+        let hash = |x: Float| fxhash::hash64(&x);
+        assert_eq!(Float(0.0), Float(-0.0));
+        assert_eq!(hash(Float(0.0)), hash(Float(-0.0)));
+        assert!(Float(-0.0) > Float(-1e-300));
+        assert!(Float(-0.0) < Float(1e-300));
+    }
+
     #[test]
     fn nullary_prog_ok() {
         let prog = Ast::new(vec![null_fact()]).unwrap();
         assert_eq!(
             Ast {
+                decls: Vec::new(),
                 rules: vec![null_fact()]
             },
             prog
@@ -378,10 +1698,318 @@ mod tests {
         let prog = Ast::new(vec![unary_fact()]).unwrap();
         assert_eq!(
             Ast {
+                decls: Vec::new(),
                 rules: vec![unary_fact()]
             },
             prog
         );
         assert_eq!(vec![&unary_atom()], prog.atoms().collect::<Vec<_>>());
     }
+
+    #[test]
+    fn spans_ignored_by_eq() {
+        let span = Span::default();
+        assert_eq!(null_fact(), null_fact().with_span(span));
+        assert_eq!(null_atom(), null_atom().with_span(span));
+    }
+
+    fn var(name: &str) -> Term {
+        Term::Var(Var::new_unchecked(String::from(name)))
+    }
+
+    #[test]
+    fn ungrounded_fact() {
+        let fact = Rule::new(
+            Atom::new(Rel::new(String::from("r")), vec![var("X")]),
+            Vec::new(),
+        );
+        assert_eq!(
+            Err(Error::Ungrounded {
+                rule: Box::new(fact.clone()),
+                vars: vec![Var::new_unchecked(String::from("X"))],
+            }),
+            Ast::new(vec![fact])
+        );
+    }
+
+    #[test]
+    fn ungrounded_rule_all_vars() {
+        // r(X, Y, Z, X) :- s(Y).
+        let rule = Rule::new(
+            Atom::new(
+                Rel::new(String::from("r")),
+                vec![var("X"), var("Y"), var("Z"), var("X")],
+            ),
+            vec![Atom::new(Rel::new(String::from("s")), vec![var("Y")])],
+        );
+        assert_eq!(
+            vec![
+                Var::new_unchecked(String::from("X")),
+                Var::new_unchecked(String::from("Z"))
+            ],
+            rule.ungrounded()
+        );
+        let err = Ast::new(vec![rule]).unwrap_err();
+        assert_eq!(
+            "ungrounded variables `X`, `Z` in rule `r(X, Y, Z, X) :- s(Y).`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn range_restricted_ok() {
+        // r(X) :- s(X, Y).
+        let rule = Rule::new(
+            Atom::new(Rel::new(String::from("r")), vec![var("X")]),
+            vec![Atom::new(
+                Rel::new(String::from("s")),
+                vec![var("X"), var("Y")],
+            )],
+        );
+        assert!(rule.ungrounded().is_empty());
+        assert!(Ast::new(vec![rule]).is_ok());
+    }
+
+    #[test]
+    fn ungrounded_negation() {
+        // r(X) :- s(X), !t(X, Y).
+        let rule = Rule::new_with_literals(
+            Atom::new(Rel::new(String::from("r")), vec![var("X")]),
+            vec![
+                Literal::Atom(Atom::new(Rel::new(String::from("s")), vec![var("X")])),
+                Literal::Not(Atom::new(
+                    Rel::new(String::from("t")),
+                    vec![var("X"), var("Y")],
+                )),
+            ],
+        );
+        assert_eq!(
+            vec![Var::new_unchecked(String::from("Y"))],
+            rule.ungrounded()
+        );
+        assert_eq!("r(X) :- s(X), !t(X, Y).", rule.to_string());
+    }
+
+    #[test]
+    fn negation_through_recursion() {
+        // p(X) :- r(X), !q(X).
+        // q(X) :- p(X).
+        let p = Rel::new(String::from("p"));
+        let q = Rel::new(String::from("q"));
+        let r = Rel::new(String::from("r"));
+        let rules = vec![
+            Rule::new_with_literals(
+                Atom::new(p.clone(), vec![var("X")]),
+                vec![
+                    Literal::Atom(Atom::new(r, vec![var("X")])),
+                    Literal::Not(Atom::new(q.clone(), vec![var("X")])),
+                ],
+            ),
+            Rule::new(
+                Atom::new(q.clone(), vec![var("X")]),
+                vec![Atom::new(p.clone(), vec![var("X")])],
+            ),
+        ];
+        let err = Ast::new(rules.clone()).unwrap_err();
+        assert_eq!(
+            Error::Negation {
+                cycle: vec![p.clone(), q, p],
+                span: None
+            },
+            err
+        );
+        assert_eq!(
+            "negation through recursion: `p` -> `q` -> `p`",
+            err.to_string()
+        );
+        // Without the cycle, `q` is in a lower stratum
+        assert!(Ast::new(rules[..1].to_vec()).is_ok());
+    }
+
+    #[test]
+    fn infer_types() {
+        // r(1, a).
+        // s(X, Y) :- r(X, Z), t(Y).
+        let r = Rel::new(String::from("r"));
+        let s = Rel::new(String::from("s"));
+        let t = Rel::new(String::from("t"));
+        let prog = Ast::new(vec![
+            Rule::new(
+                Atom::new(
+                    r.clone(),
+                    vec![
+                        Term::Const(Const::Int(1)),
+                        Term::Const(Const::new_unchecked(String::from("a"))),
+                    ],
+                ),
+                Vec::new(),
+            ),
+            Rule::new(
+                Atom::new(s.clone(), vec![var("X"), var("Y")]),
+                vec![
+                    Atom::new(r.clone(), vec![var("X"), var("Z")]),
+                    Atom::new(t.clone(), vec![var("Y")]),
+                ],
+            ),
+        ])
+        .unwrap();
+        let types = prog.types().unwrap();
+        assert_eq!(&vec![Type::Int, Type::Sym], types.get(&r).unwrap());
+        assert_eq!(&vec![Type::Int, Type::Sym], types.get(&s).unwrap());
+        assert_eq!(&vec![Type::Sym], types.get(&t).unwrap());
+    }
+
+    #[test]
+    fn type_error_via_var() {
+        // r(1).
+        // s(true).
+        // t(X) :- r(X), s(X).
+        let r = Rel::new(String::from("r"));
+        let s = Rel::new(String::from("s"));
+        let err = Ast::new(vec![
+            Rule::new(
+                Atom::new(r.clone(), vec![Term::Const(Const::Int(1))]),
+                Vec::new(),
+            ),
+            Rule::new(
+                Atom::new(s.clone(), vec![Term::Const(Const::Bool(true))]),
+                Vec::new(),
+            ),
+            Rule::new(
+                Atom::new(Rel::new(String::from("t")), vec![var("X")]),
+                vec![Atom::new(r, vec![var("X")]), Atom::new(s, vec![var("X")])],
+            ),
+        ])
+        .unwrap_err();
+        assert!(matches!(err, Error::Type { .. }));
+    }
+
+    fn edge_decl() -> RelDecl {
+        RelDecl::new(
+            Rel::new(String::from("edge")),
+            vec![
+                Column::new(String::from("src"), Type::Int),
+                Column::new(String::from("dst"), Type::Int),
+            ],
+        )
+    }
+
+    #[test]
+    fn print_decl() {
+        assert_eq!(".decl edge(src: int, dst: int)", edge_decl().to_string());
+        assert_eq!(
+            ".decl edge(src: int, dst: int)\n.input edge",
+            edge_decl().with_input(IoParams::new()).to_string()
+        );
+        let io = IoParams::from([
+            (String::from("IO"), String::from("file")),
+            (String::from("delimiter"), String::from(",")),
+        ]);
+        assert_eq!(
+            ".decl edge(src: int, dst: int)\n.input edge(IO=\"file\", delimiter=\",\")",
+            edge_decl().with_input(io.clone()).to_string()
+        );
+        assert_eq!(
+            ".decl edge(src: int, dst: int)\n.input edge\n.output edge(IO=\"file\", delimiter=\",\")",
+            edge_decl()
+                .with_input(IoParams::new())
+                .with_output(io)
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn decl_types() {
+        // path(X, Y) :- edge(X, Y).
+        let path = Rel::new(String::from("path"));
+        let prog = Ast::new_with_decls(
+            vec![edge_decl()],
+            vec![Rule::new(
+                Atom::new(path.clone(), vec![var("X"), var("Y")]),
+                vec![Atom::new(
+                    Rel::new(String::from("edge")),
+                    vec![var("X"), var("Y")],
+                )],
+            )],
+        )
+        .unwrap();
+        let types = prog.types().unwrap();
+        assert_eq!(&vec![Type::Int, Type::Int], types.get(&path).unwrap());
+        assert_eq!(
+            Some(&2),
+            prog.arities().get(&Rel::new(String::from("edge")))
+        );
+    }
+
+    #[test]
+    fn decl_errors() {
+        let edge = Rel::new(String::from("edge"));
+        // edge(a, b).
+        let sym_fact = Rule::new(
+            Atom::new(
+                edge.clone(),
+                vec![
+                    Term::Const(Const::new_unchecked(String::from("a"))),
+                    Term::Const(Const::new_unchecked(String::from("b"))),
+                ],
+            ),
+            Vec::new(),
+        );
+        assert!(matches!(
+            Ast::new_with_decls(vec![edge_decl()], vec![sym_fact]),
+            Err(Error::Type { .. })
+        ));
+        // edge(1).
+        let unary_fact = Rule::new(
+            Atom::new(edge.clone(), vec![Term::Const(Const::Int(1))]),
+            Vec::new(),
+        );
+        assert!(matches!(
+            Ast::new_with_decls(vec![edge_decl()], vec![unary_fact]),
+            Err(Error::Arity {
+                arity1: 2,
+                arity2: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            Ast::new_with_decls(vec![edge_decl(), edge_decl()], Vec::new()),
+            Err(Error::Redeclared { .. })
+        ));
+        let reserved = RelDecl::new(
+            edge.clone(),
+            vec![Column::new(String::from("ID"), Type::Int)],
+        );
+        assert!(matches!(
+            Ast::new_with_decls(vec![reserved], Vec::new()),
+            Err(Error::Column { .. })
+        ));
+        let dup = RelDecl::new(
+            edge,
+            vec![
+                Column::new(String::from("x"), Type::Int),
+                Column::new(String::from("x"), Type::Sym),
+            ],
+        );
+        assert!(matches!(
+            Ast::new_with_decls(vec![dup], Vec::new()),
+            Err(Error::Column { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_const() {
+        assert_eq!(Some(Const::Bool(true)), Const::parse(Type::Bool, "true"));
+        assert_eq!(Some(Const::Int(-1)), Const::parse(Type::Int, "-1"));
+        assert_eq!(
+            Some(Const::Float(Float(1.5))),
+            Const::parse(Type::Float, "1.5")
+        );
+        assert_eq!(
+            Some(Const::Sym(String::from("A b"))),
+            Const::parse(Type::Sym, "A b")
+        );
+        assert_eq!(None, Const::parse(Type::Int, "a"));
+        assert_eq!(None, Const::parse(Type::Bool, "1"));
+    }
 }
@@ -0,0 +1,922 @@
+//! Database engines that [`crate::eval::Eval`] can run on.
+//!
+//! Each relation is a table with the columns of its [`RelDecl`], plus `id`
+//! and `it` (the iteration in which the fact was derived, starting from 1;
+//! facts given to [`Backend::insert`] are at iteration 0). A fact may have
+//! several rows, e.g., when it's both given and derived. `id`s only increase,
+//! so they tell which rows were added since some point. Rows at iteration -1
+//! are about to be deleted, see [`overdelete`]. For semi-naive
+//! evaluation, each relation also has a `delta` table with the facts derived
+//! in the previous iteration, and a `new` table with the facts derived in the
+//! current one (see [`merge`]). [`crate::eval`] generates the SQL for rules,
+//! the backends execute it.
+
+use std::path::{Path, PathBuf};
+
+use fxhash::FxHashSet as HashSet;
+
+use crate::ast::{Const, Float, Rel, RelDecl, Type};
+
+#[cfg(feature = "duckdb")]
+mod duckdb;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[cfg(feature = "duckdb")]
+    #[error(transparent)]
+    DuckDB(#[from] ::duckdb::Error),
+    #[cfg(feature = "sqlite")]
+    #[error(transparent)]
+    Sqlite(#[from] ::rusqlite::Error),
+    #[error("{}: {source}", .path.display())]
+    Csv { path: PathBuf, source: csv::Error },
+    #[error("{}: expected {expected} columns for relation `{relation}`, found {found}", .path.display())]
+    SourceArity {
+        path: PathBuf,
+        relation: Rel,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{}: no column `{column}`", .path.display())]
+    SourceColumn { path: PathBuf, column: String },
+    #[error("{}: this backend can't read {format} files", .path.display())]
+    SourceFormat { path: PathBuf, format: &'static str },
+    #[error("{}: this backend can't write {format} files", .path.display())]
+    ExportFormat { path: PathBuf, format: &'static str },
+    #[error("{}: {source}", .path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("{}:{line}: invalid {ty} `{value}`", .path.display())]
+    SourceValue {
+        path: PathBuf,
+        line: u64,
+        ty: Type,
+        value: String,
+    },
+    #[error("unknown relation `{relation}`")]
+    QueryRelation { relation: Rel },
+    #[error("relation `{relation}` has arity {arity}")]
+    QueryArity { relation: Rel, arity: usize },
+    #[error("column {column} of relation `{relation}` has type `{expected}`, not `{found}`")]
+    QueryType {
+        relation: Rel,
+        column: usize,
+        expected: Type,
+        found: Type,
+    },
+    #[error("magic sets made an invalid program: {source}")]
+    Magic { source: crate::mir::Error },
+    #[error("relation `{relation}` has arity {arity}, not {found}")]
+    FactArity {
+        relation: Rel,
+        arity: usize,
+        found: usize,
+    },
+    #[error("column {column} of relation `{relation}` has type `{expected}`, not `{found}`")]
+    FactType {
+        relation: Rel,
+        column: usize,
+        expected: Type,
+        found: Type,
+    },
+}
+
+/// Differences in the SQL understood by each backend
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Dialect {
+    DuckDB,
+    Sqlite,
+}
+
+/// A SQL statement and the values of its parameters
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    pub sql: String,
+    pub params: Vec<Const>,
+}
+
+impl Query {
+    /// Add a parameter, returning its placeholder
+    pub(crate) fn param(&mut self, dialect: Dialect, c: &Const) -> String {
+        self.params.push(c.clone());
+        placeholder(dialect, self.params.len())
+    }
+
+    /// Add a parameter, returning its placeholder cast to the type of `c`, for
+    /// where the backend can't infer the type of a parameter (e.g., constants
+    /// in the head)
+    pub(crate) fn typed_param(&mut self, dialect: Dialect, c: &Const) -> String {
+        format!("CAST({} AS {})", self.param(dialect, c), c.ty().sql())
+    }
+}
+
+/// Check that a fact has the arity and column types of a relation, rather
+/// than letting the backend coerce its values
+pub(crate) fn check_fact(decl: &RelDecl, fact: &[Const]) -> Result<(), Error> {
+    if fact.len() != decl.arity() {
+        return Err(Error::FactArity {
+            relation: decl.rel.clone(),
+            arity: decl.arity(),
+            found: fact.len(),
+        });
+    }
+    for (column, (col, c)) in decl.cols.iter().zip(fact).enumerate() {
+        if c.ty() != col.ty {
+            return Err(Error::FactType {
+                relation: decl.rel.clone(),
+                column,
+                expected: col.ty,
+                found: c.ty(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// The placeholder for the `n`th parameter, starting from 1
+fn placeholder(dialect: Dialect, n: usize) -> String {
+    match dialect {
+        Dialect::DuckDB => format!("${n}"),
+        Dialect::Sqlite => format!("?{n}"),
+    }
+}
+
+/// How a [`Source`] is encoded
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SourceFormat {
+    Csv {
+        delimiter: u8,
+        /// Whether the first line holds the column names
+        header: bool,
+    },
+    Parquet,
+}
+
+/// A file of facts, which a backend reads directly into a relation (see
+/// [`Backend::load`]), rather than going through [`Const`]s.
+///
+/// Columns without a header are named `column0`, `column1`, etc.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Source {
+    pub(crate) path: PathBuf,
+    pub(crate) format: SourceFormat,
+    /// The column of the file for each column of the relation. If `None`,
+    /// the file has exactly the relation's columns, in order.
+    pub(crate) columns: Option<Vec<String>>,
+}
+
+impl Source {
+    /// A comma-separated file without a header
+    pub fn csv(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            format: SourceFormat::Csv {
+                delimiter: b',',
+                header: false,
+            },
+            columns: None,
+        }
+    }
+
+    pub fn parquet(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            format: SourceFormat::Parquet,
+            columns: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> SourceFormat {
+        self.format
+    }
+
+    /// Only for CSV files
+    pub fn with_delimiter(mut self, delimiter: u8) -> Self {
+        if let SourceFormat::Csv { header, .. } = self.format {
+            self.format = SourceFormat::Csv { delimiter, header };
+        }
+        self
+    }
+
+    /// Only for CSV files
+    pub fn with_header(mut self, header: bool) -> Self {
+        if let SourceFormat::Csv { delimiter, .. } = self.format {
+            self.format = SourceFormat::Csv { delimiter, header };
+        }
+        self
+    }
+
+    /// Read each column of the relation from the file's column with the
+    /// corresponding name
+    pub fn with_columns(mut self, columns: Vec<String>) -> Self {
+        self.columns = Some(columns);
+        self
+    }
+
+    /// For each column of `decl`, the index of the file's column to read it
+    /// from, given the names of the file's columns
+    pub(crate) fn mapping(&self, decl: &RelDecl, names: &[String]) -> Result<Vec<usize>, Error> {
+        let arity_err = |found| Error::SourceArity {
+            path: self.path.clone(),
+            relation: decl.rel.clone(),
+            expected: decl.arity(),
+            found,
+        };
+        match &self.columns {
+            None if names.len() == decl.arity() => Ok((0..names.len()).collect()),
+            None => Err(arity_err(names.len())),
+            Some(columns) if columns.len() != decl.arity() => Err(arity_err(columns.len())),
+            Some(columns) => columns
+                .iter()
+                .map(|c| {
+                    names
+                        .iter()
+                        .position(|n| n == c)
+                        .ok_or_else(|| Error::SourceColumn {
+                            path: self.path.clone(),
+                            column: c.clone(),
+                        })
+                })
+                .collect(),
+        }
+    }
+}
+
+/// How [`Backend::export`] writes a relation
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ExportFormat {
+    /// Comma-separated, with a header
+    Csv,
+    Parquet,
+    /// Newline-delimited JSON, one object per fact
+    Json,
+}
+
+impl ExportFormat {
+    fn name(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "CSV",
+            ExportFormat::Parquet => "Parquet",
+            ExportFormat::Json => "JSON",
+        }
+    }
+}
+
+pub trait Backend {
+    fn dialect(&self) -> Dialect;
+
+    /// Create the tables for a relation.
+    fn create_relation(&self, decl: &RelDecl) -> Result<(), Error>;
+
+    /// Drop the tables of a relation, see [`Backend::create_relation`].
+    fn drop_relation(&self, decl: &RelDecl) -> Result<(), Error>;
+
+    /// Add facts to a relation (as of iteration 0), skipping those that are
+    /// already present at iteration 0. Returns an error, and adds nothing, if
+    /// a fact doesn't have the arity and column types of the relation.
+    fn insert<'a>(
+        &self,
+        decl: &RelDecl,
+        facts: impl IntoIterator<Item = &'a [Const]>,
+    ) -> Result<(), Error>;
+
+    /// Add the facts in a file to a relation, like [`Backend::insert`].
+    fn load(&self, decl: &RelDecl, source: &Source) -> Result<(), Error>;
+
+    /// Run the queries for an iteration of a rule evaluation, which insert
+    /// into the `new` tables of some relations, then the [`Merge`]s for those
+    /// relations, in a single transaction. Returns whether any
+    /// [`Merge::diff`] inserted rows.
+    fn step(&self, queries: &[Query], merges: &[Merge]) -> Result<bool, Error>;
+
+    /// Run statements in a single transaction.
+    fn batch(&self, queries: &[Query]) -> Result<(), Error>;
+
+    /// The largest `id` of a relation, or 0 if it's empty.
+    fn last_id(&self, decl: &RelDecl) -> Result<i64, Error>;
+
+    /// Read the contents of a relation.
+    fn read(&self, decl: &RelDecl) -> Result<HashSet<Vec<Const>>, Error>;
+
+    /// Write the facts of a relation to a file, without `id` and `it` (see
+    /// [`export`]).
+    fn export(&self, decl: &RelDecl, path: &Path, format: ExportFormat) -> Result<(), Error>;
+}
+
+/// The part of a database connection that the backends have in common, so
+/// that [`step`], [`batch`], [`read`], etc. are written only once
+pub(crate) trait Sql {
+    /// Run statements without parameters
+    fn run(&self, sql: &str) -> Result<(), Error>;
+
+    /// Run a statement, returning the number of rows it changed
+    fn run_with(&self, sql: &str, params: &[Const]) -> Result<usize, Error>;
+
+    /// Call `f` on each row of the result of a query
+    fn each_row(
+        &self,
+        sql: &str,
+        f: &mut dyn FnMut(&dyn SqlRow) -> Result<(), Error>,
+    ) -> Result<(), Error>;
+}
+
+/// A row of the result of a query, see [`Sql::each_row`]
+pub(crate) trait SqlRow {
+    fn get_bool(&self, i: usize) -> Result<bool, Error>;
+    fn get_int(&self, i: usize) -> Result<i64, Error>;
+    fn get_float(&self, i: usize) -> Result<f64, Error>;
+    fn get_sym(&self, i: usize) -> Result<String, Error>;
+}
+
+/// Read a column of type `ty`
+pub(crate) fn get_const(row: &dyn SqlRow, i: usize, ty: Type) -> Result<Const, Error> {
+    Ok(match ty {
+        Type::Bool => Const::Bool(row.get_bool(i)?),
+        Type::Int => Const::Int(row.get_int(i)?),
+        Type::Float => Const::Float(Float(row.get_float(i)?)),
+        Type::Sym => Const::Sym(row.get_sym(i)?),
+    })
+}
+
+/// Run `f` in a transaction, which is rolled back if it fails, so that the
+/// connection isn't left in the middle of it
+fn transaction<T>(conn: &impl Sql, f: impl FnOnce() -> Result<T, Error>) -> Result<T, Error> {
+    conn.run("BEGIN;")?;
+    match f().and_then(|x| conn.run("END;").map(|()| x)) {
+        Ok(x) => Ok(x),
+        Err(e) => {
+            // The error of the statement matters more than that of `ROLLBACK`
+            let _ = conn.run("ROLLBACK;");
+            Err(e)
+        }
+    }
+}
+
+/// See [`Backend::step`]
+pub(crate) fn step(conn: &impl Sql, queries: &[Query], merges: &[Merge]) -> Result<bool, Error> {
+    transaction(conn, || {
+        let mut changed = false;
+        for q in queries {
+            conn.run_with(&q.sql, &q.params)?;
+        }
+        for m in merges {
+            conn.run(&m.clear)?;
+            changed |= conn.run_with(&m.diff, &[])? > 0;
+            conn.run(&m.commit)?;
+        }
+        Ok(changed)
+    })
+}
+
+/// See [`Backend::batch`]
+pub(crate) fn batch(conn: &impl Sql, queries: &[Query]) -> Result<(), Error> {
+    transaction(conn, || {
+        for q in queries {
+            if q.params.is_empty() {
+                conn.run(&q.sql)?;
+            } else {
+                conn.run_with(&q.sql, &q.params)?;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// See [`Backend::last_id`]
+pub(crate) fn read_last_id(conn: &impl Sql, decl: &RelDecl) -> Result<i64, Error> {
+    let mut id = 0;
+    conn.each_row(&last_id(decl), &mut |row| {
+        id = row.get_int(0)?;
+        Ok(())
+    })?;
+    Ok(id)
+}
+
+/// See [`Backend::read`]
+pub(crate) fn read(conn: &impl Sql, decl: &RelDecl) -> Result<HashSet<Vec<Const>>, Error> {
+    let mut facts = HashSet::default();
+    conn.each_row(&select(decl), &mut |row| {
+        let fact = decl
+            .cols
+            .iter()
+            .enumerate()
+            .map(|(i, col)| get_const(row, i, col.ty))
+            .collect::<Result<_, _>>()?;
+        facts.insert(fact);
+        Ok(())
+    })?;
+    Ok(facts)
+}
+
+/// A quoted SQL identifier
+pub(crate) fn ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// The quoted name of the table for a relation
+pub(crate) fn table(rel: &Rel) -> String {
+    ident(&rel.to_string())
+}
+
+/// The quoted name of the table with the facts derived in the previous
+/// iteration. Not a valid [`Rel`] in the surface syntax, so it can't clash
+/// with one.
+pub(crate) fn delta_table(rel: &Rel) -> String {
+    ident(&format!("delta:{rel}"))
+}
+
+/// The quoted name of the table with the facts derived in this iteration,
+/// see [`delta_table`]
+pub(crate) fn new_table(rel: &Rel) -> String {
+    ident(&new_table_name(rel))
+}
+
+/// The unquoted name of the `new` table, see [`new_table`]
+pub(crate) fn new_table_name(rel: &Rel) -> String {
+    format!("new:{rel}")
+}
+
+/// The quoted names of the columns of the `delta` and `new` tables. Tables
+/// need at least one column, so nullary relations get `unit`.
+pub(crate) fn delta_cols(decl: &RelDecl) -> Vec<String> {
+    if decl.cols.is_empty() {
+        return vec![ident("unit")];
+    }
+    decl.cols.iter().map(|c| ident(&c.name)).collect()
+}
+
+/// The (quoted) name of the DuckDB sequence for the `id` column. Like the
+/// names of the `delta` and `new` tables and of the indices, it contains a
+/// `:`, which relation names don't, so it can't be the name of another
+/// relation, or of another relation's sequence.
+pub(crate) fn seq(rel: &Rel) -> String {
+    ident(&format!("{rel}:seq"))
+}
+
+/// A SQL string literal
+pub(crate) fn string_literal(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// A SQL literal. Prefer parameters (see [`Query::param`]): SQLite doesn't
+/// always parse float literals to the nearest float.
+pub(crate) fn literal(dialect: Dialect, c: &Const) -> String {
+    match c {
+        Const::Bool(b) => String::from(if *b { "TRUE" } else { "FALSE" }),
+        Const::Int(i) => i.to_string(),
+        Const::Float(x) if x.0.is_finite() => x.to_string(),
+        // SQL has no literals for these. SQLite turns overflowing literals
+        // into infinities, and NaNs into `NULL`s.
+        Const::Float(x) => match dialect {
+            Dialect::DuckDB => format!("CAST('{}' AS DOUBLE)", x.0),
+            Dialect::Sqlite if x.0.is_nan() => String::from("NULL"),
+            Dialect::Sqlite if x.0 > 0.0 => String::from("9e999"),
+            Dialect::Sqlite => String::from("-9e999"),
+        },
+        Const::Sym(s) => string_literal(s),
+    }
+}
+
+pub(crate) fn create_table(dialect: Dialect, decl: &RelDecl) -> String {
+    let rel = &decl.rel;
+    let table = table(rel);
+    let mut attrs = Vec::with_capacity(decl.arity());
+    let mut indices = Vec::new();
+    for (i, col) in decl.cols.iter().enumerate() {
+        let col_name = ident(&col.name);
+        attrs.push(format!("{col_name}  {} NOT NULL", col.ty.sql()));
+        let index = ident(&format!("{rel}:idx:{i}"));
+        indices.push(format!("CREATE INDEX {index} ON {table} ({col_name})"));
+    }
+    if decl.arity() > 1 {
+        let cols: Vec<_> = decl.cols.iter().map(|c| ident(&c.name)).collect();
+        let index = ident(&format!("{rel}:idx"));
+        indices.push(format!(
+            "CREATE INDEX {index} ON {table} ({})",
+            cols.join(", ")
+        ));
+    }
+    let mut deltas = Vec::with_capacity(decl.arity());
+    for col in &decl.cols {
+        deltas.push(format!("{}  {}", ident(&col.name), col.ty.sql()));
+    }
+    if deltas.is_empty() {
+        deltas.push(format!("{}  INTEGER", ident("unit")));
+    }
+    let delta_tables = format!(
+        r"CREATE TABLE {0} ({1});
+          CREATE TABLE {2} ({1});",
+        delta_table(rel),
+        deltas.join(", "),
+        new_table(rel),
+    );
+
+    // `it` is the iteration number
+    match dialect {
+        Dialect::DuckDB => {
+            let seq = seq(rel);
+            format!(
+                r"CREATE SEQUENCE {seq};
+          CREATE TABLE {0} (
+              id  INTEGER PRIMARY KEY DEFAULT NEXTVAL({4}),
+              it  INTEGER,
+              {1}
+          );
+          {2}{3}
+          {5}
+         ",
+                table,
+                attrs.join(",\n"),
+                indices.join(";\n"),
+                if indices.is_empty() { "" } else { ";" },
+                string_literal(&seq),
+                delta_tables,
+            )
+        }
+        Dialect::Sqlite => format!(
+            r"CREATE TABLE {0} (
+              id  INTEGER PRIMARY KEY AUTOINCREMENT,
+              it  INTEGER{1}
+              {2}
+          );
+          {3}{4}
+          {5}
+         ",
+            table,
+            if attrs.is_empty() { "" } else { "," },
+            attrs.join(",\n"),
+            indices.join(";\n"),
+            if indices.is_empty() { "" } else { ";" },
+            delta_tables,
+        ),
+    }
+}
+
+/// Drop the tables created by [`create_table`]
+pub(crate) fn drop_table(dialect: Dialect, decl: &RelDecl) -> String {
+    let rel = &decl.rel;
+    let mut sql = format!(
+        "DROP TABLE {}; DROP TABLE {}; DROP TABLE {};",
+        table(rel),
+        delta_table(rel),
+        new_table(rel)
+    );
+    if dialect == Dialect::DuckDB {
+        // The table depends on the sequence
+        sql += &format!(" DROP SEQUENCE {};", seq(rel));
+    }
+    sql
+}
+
+/// Statements that merge the `new` table of a relation at the end of an
+/// iteration, see [`merge`] and [`overdelete`]
+#[derive(Clone, Debug)]
+pub struct Merge {
+    /// Empties the `delta` table
+    pub(crate) clear: String,
+    /// Inserts the facts from `new` that aren't yet in the relation into
+    /// `delta`. The number of rows it inserts is the number of new facts.
+    pub(crate) diff: String,
+    /// Empties `new`, and inserts `delta` into the relation at iteration `it`
+    pub(crate) commit: String,
+}
+
+/// Duplicates are removed once per relation and iteration, rather than by
+/// each query that derives facts.
+pub(crate) fn merge(decl: &RelDecl, it: usize) -> Merge {
+    let (table, delta, new) = (
+        table(&decl.rel),
+        delta_table(&decl.rel),
+        new_table(&decl.rel),
+    );
+    let cols = delta_cols(decl).join(", ");
+    let attrs = if decl.cols.is_empty() {
+        String::new()
+    } else {
+        format!(", {cols}")
+    };
+    let mut eqs = Vec::with_capacity(decl.arity());
+    for col in &decl.cols {
+        let col = ident(&col.name);
+        eqs.push(format!("old.{col} = {new}.{col}"));
+    }
+    let mut old = format!("SELECT 1 FROM {table} AS old");
+    if !eqs.is_empty() {
+        old += " WHERE ";
+        old += &eqs.join(" AND ");
+    }
+    Merge {
+        clear: format!("DELETE FROM {delta};"),
+        diff: format!(
+            "INSERT INTO {delta} ({cols}) SELECT DISTINCT {cols} FROM {new} WHERE NOT EXISTS ({old});"
+        ),
+        commit: format!(
+            "DELETE FROM {new}; INSERT INTO {table} (it{attrs}) SELECT {it}{attrs} FROM {delta};"
+        ),
+    }
+}
+
+/// Like [`merge`], but for the over-deletion phase of delete-and-rederive
+/// (see [`crate::eval::Eval::retract_facts`]), where `new` has the facts that
+/// can be derived from deleted facts. Deleted rows are kept for now, with
+/// `it` set to -1, so that queries see the relation as it was.
+///
+/// `commit` deletes the derived rows of the facts in `new`. `diff` inserts
+/// those of them that are no longer in the relation into `delta`: those that
+/// have a derived row that isn't deleted yet, and aren't given.
+pub(crate) fn overdelete(decl: &RelDecl) -> Merge {
+    let (table, delta, new) = (
+        table(&decl.rel),
+        delta_table(&decl.rel),
+        new_table(&decl.rel),
+    );
+    let cols = delta_cols(decl).join(", ");
+    let eqs = |other: &str| -> String {
+        decl.cols
+            .iter()
+            .map(|col| {
+                let col = ident(&col.name);
+                format!(" AND old.{col} = {other}.{col}")
+            })
+            .collect()
+    };
+    let derived = format!("SELECT 1 FROM {table} AS old WHERE old.it > 0{}", eqs(&new));
+    let given = format!("SELECT 1 FROM {table} AS old WHERE old.it = 0{}", eqs(&new));
+    let gone = format!("SELECT 1 FROM {new} AS old WHERE TRUE{}", eqs(&table));
+    Merge {
+        clear: format!("DELETE FROM {delta};"),
+        diff: format!(
+            "INSERT INTO {delta} ({cols}) SELECT DISTINCT {cols} FROM {new} WHERE EXISTS ({derived}) AND NOT EXISTS ({given});"
+        ),
+        commit: format!(
+            "UPDATE {table} SET it = -1 WHERE it > 0 AND EXISTS ({gone}); DELETE FROM {new};"
+        ),
+    }
+}
+
+/// Mark the rows of a fact given to [`Backend::insert`] as deleted (see
+/// [`overdelete`]), the fact is passed as parameters. The fact must have
+/// been checked with [`check_fact`], or it may match other facts.
+pub(crate) fn retract(dialect: Dialect, decl: &RelDecl, consts: &[Const]) -> Query {
+    debug_assert!(check_fact(decl, consts).is_ok());
+    let mut q = Query {
+        sql: format!("UPDATE {} SET it = -1 WHERE it = 0", table(&decl.rel)),
+        params: Vec::with_capacity(consts.len()),
+    };
+    for (col, c) in decl.cols.iter().zip(consts) {
+        let param = q.param(dialect, c);
+        q.sql += &format!(" AND {} = {param}", ident(&col.name));
+    }
+    q.sql += ";";
+    q
+}
+
+/// Replace the contents of the `delta` table of a relation with the facts
+/// whose rows were all deleted by [`overdelete`] or [`retract`]
+pub(crate) fn vanished(decl: &RelDecl) -> Query {
+    let (table, delta) = (table(&decl.rel), delta_table(&decl.rel));
+    let cols = delta_cols(decl).join(", ");
+    let (attrs, eqs) = if decl.cols.is_empty() {
+        (String::from("1"), String::new())
+    } else {
+        let attrs: Vec<_> = decl
+            .cols
+            .iter()
+            .map(|col| format!("gone.{}", ident(&col.name)))
+            .collect();
+        let eqs: String = decl
+            .cols
+            .iter()
+            .map(|col| {
+                let col = ident(&col.name);
+                format!(" AND old.{col} = gone.{col}")
+            })
+            .collect();
+        (attrs.join(", "), eqs)
+    };
+    Query {
+        sql: format!(
+            "DELETE FROM {delta}; INSERT INTO {delta} ({cols}) SELECT DISTINCT {attrs} FROM {table} AS gone WHERE gone.it = -1 AND NOT EXISTS (SELECT 1 FROM {table} AS old WHERE old.it >= 0{eqs});"
+        ),
+        params: Vec::new(),
+    }
+}
+
+/// Delete the rows that were marked as deleted, see [`overdelete`]
+pub(crate) fn purge(decl: &RelDecl) -> Query {
+    Query {
+        sql: format!("DELETE FROM {} WHERE it = -1;", table(&decl.rel)),
+        params: Vec::new(),
+    }
+}
+
+/// Insert `rows` facts into the `new` table of a relation, the facts are
+/// passed as parameters, one after the other. See [`load`].
+pub(crate) fn stage(dialect: Dialect, decl: &RelDecl, rows: usize) -> String {
+    let arity = decl.arity();
+    let values: Vec<String> = (0..rows)
+        .map(|row| {
+            if arity == 0 {
+                // See [`delta_cols`]
+                return String::from("(1)");
+            }
+            let params: Vec<_> = (1..=arity)
+                .map(|i| placeholder(dialect, row * arity + i))
+                .collect();
+            format!("({})", params.join(", "))
+        })
+        .collect();
+    format!(
+        "INSERT INTO {} ({}) VALUES {};",
+        new_table(&decl.rel),
+        delta_cols(decl).join(", "),
+        values.join(", ")
+    )
+}
+
+/// Move the facts in the `new` table of a relation into the relation at
+/// iteration 0, skipping those that are already there. Inserting facts into
+/// `new` first (see [`stage`]) lets the backends load them in bulk, and
+/// removes duplicates in a single query. Like [`merge`], leaves `id` to its
+/// default.
+pub(crate) fn load(decl: &RelDecl) -> String {
+    let (table, new) = (table(&decl.rel), new_table(&decl.rel));
+    let mut attrs = String::new();
+    let mut eqs = String::new();
+    for col in &decl.cols {
+        let col = ident(&col.name);
+        attrs += &format!(", {col}");
+        eqs += &format!(" AND old.{col} = {new}.{col}");
+    }
+    format!(
+        "INSERT INTO {table} (it{attrs}) SELECT DISTINCT 0{attrs} FROM {new} WHERE NOT EXISTS (SELECT 1 FROM {table} AS old WHERE old.it = 0{eqs}); DELETE FROM {new};"
+    )
+}
+
+/// Replace the contents of the `delta` table of a relation with the facts in
+/// rows after `id`
+pub(crate) fn delta_since(decl: &RelDecl, id: i64) -> Query {
+    let cols = delta_cols(decl).join(", ");
+    let attrs = if decl.cols.is_empty() {
+        String::from("1")
+    } else {
+        cols.clone()
+    };
+    Query {
+        sql: format!(
+            "DELETE FROM {0}; INSERT INTO {0} ({cols}) SELECT DISTINCT {attrs} FROM {1} WHERE id > {id};",
+            delta_table(&decl.rel),
+            table(&decl.rel),
+        ),
+        params: Vec::new(),
+    }
+}
+
+/// Delete the rows of a relation that weren't given to [`Backend::insert`]
+pub(crate) fn clear_derived(decl: &RelDecl) -> Query {
+    Query {
+        sql: format!("DELETE FROM {} WHERE it > 0;", table(&decl.rel)),
+        params: Vec::new(),
+    }
+}
+
+/// The largest `id` of a relation, or 0
+pub(crate) fn last_id(decl: &RelDecl) -> String {
+    format!("SELECT COALESCE(MAX(id), 0) FROM {};", table(&decl.rel))
+}
+
+/// Select the distinct facts of a relation, without `id` and `it`
+pub(crate) fn select(decl: &RelDecl) -> String {
+    if decl.cols.is_empty() {
+        return format!("SELECT 1 from {} LIMIT 1;", table(&decl.rel));
+    }
+    let cols: Vec<_> = decl.cols.iter().map(|c| ident(&c.name)).collect();
+    format!(
+        "SELECT DISTINCT {} from {};",
+        cols.join(", "),
+        table(&decl.rel)
+    )
+}
+
+/// Select the distinct facts of a relation for [`Backend::export`]. Files
+/// need at least one column, so nullary relations get `unit`, like their
+/// `delta` tables, which is `true` if the relation holds.
+pub(crate) fn export(decl: &RelDecl) -> String {
+    if decl.cols.is_empty() {
+        return format!(
+            "SELECT DISTINCT true AS {} FROM {}",
+            ident("unit"),
+            table(&decl.rel)
+        );
+    }
+    select(decl).trim_end_matches(';').to_string()
+}
+
+/// The names and types of the columns selected by [`export`]
+pub(crate) fn export_cols(decl: &RelDecl) -> Vec<(&str, Type)> {
+    if decl.cols.is_empty() {
+        return vec![("unit", Type::Bool)];
+    }
+    decl.cols.iter().map(|c| (c.name.as_str(), c.ty)).collect()
+}
+
+/// A constant as a JSON value
+pub(crate) fn json(c: &Const) -> String {
+    match c {
+        Const::Float(x) if !x.0.is_finite() => String::from("null"),
+        Const::Sym(s) => json_string(s),
+        c => c.to_string(),
+    }
+}
+
+/// A JSON string literal
+pub(crate) fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if u32::from(c) < 0x20 => out.push_str(&format!("\\u{:04x}", u32::from(c))),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{Float, Type};
+
+    use super::*;
+
+    fn round_trip<B: Backend>(backend: B) {
+        let decl = RelDecl::inferred(Rel::new(String::from("r")), vec![Type::Sym, Type::Int]);
+        backend.create_relation(&decl).unwrap();
+        let a = vec![Const::Sym(String::from("a")), Const::Int(1)];
+        let b = vec![Const::Sym(String::from("b'")), Const::Int(2)];
+        backend
+            .insert(&decl, [a.as_slice(), b.as_slice(), a.as_slice()])
+            .unwrap();
+        backend.insert(&decl, [b.as_slice()]).unwrap();
+        let facts = backend.read(&decl).unwrap();
+        assert_eq!(HashSet::from_iter([a, b]), facts);
+
+        let nullary = RelDecl::inferred(Rel::new(String::from("n")), Vec::new());
+        backend.create_relation(&nullary).unwrap();
+        assert!(backend.read(&nullary).unwrap().is_empty());
+
+        backend.drop_relation(&decl).unwrap();
+        backend.create_relation(&decl).unwrap();
+        assert!(backend.read(&decl).unwrap().is_empty());
+        backend.insert(&nullary, [[].as_slice()]).unwrap();
+        assert_eq!(1, backend.read(&nullary).unwrap().len());
+    }
+
+    #[test]
+    fn test_literal() {
+        let lit = |dialect, x| literal(dialect, &Const::Float(Float(x)));
+        assert_eq!("1.5", lit(Dialect::Sqlite, 1.5));
+        assert_eq!("9e999", lit(Dialect::Sqlite, f64::INFINITY));
+        assert_eq!("-9e999", lit(Dialect::Sqlite, f64::NEG_INFINITY));
+        assert_eq!(
+            "CAST('-inf' AS DOUBLE)",
+            lit(Dialect::DuckDB, f64::NEG_INFINITY)
+        );
+        assert_eq!("CAST('NaN' AS DOUBLE)", lit(Dialect::DuckDB, f64::NAN));
+    }
+
+    #[test]
+    fn test_json() {
+        assert_eq!("1", json(&Const::Int(1)));
+        assert_eq!("null", json(&Const::Float(Float(f64::NAN))));
+        assert_eq!(
+            r#""a\"\\\n\u0001""#,
+            json(&Const::Sym(String::from("a\"\\\n\u{1}")))
+        );
+    }
+
+    #[cfg(feature = "duckdb")]
+    #[test]
+    fn duckdb_round_trip() {
+        round_trip(::duckdb::Connection::open_in_memory().unwrap())
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_round_trip() {
+        round_trip(::rusqlite::Connection::open_in_memory().unwrap())
+    }
+}
@@ -0,0 +1,165 @@
+use std::path::Path;
+
+use duckdb::{appender_params_from_iter, params_from_iter, Connection, Result, Row};
+use fxhash::FxHashSet as HashSet;
+
+use super::{
+    batch, check_fact, create_table, delta_cols, drop_table, export, ident, load, new_table,
+    new_table_name, read, read_last_id, step, string_literal, Backend, Dialect, Error,
+    ExportFormat, Merge, Query, Source, SourceFormat, Sql, SqlRow,
+};
+use crate::ast::{Const, RelDecl};
+
+impl SqlRow for Row<'_> {
+    fn get_bool(&self, i: usize) -> Result<bool, Error> {
+        Ok(self.get(i)?)
+    }
+
+    fn get_int(&self, i: usize) -> Result<i64, Error> {
+        Ok(self.get(i)?)
+    }
+
+    fn get_float(&self, i: usize) -> Result<f64, Error> {
+        Ok(self.get(i)?)
+    }
+
+    fn get_sym(&self, i: usize) -> Result<String, Error> {
+        Ok(self.get(i)?)
+    }
+}
+
+impl Sql for Connection {
+    fn run(&self, sql: &str) -> Result<(), Error> {
+        self.execute_batch(sql)?;
+        Ok(())
+    }
+
+    fn run_with(&self, sql: &str, params: &[Const]) -> Result<usize, Error> {
+        Ok(self.execute(sql, params_from_iter(params))?)
+    }
+
+    fn each_row(
+        &self,
+        sql: &str,
+        f: &mut dyn FnMut(&dyn SqlRow) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut q = self.prepare(sql)?;
+        let mut rows = q.query([])?;
+        while let Some(row) = rows.next()? {
+            f(row)?;
+        }
+        Ok(())
+    }
+}
+
+/// A table function that reads `source`. CSV columns are read as text, and
+/// cast to the types of the relation by [`Backend::load`], rather than
+/// guessing their types, which would turn the symbol `1.50` into `1.5`.
+fn reader(source: &Source) -> String {
+    let path = string_literal(&source.path.to_string_lossy());
+    match source.format {
+        SourceFormat::Csv { delimiter, header } => format!(
+            "read_csv_auto({path}, delim={}, header={header}, all_varchar=true)",
+            string_literal(&char::from(delimiter).to_string())
+        ),
+        SourceFormat::Parquet => format!("read_parquet({path})"),
+    }
+}
+
+impl Backend for Connection {
+    fn dialect(&self) -> Dialect {
+        Dialect::DuckDB
+    }
+
+    fn create_relation(&self, decl: &RelDecl) -> Result<(), Error> {
+        self.execute_batch(&create_table(Dialect::DuckDB, decl))?;
+        Ok(())
+    }
+
+    fn drop_relation(&self, decl: &RelDecl) -> Result<(), Error> {
+        self.execute_batch(&drop_table(Dialect::DuckDB, decl))?;
+        Ok(())
+    }
+
+    fn insert<'a>(
+        &self,
+        decl: &RelDecl,
+        facts: impl IntoIterator<Item = &'a [Const]>,
+    ) -> Result<(), Error> {
+        // Appended rows are only visible to the transaction, so an error
+        // doesn't leave some of them in `new` for the next evaluation
+        let tx = self.unchecked_transaction()?;
+        {
+            // Flushed when dropped
+            let mut appender = tx.appender(&new_table_name(&decl.rel))?;
+            let mut any = false;
+            for fact in facts {
+                check_fact(decl, fact)?;
+                if !decl.cols.is_empty() {
+                    appender.append_row(appender_params_from_iter(fact))?;
+                } else if !any {
+                    appender.append_row([1])?;
+                }
+                any = true;
+            }
+        }
+        tx.execute_batch(&load(decl))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load(&self, decl: &RelDecl, source: &Source) -> Result<(), Error> {
+        let reader = reader(source);
+        let names: Vec<String> = self
+            .prepare(&format!("DESCRIBE SELECT * FROM {reader}"))?
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_>>()?;
+        let mapping = source.mapping(decl, &names)?;
+        let cols: Vec<String> = mapping
+            .iter()
+            .zip(&decl.cols)
+            .map(|(i, col)| format!("CAST({} AS {})", ident(&names[*i]), col.ty.sql()))
+            .collect();
+        // Without `COMMIT`, a bad value leaves nothing behind
+        let tx = self.unchecked_transaction()?;
+        tx.execute_batch(&format!(
+            "INSERT INTO {} ({}) SELECT {} FROM {reader}; {}",
+            new_table(&decl.rel),
+            delta_cols(decl).join(", "),
+            cols.join(", "),
+            load(decl)
+        ))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn step(&self, queries: &[Query], merges: &[Merge]) -> Result<bool, Error> {
+        step(self, queries, merges)
+    }
+
+    fn batch(&self, queries: &[Query]) -> Result<(), Error> {
+        batch(self, queries)
+    }
+
+    fn last_id(&self, decl: &RelDecl) -> Result<i64, Error> {
+        read_last_id(self, decl)
+    }
+
+    fn read(&self, decl: &RelDecl) -> Result<HashSet<Vec<Const>>, Error> {
+        read(self, decl)
+    }
+
+    fn export(&self, decl: &RelDecl, path: &Path, format: ExportFormat) -> Result<(), Error> {
+        let options = match format {
+            ExportFormat::Csv => "FORMAT CSV, HEADER true",
+            ExportFormat::Parquet => "FORMAT PARQUET",
+            ExportFormat::Json => "FORMAT JSON",
+        };
+        self.execute_batch(&format!(
+            "COPY ({}) TO {} ({options});",
+            export(decl),
+            string_literal(&path.to_string_lossy())
+        ))?;
+        Ok(())
+    }
+}
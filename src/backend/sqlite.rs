@@ -0,0 +1,264 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use fxhash::FxHashSet as HashSet;
+use rusqlite::{params_from_iter, Connection, Row};
+
+use super::{
+    batch, check_fact, create_table, drop_table, export, export_cols, get_const, json, json_string,
+    load, read, read_last_id, stage, step, Backend, Dialect, Error, ExportFormat, Merge, Query,
+    Source, SourceFormat, Sql, SqlRow,
+};
+use crate::ast::{Const, RelDecl};
+
+impl SqlRow for Row<'_> {
+    fn get_bool(&self, i: usize) -> Result<bool, Error> {
+        Ok(self.get(i)?)
+    }
+
+    fn get_int(&self, i: usize) -> Result<i64, Error> {
+        Ok(self.get(i)?)
+    }
+
+    fn get_float(&self, i: usize) -> Result<f64, Error> {
+        Ok(self.get(i)?)
+    }
+
+    fn get_sym(&self, i: usize) -> Result<String, Error> {
+        Ok(self.get(i)?)
+    }
+}
+
+impl Sql for Connection {
+    fn run(&self, sql: &str) -> Result<(), Error> {
+        self.execute_batch(sql)?;
+        Ok(())
+    }
+
+    fn run_with(&self, sql: &str, params: &[Const]) -> Result<usize, Error> {
+        Ok(self.execute(sql, params_from_iter(params))?)
+    }
+
+    fn each_row(
+        &self,
+        sql: &str,
+        f: &mut dyn FnMut(&dyn SqlRow) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut q = self.prepare(sql)?;
+        let mut rows = q.query([])?;
+        while let Some(row) = rows.next()? {
+            f(row)?;
+        }
+        Ok(())
+    }
+}
+
+/// The most facts to insert with one statement. SQLite allows at most 32766
+/// parameters per statement.
+fn batch_size(arity: usize) -> usize {
+    1024.min(32766 / arity.max(1))
+}
+
+/// Insert facts into the `new` table of a relation, see [`stage`]
+fn stage_facts<'a>(
+    conn: &Connection,
+    decl: &RelDecl,
+    facts: impl IntoIterator<Item = &'a [Const]>,
+) -> Result<(), Error> {
+    if decl.cols.is_empty() {
+        let mut any = false;
+        for fact in facts {
+            check_fact(decl, fact)?;
+            any = true;
+        }
+        if any {
+            conn.execute(&stage(Dialect::Sqlite, decl, 1), [])?;
+        }
+        return Ok(());
+    }
+    let size = batch_size(decl.arity());
+    let mut batch: Vec<&Const> = Vec::new();
+    let mut rows = 0;
+    for fact in facts {
+        // The parameters of a batch are flattened, so a fact of the wrong
+        // arity would shift the values of the others
+        check_fact(decl, fact)?;
+        batch.extend(fact);
+        rows += 1;
+        if rows == size {
+            conn.prepare_cached(&stage(Dialect::Sqlite, decl, size))?
+                .execute(params_from_iter(&batch))?;
+            batch.clear();
+            rows = 0;
+        }
+    }
+    if rows > 0 {
+        conn.prepare(&stage(Dialect::Sqlite, decl, rows))?
+            .execute(params_from_iter(&batch))?;
+    }
+    Ok(())
+}
+
+impl Backend for Connection {
+    fn dialect(&self) -> Dialect {
+        Dialect::Sqlite
+    }
+
+    fn create_relation(&self, decl: &RelDecl) -> Result<(), Error> {
+        self.execute_batch(&create_table(Dialect::Sqlite, decl))?;
+        Ok(())
+    }
+
+    fn drop_relation(&self, decl: &RelDecl) -> Result<(), Error> {
+        self.execute_batch(&drop_table(Dialect::Sqlite, decl))?;
+        Ok(())
+    }
+
+    fn insert<'a>(
+        &self,
+        decl: &RelDecl,
+        facts: impl IntoIterator<Item = &'a [Const]>,
+    ) -> Result<(), Error> {
+        let tx = self.unchecked_transaction()?;
+        stage_facts(&tx, decl, facts)?;
+        tx.execute_batch(&load(decl))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load(&self, decl: &RelDecl, source: &Source) -> Result<(), Error> {
+        let (delimiter, header) = match source.format {
+            SourceFormat::Csv { delimiter, header } => (delimiter, header),
+            SourceFormat::Parquet => {
+                return Err(Error::SourceFormat {
+                    path: source.path.clone(),
+                    format: "Parquet",
+                })
+            }
+        };
+        let csv_err = |e| Error::Csv {
+            path: source.path.clone(),
+            source: e,
+        };
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(header)
+            .delimiter(delimiter)
+            .from_path(&source.path)
+            .map_err(csv_err)?;
+        let mut mapping = if header {
+            let names: Vec<String> = reader
+                .headers()
+                .map_err(csv_err)?
+                .iter()
+                .map(String::from)
+                .collect();
+            Some(source.mapping(decl, &names)?)
+        } else {
+            None
+        };
+        // Parse the file in chunks, so that it needn't fit in memory, but add
+        // them to the relation all at once, so that a bad value doesn't leave
+        // the file half-loaded
+        let tx = self.unchecked_transaction()?;
+        let size = 1 << 16;
+        let mut facts: Vec<Vec<Const>> = Vec::with_capacity(size);
+        for record in reader.records() {
+            let record = record.map_err(csv_err)?;
+            let mapping = match &mut mapping {
+                Some(mapping) => mapping,
+                None => {
+                    let names: Vec<String> =
+                        (0..record.len()).map(|i| format!("column{i}")).collect();
+                    mapping.insert(source.mapping(decl, &names)?)
+                }
+            };
+            let line = record.position().map_or(0, csv::Position::line);
+            let fact = mapping
+                .iter()
+                .zip(&decl.cols)
+                .map(|(i, col)| {
+                    let value = &record[*i];
+                    Const::parse(col.ty, value).ok_or_else(|| Error::SourceValue {
+                        path: source.path.clone(),
+                        line,
+                        ty: col.ty,
+                        value: value.to_string(),
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+            facts.push(fact);
+            if facts.len() == size {
+                stage_facts(&tx, decl, facts.iter().map(Vec::as_slice))?;
+                facts.clear();
+            }
+        }
+        stage_facts(&tx, decl, facts.iter().map(Vec::as_slice))?;
+        tx.execute_batch(&load(decl))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn step(&self, queries: &[Query], merges: &[Merge]) -> Result<bool, Error> {
+        step(self, queries, merges)
+    }
+
+    fn batch(&self, queries: &[Query]) -> Result<(), Error> {
+        batch(self, queries)
+    }
+
+    fn last_id(&self, decl: &RelDecl) -> Result<i64, Error> {
+        read_last_id(self, decl)
+    }
+
+    fn read(&self, decl: &RelDecl) -> Result<HashSet<Vec<Const>>, Error> {
+        read(self, decl)
+    }
+
+    /// Streams the facts into the file. Only CSV and JSON are supported.
+    fn export(&self, decl: &RelDecl, path: &Path, format: ExportFormat) -> Result<(), Error> {
+        if format == ExportFormat::Parquet {
+            return Err(Error::ExportFormat {
+                path: path.to_path_buf(),
+                format: format.name(),
+            });
+        }
+        let io_err = |e| Error::Io {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let csv_err = |e| Error::Csv {
+            path: path.to_path_buf(),
+            source: e,
+        };
+        let cols = export_cols(decl);
+        let file = File::create(path).map_err(io_err)?;
+        if format == ExportFormat::Csv {
+            let mut writer = csv::Writer::from_writer(file);
+            writer
+                .write_record(cols.iter().map(|(name, _)| name))
+                .map_err(csv_err)?;
+            self.each_row(&export(decl), &mut |row| {
+                for (i, (_, ty)) in cols.iter().enumerate() {
+                    writer
+                        .write_field(String::from(get_const(row, i, *ty)?))
+                        .map_err(csv_err)?;
+                }
+                writer.write_record(None::<&[u8]>).map_err(csv_err)
+            })?;
+            writer.flush().map_err(io_err)?;
+        } else {
+            let mut writer = BufWriter::new(file);
+            self.each_row(&export(decl), &mut |row| {
+                let mut fields = Vec::with_capacity(cols.len());
+                for (i, (name, ty)) in cols.iter().enumerate() {
+                    let value = json(&get_const(row, i, *ty)?);
+                    fields.push(format!("{}:{value}", json_string(name)));
+                }
+                writeln!(writer, "{{{}}}", fields.join(",")).map_err(io_err)
+            })?;
+            writer.flush().map_err(io_err)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,652 @@
+//! Command-line interface: evaluate a Datalog program over facts from CSV and
+//! TSV files, or interactively (see [`repl`]).
+
+use std::collections::BTreeSet;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use duckalog::ast::{Const, IoParams, Rel, RelDecl, Type};
+use duckalog::backend::{self, Backend};
+use duckalog::eval::Eval;
+use duckalog::mir::{self, Mir};
+use duckalog::parse;
+
+mod repl;
+
+const USAGE: &str = "\
+Usage: duckalog [OPTIONS] PROGRAM
+       duckalog --repl [OPTIONS] [PROGRAM]
+
+Evaluate the Datalog program in the file PROGRAM, or start an interactive
+session with it (see `.help` in the session).
+
+Relations declared with `.input` are read from files in the facts directory,
+and those declared with `.output` are written to files in the output
+directory, as in Soufflé. Files are tab-separated unless the directive sets
+`delimiter` or `rfc4180=true`. Without `.input` directives, the facts of each
+relation `r` are read from `r.csv` (comma-separated), `r.tsv`, or `r.facts`
+(tab-separated), if present. Without `.output` directives, the rule heads are
+written to `r.csv` in the output directory, if given, or to stdout as facts.
+
+Options:
+  -F, --facts DIR      Read facts from DIR [default: .]
+  -D, --output DIR     Write output relations to files in DIR, or to stdout if
+                       DIR is `-` [default: . with `.output` directives]
+  -b, --backend NAME   Evaluate with `duckdb` or `sqlite` [default: duckdb if
+                       enabled, otherwise sqlite]
+      --db PATH        Store the relations in a new database at PATH, rather
+                       than in memory
+  -i, --iterations     Print the number of iterations of each group of
+                       mutually recursive relations to stderr
+  -r, --repl           Read rules, facts, queries, and commands from stdin
+  -h, --help           Print this message
+";
+
+#[derive(Debug, thiserror::Error)]
+enum Error {
+    #[error(transparent)]
+    Args(#[from] lexopt::Error),
+    #[error(transparent)]
+    Backend(#[from] backend::Error),
+    #[error("{}: {source}", .path.display())]
+    Csv { path: PathBuf, source: csv::Error },
+    #[error("{}:{line}: expected {expected} fields for relation `{rel}`, found {found}", .path.display())]
+    Fields {
+        path: PathBuf,
+        line: u64,
+        rel: Rel,
+        expected: usize,
+        found: usize,
+    },
+    #[error("{}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    Mir(#[from] mir::Error),
+    #[error("unsupported value `{value}` of parameter `{key}` of relation `{rel}`")]
+    Param {
+        rel: Rel,
+        key: &'static str,
+        value: String,
+    },
+    /// A rendered [`duckalog::diag::Diagnostic`]
+    #[error("{0}")]
+    Parse(String),
+    #[error("{0}")]
+    Usage(String),
+    #[error("{}:{line}: invalid {ty} `{value}`", .path.display())]
+    Value {
+        path: PathBuf,
+        line: u64,
+        ty: Type,
+        value: String,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BackendKind {
+    #[cfg(feature = "duckdb")]
+    DuckDB,
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+impl BackendKind {
+    fn new(name: &str) -> Result<Self, Error> {
+        match name {
+            #[cfg(feature = "duckdb")]
+            "duckdb" => Ok(BackendKind::DuckDB),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(BackendKind::Sqlite),
+            #[cfg(not(feature = "duckdb"))]
+            "duckdb" => Err(Error::Usage(String::from(
+                "backend `duckdb` is not enabled, rebuild with `--features duckdb`",
+            ))),
+            #[cfg(not(feature = "sqlite"))]
+            "sqlite" => Err(Error::Usage(String::from(
+                "backend `sqlite` is not enabled, rebuild with `--features sqlite`",
+            ))),
+            _ => Err(Error::Usage(format!("unknown backend `{name}`"))),
+        }
+    }
+
+    #[allow(unreachable_code)]
+    fn default() -> Option<Self> {
+        #[cfg(feature = "duckdb")]
+        return Some(BackendKind::DuckDB);
+        #[cfg(feature = "sqlite")]
+        return Some(BackendKind::Sqlite);
+        None
+    }
+}
+
+#[derive(Debug)]
+struct Opts {
+    /// Optional with `--repl`
+    program: Option<PathBuf>,
+    facts: PathBuf,
+    output: Option<PathBuf>,
+    backend: BackendKind,
+    db: Option<PathBuf>,
+    iterations: bool,
+    repl: bool,
+}
+
+/// Returns `None` if the user asked for help.
+fn opts(args: impl IntoIterator<Item = std::ffi::OsString>) -> Result<Option<Opts>, Error> {
+    use lexopt::prelude::*;
+
+    let mut program = None;
+    let mut facts = PathBuf::from(".");
+    let mut output = None;
+    let mut backend = None;
+    let mut db = None;
+    let mut iterations = false;
+    let mut repl = false;
+    let mut parser = lexopt::Parser::from_args(args);
+    while let Some(arg) = parser.next()? {
+        match arg {
+            Short('F') | Long("facts") => facts = parser.value()?.into(),
+            Short('D') | Long("output") => output = Some(parser.value()?.into()),
+            Short('b') | Long("backend") => {
+                backend = Some(BackendKind::new(&parser.value()?.string()?)?)
+            }
+            Long("db") => db = Some(parser.value()?.into()),
+            Short('i') | Long("iterations") => iterations = true,
+            Short('r') | Long("repl") => repl = true,
+            Short('h') | Long("help") => return Ok(None),
+            Value(path) if program.is_none() => program = Some(path.into()),
+            _ => return Err(arg.unexpected().into()),
+        }
+    }
+    if program.is_none() && !repl {
+        return Err(Error::Usage(String::from("missing PROGRAM")));
+    }
+    let backend = backend
+        .or_else(BackendKind::default)
+        .ok_or_else(|| Error::Usage(String::from("no backends are enabled")))?;
+    Ok(Some(Opts {
+        program,
+        facts,
+        output,
+        backend,
+        db,
+        iterations,
+        repl,
+    }))
+}
+
+fn read_to_string(path: &Path) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn program(path: Option<&Path>) -> Result<Mir, Error> {
+    let (path, src) = match path {
+        None => return Ok(Mir::new(parse::parse("").expect("empty program"))?),
+        Some(path) => (path, read_to_string(path)?),
+    };
+    let ast = parse::parse(&src)
+        .map_err(|e| Error::Parse(e.diagnostic().render(&path.display().to_string(), &src)))?;
+    Ok(Mir::new(ast)?)
+}
+
+/// How the facts of a relation are laid out in a file
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Format {
+    delimiter: u8,
+    /// Whether fields may be quoted, as in RFC 4180
+    quoting: bool,
+    /// Whether the first line holds the column names
+    headers: bool,
+}
+
+impl Format {
+    const CSV: Format = Format {
+        delimiter: b',',
+        quoting: true,
+        headers: false,
+    };
+
+    const TSV: Format = Format {
+        delimiter: b'\t',
+        quoting: false,
+        headers: false,
+    };
+
+    /// From the parameters of an `.input` or `.output` directive, with the
+    /// same defaults as Soufflé
+    fn new(rel: &Rel, params: &IoParams) -> Result<Self, Error> {
+        let flag = |key: &'static str| match params.get(key).map(String::as_str) {
+            None | Some("false") => Ok(false),
+            Some("true") => Ok(true),
+            Some(value) => Err(Error::Param {
+                rel: rel.clone(),
+                key,
+                value: String::from(value),
+            }),
+        };
+        let quoting = flag("rfc4180")?;
+        let headers = flag("headers")?;
+        let delimiter = match params.get("delimiter") {
+            None if quoting => b',',
+            None => b'\t',
+            Some(d) if d.len() == 1 => d.as_bytes()[0],
+            Some(d) => {
+                return Err(Error::Param {
+                    rel: rel.clone(),
+                    key: "delimiter",
+                    value: d.clone(),
+                })
+            }
+        };
+        Ok(Self {
+            delimiter,
+            quoting,
+            headers,
+        })
+    }
+}
+
+/// Where to read or write `rel`, given the parameters of its `.input` or
+/// `.output` directive, relative to `dir`: `None` for stdout, which only
+/// outputs may use.
+fn directive_file(
+    rel: &Rel,
+    params: &IoParams,
+    output: bool,
+    dir: &Path,
+    ext: &str,
+) -> Result<Option<(PathBuf, Format)>, Error> {
+    let file = match params.get("IO").map(String::as_str) {
+        None | Some("file") => params
+            .get("filename")
+            .map_or_else(|| format!("{rel}.{ext}"), String::clone),
+        Some("stdout") if output => return Ok(None),
+        Some(value) => {
+            return Err(Error::Param {
+                rel: rel.clone(),
+                key: "IO",
+                value: String::from(value),
+            })
+        }
+    };
+    Ok(Some((dir.join(file), Format::new(rel, params)?)))
+}
+
+/// The facts file for `rel` in `dir`, for programs without `.input`
+/// directives
+fn facts_file(dir: &Path, rel: &Rel) -> Option<(PathBuf, Format)> {
+    [
+        ("csv", Format::CSV),
+        ("tsv", Format::TSV),
+        ("facts", Format::TSV),
+    ]
+    .into_iter()
+    .map(|(ext, format)| (dir.join(format!("{rel}.{ext}")), format))
+    .find(|(path, _)| path.is_file())
+}
+
+fn read_facts(path: &Path, format: Format, decl: &RelDecl) -> Result<Vec<Vec<Const>>, Error> {
+    // The reader skips empty lines, so a nullary relation holds if its file
+    // has any line at all
+    if decl.arity() == 0 {
+        let lines = read_to_string(path)?.lines().count();
+        return Ok(if lines > 0 {
+            vec![Vec::new()]
+        } else {
+            Vec::new()
+        });
+    }
+    let csv_err = |source| Error::Csv {
+        path: path.to_path_buf(),
+        source,
+    };
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(format.headers)
+        .delimiter(format.delimiter)
+        .quoting(format.quoting)
+        .flexible(true)
+        .from_path(path)
+        .map_err(csv_err)?;
+    let types = decl.types();
+    let mut facts = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(csv_err)?;
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+        if record.len() != types.len() {
+            return Err(Error::Fields {
+                path: path.to_path_buf(),
+                line,
+                rel: decl.rel().clone(),
+                expected: types.len(),
+                found: record.len(),
+            });
+        }
+        let fact = record
+            .iter()
+            .zip(&types)
+            .map(|(field, ty)| {
+                Const::parse(*ty, field).ok_or_else(|| Error::Value {
+                    path: path.to_path_buf(),
+                    line,
+                    ty: *ty,
+                    value: String::from(field),
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        facts.push(fact);
+    }
+    Ok(facts)
+}
+
+/// The relations to print: the declared outputs, if any, otherwise the rule
+/// heads, except those that hold the results of aggregates in rule bodies
+fn outputs(mir: &Mir) -> BTreeSet<Rel> {
+    let declared: BTreeSet<Rel> = mir
+        .decls()
+        .into_values()
+        .filter(|d| d.output().is_some())
+        .map(|d| d.rel().clone())
+        .collect();
+    if !declared.is_empty() {
+        return declared;
+    }
+    mir.rules()
+        .map(|r| r.head().rel())
+        .filter(|rel| !rel.is_aggregate())
+        .cloned()
+        .collect()
+}
+
+fn write_facts(out: &mut impl Write, rel: &Rel, facts: &[Vec<Const>]) -> io::Result<()> {
+    for fact in facts {
+        let terms: Vec<String> = fact.iter().map(Const::to_string).collect();
+        writeln!(out, "{rel}({}).", terms.join(", "))?;
+    }
+    Ok(())
+}
+
+fn write_csv(
+    path: &Path,
+    format: Format,
+    decl: &RelDecl,
+    facts: &[Vec<Const>],
+) -> Result<(), Error> {
+    let csv_err = |source| Error::Csv {
+        path: path.to_path_buf(),
+        source,
+    };
+    let quote = if format.quoting {
+        csv::QuoteStyle::Necessary
+    } else {
+        csv::QuoteStyle::Never
+    };
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(format.delimiter)
+        .quote_style(quote)
+        .from_path(path)
+        .map_err(csv_err)?;
+    if format.headers {
+        writer
+            .write_record(decl.cols().iter().map(|c| c.name()))
+            .map_err(csv_err)?;
+    }
+    for fact in facts {
+        writer
+            .write_record(fact.iter().cloned().map(String::from))
+            .map_err(csv_err)?;
+    }
+    writer.flush().map_err(|source| Error::Io {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+fn run<B: Backend>(backend: B, opts: &Opts, out: &mut impl Write) -> Result<(), Error> {
+    let mut mir = program(opts.program.as_deref())?;
+    let decls = mir.decls();
+    let directives = decls.values().any(|d| d.input().is_some());
+    for (rel, decl) in &decls {
+        let file = match decl.input() {
+            _ if !directives => facts_file(&opts.facts, rel),
+            Some(params) => directive_file(rel, params, false, &opts.facts, "facts")?,
+            None => None,
+        };
+        if let Some((path, format)) = file {
+            for fact in read_facts(&path, format, decl)? {
+                mir.add_fact(rel, fact);
+            }
+        }
+    }
+    // Where to write each output relation, `None` for stdout
+    let mut targets: Vec<(Rel, Option<(PathBuf, Format)>)> = Vec::new();
+    for rel in outputs(&mir) {
+        let decl = &decls[&rel];
+        let file = match (opts.output.as_deref(), decl.output()) {
+            (Some(dir), _) if dir == Path::new("-") => None,
+            (dir, Some(params)) => {
+                directive_file(&rel, params, true, dir.unwrap_or(Path::new(".")), "csv")?
+            }
+            (Some(dir), None) => Some((dir.join(format!("{rel}.csv")), Format::CSV)),
+            (None, None) => None,
+        };
+        targets.push((rel, file));
+    }
+
+    let mut eval = Eval::new(backend, mir)?;
+    eval.clear_facts();
+    if opts.repl {
+        let stdin = io::stdin();
+        let prompt = stdin.is_terminal();
+        return repl::repl(&mut eval, stdin.lock(), out, prompt).map_err(|source| Error::Io {
+            path: PathBuf::from("<stdin>"),
+            source,
+        });
+    }
+    let iters = eval.go()?;
+    if opts.iterations {
+        for (rels, n) in iters {
+            let names: Vec<String> = rels.iter().map(Rel::to_string).collect();
+            eprintln!("{}: {n} iterations", names.join(", "));
+        }
+    }
+
+    let stdout_err = |source| Error::Io {
+        path: PathBuf::from("<stdout>"),
+        source,
+    };
+    for (rel, file) in &targets {
+        let mut facts: Vec<_> = eval.relation(rel)?.into_iter().collect();
+        facts.sort();
+        match file {
+            Some((path, format)) => write_csv(path, *format, &decls[rel], &facts)?,
+            None => write_facts(out, rel, &facts).map_err(stdout_err)?,
+        }
+    }
+    out.flush().map_err(stdout_err)
+}
+
+fn cli(opts: &Opts, out: &mut impl Write) -> Result<(), Error> {
+    if let Some(path) = opts.db.as_ref().filter(|p| p.exists()) {
+        return Err(Error::Usage(format!(
+            "database `{}` already exists",
+            path.display()
+        )));
+    }
+    match opts.backend {
+        #[cfg(feature = "duckdb")]
+        BackendKind::DuckDB => {
+            let conn = match &opts.db {
+                Some(path) => duckdb::Connection::open(path),
+                None => duckdb::Connection::open_in_memory(),
+            }
+            .map_err(backend::Error::from)?;
+            run(conn, opts, out)
+        }
+        #[cfg(feature = "sqlite")]
+        BackendKind::Sqlite => {
+            let conn = match &opts.db {
+                Some(path) => rusqlite::Connection::open(path),
+                None => rusqlite::Connection::open_in_memory(),
+            }
+            .map_err(backend::Error::from)?;
+            run(conn, opts, out)
+        }
+    }
+}
+
+fn main() -> ExitCode {
+    let result = opts(std::env::args_os().skip(1)).and_then(|opts| match opts {
+        None => {
+            print!("{USAGE}");
+            Ok(())
+        }
+        Some(opts) => cli(&opts, &mut io::stdout().lock()),
+    });
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(Error::Parse(rendered)) => {
+            eprint!("{rendered}");
+            ExitCode::FAILURE
+        }
+        Err(e @ (Error::Args(_) | Error::Usage(_))) => {
+            eprintln!("error: {e}\nRun `duckalog --help` for usage.");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory containing `files`
+    fn dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("duckalog-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    fn duckalog(dir: &Path, args: &[&str]) -> Result<String, Error> {
+        let args = args
+            .iter()
+            .map(|a| a.replace("$DIR", &dir.display().to_string()).into());
+        let opts = opts(args)?.unwrap();
+        let mut out = Vec::new();
+        cli(&opts, &mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    const TC: &str = r"
+        path(X, Y) :- edge(X, Y).
+        path(X, Z) :- edge(X, Y), path(Y, Z).
+        ";
+
+    #[test]
+    fn test_cli_tc() {
+        let d = dir("tc", &[("tc.dl", TC), ("edge.facts", "a\tb\nb\tc\n")]);
+        assert_eq!(
+            "path(a, b).\npath(a, c).\npath(b, c).\n",
+            duckalog(&d, &["-F", "$DIR", "$DIR/tc.dl"]).unwrap()
+        );
+        assert_eq!(
+            "",
+            duckalog(&d, &["-F", "$DIR", "-D", "$DIR", "$DIR/tc.dl"]).unwrap()
+        );
+        assert_eq!(
+            "a,b\na,c\nb,c\n",
+            std::fs::read_to_string(d.join("path.csv")).unwrap()
+        );
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    #[test]
+    fn test_cli_typed_facts() {
+        let d = dir(
+            "typed",
+            &[
+                ("p.dl", "big(X) :- n(X), X > 10. yes() :- flag().\n"),
+                ("n.csv", "5\n11\n12\n"),
+                ("flag.csv", "\n"),
+            ],
+        );
+        assert_eq!(
+            "big(11).\nbig(12).\nyes().\n",
+            duckalog(&d, &["-F", "$DIR", "$DIR/p.dl"]).unwrap()
+        );
+        std::fs::write(d.join("n.csv"), "5\nfive\n").unwrap();
+        assert!(matches!(
+            duckalog(&d, &["-F", "$DIR", "$DIR/p.dl"]),
+            Err(Error::Value { line: 2, .. })
+        ));
+        std::fs::write(d.join("n.csv"), "5,6\n").unwrap();
+        assert!(matches!(
+            duckalog(&d, &["-F", "$DIR", "$DIR/p.dl"]),
+            Err(Error::Fields {
+                expected: 1,
+                found: 2,
+                ..
+            })
+        ));
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    #[test]
+    fn test_cli_directives() {
+        let prog = r#"
+            .decl edge(src: symbol, dst: symbol)
+            .input edge
+            .output edge(IO=stdout)
+            .decl n(x: number)
+            .input n(IO=file, filename="nums.csv", rfc4180=true, headers=true)
+            .decl path(src: symbol, dst: symbol)
+            .output path(headers=true)
+            .decl big(x: number)
+            .output big(IO=stdout)
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+            big(X) :- n(X), X > 10.
+            ignored(X) :- n(X).
+            "#;
+        let d = dir(
+            "directives",
+            &[
+                ("tc.dl", prog),
+                ("edge.facts", "a\tb\nb\tc\n"),
+                ("nums.csv", "x\n5\n11\n"),
+                // Not read, `edge` has an `.input` directive
+                ("edge.csv", "x,y\n"),
+            ],
+        );
+        std::fs::create_dir(d.join("out")).unwrap();
+        assert_eq!(
+            "big(11).\nedge(a, b).\nedge(b, c).\n",
+            duckalog(&d, &["-F", "$DIR", "-D", "$DIR/out", "$DIR/tc.dl"]).unwrap()
+        );
+        assert_eq!(
+            "src\tdst\na\tb\na\tc\nb\tc\n",
+            std::fs::read_to_string(d.join("out/path.csv")).unwrap()
+        );
+        assert_eq!(
+            "big(11).\nedge(a, b).\nedge(b, c).\npath(a, b).\npath(a, c).\npath(b, c).\n",
+            duckalog(&d, &["-F", "$DIR", "-D", "-", "$DIR/tc.dl"]).unwrap()
+        );
+        std::fs::remove_file(d.join("edge.facts")).unwrap();
+        assert!(matches!(
+            duckalog(&d, &["-F", "$DIR", "-D", "-", "$DIR/tc.dl"]),
+            Err(Error::Csv { .. })
+        ));
+        std::fs::remove_dir_all(d).unwrap();
+    }
+}
@@ -0,0 +1,271 @@
+//! Interactive evaluation: add rules and facts, run them, query the results.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+use duckalog::ast::{Ast, Atom, Const, Rule};
+use duckalog::backend::Backend;
+use duckalog::eval::Eval;
+use duckalog::mir::Mir;
+use duckalog::parse;
+
+const HELP: &str = "\
+Enter rules and facts to add them to the program, e.g., `edge(a, b).` or
+`path(X, Y) :- edge(X, Y).`, and queries to print the matching facts, e.g.,
+`?- path(a, X).`. Clauses may span several lines, up to the final `.`.
+
+Commands:
+  .run          Evaluate the program to a fixpoint
+  .rels         List the relations, with their numbers of facts
+  .rules        List the rules
+  .sql REL      Print the SQL queries for the rules that define REL
+  .help         Print this message
+  .quit         Exit
+";
+
+fn render(e: parse::Error, src: &str) -> String {
+    e.diagnostic().render("<input>", src)
+}
+
+/// Add rules and facts to the program. Facts of known relations are added
+/// with [`Eval::insert_facts`], so that [`Eval::go`] only has to propagate
+/// them. Anything else replaces the program (see [`Eval::set_program`]).
+fn add<B: Backend>(eval: &mut Eval<B>, src: &str) -> Result<(), String> {
+    let rules = parse::parse_rules(src).map_err(|e| render(e, src))?;
+    let prog = eval.program();
+    let decls = prog.decls();
+    let known = rules.iter().all(|r| decls.contains_key(r.head().rel()));
+    let old = prog.rules().count();
+    let mut all: Vec<Rule> = prog.rules().cloned().collect();
+    all.extend(rules);
+    // Relations declared so far keep their columns
+    let ast = Ast::new_with_decls(decls.into_values().collect(), all)
+        .map_err(|e| render(e.into(), src))?;
+    let mir = Mir::new(ast).map_err(|e| format!("error: {e}\n"))?;
+    if known && mir.rules().count() == old {
+        for (rel, facts) in mir.facts() {
+            eval.insert_facts(rel, facts.map(Vec::as_slice))
+                .map_err(|e| format!("error: {e}\n"))?;
+        }
+        Ok(())
+    } else {
+        eval.set_program(mir).map_err(|e| format!("error: {e}\n"))
+    }
+}
+
+/// The facts that match the atom in `src`, e.g., `path(a, X).`
+fn query<B: Backend>(eval: &Eval<B>, src: &str) -> Result<Vec<Vec<Const>>, String> {
+    let mut rules = parse::parse_rules(src).map_err(|e| render(e, src))?;
+    let atom: Atom = match (rules.pop(), rules.is_empty()) {
+        (Some(rule), true) if rule.body().is_empty() => rule.head().clone(),
+        _ => return Err(String::from("error: expected a single atom\n")),
+    };
+    let mut facts: Vec<_> = eval
+        .query(&atom)
+        .map_err(|e| format!("error: {e}\n"))?
+        .into_iter()
+        .collect();
+    facts.sort();
+    Ok(facts)
+}
+
+fn command<B: Backend>(
+    eval: &mut Eval<B>,
+    cmd: &str,
+    arg: &str,
+    out: &mut impl Write,
+) -> io::Result<Result<(), String>> {
+    match cmd {
+        ".run" => match eval.go() {
+            Err(e) => return Ok(Err(format!("error: {e}\n"))),
+            Ok(iters) => {
+                for (rels, n) in iters {
+                    let names: Vec<String> = rels.iter().map(ToString::to_string).collect();
+                    writeln!(out, "{}: {n} iterations", names.join(", "))?;
+                }
+            }
+        },
+        ".rels" => {
+            let decls: BTreeMap<_, _> = eval.program().decls().into_iter().collect();
+            for (rel, decl) in decls {
+                match eval.relation(&rel) {
+                    Err(e) => return Ok(Err(format!("error: {e}\n"))),
+                    Ok(facts) => writeln!(out, "{rel}/{}: {}", decl.arity(), facts.len())?,
+                }
+            }
+        }
+        ".rules" => {
+            let mut rules: Vec<String> = eval.program().rules().map(Rule::to_string).collect();
+            rules.sort();
+            for rule in rules {
+                writeln!(out, "{rule}")?;
+            }
+        }
+        ".sql" => {
+            let mut rules: Vec<&Rule> = eval
+                .program()
+                .rules()
+                .filter(|r| r.head().rel().to_string() == arg)
+                .collect();
+            if rules.is_empty() {
+                return Ok(Err(format!("error: no rules define `{arg}`\n")));
+            }
+            rules.sort();
+            for rule in rules {
+                writeln!(out, "-- {rule}")?;
+                for query in eval.rule_sql(rule) {
+                    writeln!(out, "{}", query.sql)?;
+                    if !query.params.is_empty() {
+                        let params: Vec<String> =
+                            query.params.iter().map(Const::to_string).collect();
+                        writeln!(out, "-- with parameters {}", params.join(", "))?;
+                    }
+                }
+            }
+        }
+        ".help" => write!(out, "{HELP}")?,
+        _ => {
+            return Ok(Err(format!(
+                "error: unknown command `{cmd}`, try `.help`\n"
+            )))
+        }
+    }
+    Ok(Ok(()))
+}
+
+/// Read commands, clauses, and queries from `input` until it ends or the user
+/// quits. Errors are printed to `out`, rather than returned.
+pub(crate) fn repl<B: Backend>(
+    eval: &mut Eval<B>,
+    input: impl BufRead,
+    out: &mut impl Write,
+    prompt: bool,
+) -> io::Result<()> {
+    let mut buf = String::new();
+    let mut lines = input.lines();
+    loop {
+        if prompt {
+            write!(out, "{}", if buf.is_empty() { "> " } else { "| " })?;
+            out.flush()?;
+        }
+        let line = match lines.next() {
+            None => break,
+            Some(line) => line?,
+        };
+        let result = if buf.is_empty() && line.trim_start().starts_with('.') {
+            let (cmd, arg) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            if cmd == ".quit" {
+                break;
+            }
+            command(eval, cmd, arg.trim(), out)?
+        } else {
+            buf.push_str(&line);
+            buf.push('\n');
+            if !buf.trim_end().ends_with('.') {
+                continue;
+            }
+            let src = std::mem::take(&mut buf);
+            match src.trim_start().strip_prefix("?-") {
+                Some(q) => query(eval, q).and_then(|facts| {
+                    for fact in &facts {
+                        let terms: Vec<String> = fact.iter().map(Const::to_string).collect();
+                        writeln!(out, "{}", terms.join(", ")).map_err(|e| e.to_string())?;
+                    }
+                    writeln!(out, "{} facts", facts.len()).map_err(|e| e.to_string())
+                }),
+                None => add(eval, &src),
+            }
+        };
+        if let Err(msg) = result {
+            write!(out, "{msg}")?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(all(feature = "duckdb", not(feature = "sqlite")))]
+    use duckdb::Connection;
+    #[cfg(feature = "sqlite")]
+    use rusqlite::Connection;
+
+    use super::*;
+
+    fn session(input: &str) -> String {
+        let mir = Mir::new(parse::parse("").unwrap()).unwrap();
+        let mut eval = Eval::new(Connection::open_in_memory().unwrap(), mir).unwrap();
+        let mut out = Vec::new();
+        repl(&mut eval, input.as_bytes(), &mut out, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_repl() {
+        let out = session(
+            r"edge(a, b). edge(b, c).
+path(X, Y) :- edge(X, Y).
+path(X, Z) :-
+  edge(X, Y), path(Y, Z).
+?- path(a, X).
+.run
+.rels
+edge(c, d).
+.run
+?- path(X, d).
+.quit
+?- path(a, X).
+",
+        );
+        assert_eq!(
+            "a, b
+a, c
+2 facts
+path: 3 iterations
+edge/2: 2
+path/2: 3
+path: 4 iterations
+a, d
+b, d
+c, d
+3 facts
+",
+            out
+        );
+    }
+
+    #[test]
+    fn test_repl_errors() {
+        let out = session(
+            r"?- path(a, X).
+edge(a, b).
+edge(a).
+?- edge(a).
+.sql edge
+.nope
+",
+        );
+        let lines: Vec<&str> = out.lines().filter(|l| l.starts_with("error")).collect();
+        assert_eq!(
+            vec![
+                "error: unknown relation `path`",
+                "error: relation `edge` used with multiple arities: `2`, `1`",
+                "error: relation `edge` has arity 2",
+                "error: no rules define `edge`",
+                "error: unknown command `.nope`, try `.help`",
+            ],
+            lines
+        );
+    }
+
+    #[test]
+    fn test_repl_sql() {
+        let out = session(
+            r"path(X, Y) :- edge(X, Y), X != a.
+.sql path
+",
+        );
+        assert!(out.starts_with("-- path(X, Y) :- edge(X, Y), X != a.\nINSERT INTO"));
+        assert!(out.contains("-- with parameters a"));
+    }
+}
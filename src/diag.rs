@@ -0,0 +1,159 @@
+//! Rendering errors as annotated source snippets.
+
+use std::fmt::Write;
+
+use crate::span::Span;
+
+/// A message attached to a span of source text.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: String) -> Self {
+        Self { span, message }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Diagnostic {
+    pub message: String,
+    /// Where the error happened, if known
+    pub primary: Option<Label>,
+    /// Further locations that explain the error, e.g., a conflicting use
+    pub notes: Vec<Label>,
+}
+
+impl Diagnostic {
+    pub fn new(message: String) -> Self {
+        Self {
+            message,
+            primary: None,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_primary(mut self, span: Option<Span>, message: String) -> Self {
+        self.primary = span.map(|s| Label::new(s, message));
+        self
+    }
+
+    pub fn with_note(mut self, span: Option<Span>, message: String) -> Self {
+        if let Some(s) = span {
+            self.notes.push(Label::new(s, message));
+        }
+        self
+    }
+
+    /// Render as text, in the style of `rustc`:
+    ///
+    /// ```text
+    /// error: relation `r` used with multiple arities: `1`, `2`
+    ///  --> prog.dl:2:1
+    ///   |
+    /// 2 | r(a, b).
+    ///   | ^^^^^^^ used with arity 2
+    ///   |
+    /// note: first used with arity 1
+    ///  --> prog.dl:1:1
+    ///   |
+    /// 1 | r(a).
+    ///   | ^^^^
+    /// ```
+    ///
+    /// `src` should be the text that the spans point into, `file` is only
+    /// used for display.
+    pub fn render(&self, file: &str, src: &str) -> String {
+        let lines: Vec<&str> = src.lines().collect();
+        let width = self
+            .primary
+            .iter()
+            .chain(self.notes.iter())
+            .map(|l| l.span.start.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let mut out = format!("error: {}\n", self.message);
+        if let Some(label) = &self.primary {
+            snippet(&mut out, file, &lines, width, label.span, &label.message);
+        }
+        for note in &self.notes {
+            if self.primary.is_some() {
+                let _ = writeln!(out, "{:width$} |", "");
+            }
+            let _ = writeln!(out, "note: {}", note.message);
+            snippet(&mut out, file, &lines, width, note.span, "");
+        }
+        out
+    }
+}
+
+fn snippet(out: &mut String, file: &str, lines: &[&str], width: usize, span: Span, msg: &str) {
+    let _ = writeln!(out, "{:width$}--> {file}:{}", "", span.start);
+    let line = match lines.get(span.start.line.wrapping_sub(1)) {
+        None => return,
+        Some(l) => l,
+    };
+    let _ = writeln!(out, "{:width$} |", "");
+    let _ = writeln!(out, "{:>width$} | {line}", span.start.line);
+    let start = span.start.column.saturating_sub(1);
+    let end = if span.end.line == span.start.line {
+        span.end.column.saturating_sub(1)
+    } else {
+        line.chars().count()
+    };
+    let carets = "^".repeat(end.saturating_sub(start).max(1));
+    let _ = write!(out, "{:width$} | {:start$}{carets}", "", "");
+    if msg.is_empty() {
+        let _ = writeln!(out);
+    } else {
+        let _ = writeln!(out, " {msg}");
+    }
+}
+
+// ------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::span::Pos;
+
+    fn span(line: usize, start: usize, end: usize) -> Span {
+        Span::new(
+            Pos {
+                line,
+                column: start,
+            },
+            Pos { line, column: end },
+        )
+    }
+
+    #[test]
+    fn render_primary_and_note() {
+        let d = Diagnostic::new(String::from("bad"))
+            .with_primary(Some(span(2, 1, 8)), String::from("here"))
+            .with_note(Some(span(1, 1, 5)), String::from("first"));
+        assert_eq!(
+            r"error: bad
+ --> p.dl:2:1
+  |
+2 | r(a, b).
+  | ^^^^^^^ here
+  |
+note: first
+ --> p.dl:1:1
+  |
+1 | r(a).
+  | ^^^^
+",
+            d.render("p.dl", "r(a).\nr(a, b).\n")
+        );
+    }
+
+    #[test]
+    fn render_no_span() {
+        let d = Diagnostic::new(String::from("bad")).with_primary(None, String::from("here"));
+        assert_eq!("error: bad\n", d.render("p.dl", ""));
+    }
+}
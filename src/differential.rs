@@ -0,0 +1,980 @@
+//! Differential testing: random programs must have the same model on every
+//! available backend, in [`Interp`], and by the naive definition of the least
+//! fixpoint. Likewise, [`Eval::query`], and [`Eval::go`] after
+//! [`Eval::insert_facts`] or [`Eval::retract_facts`], must agree with the
+//! naive model.
+//!
+//! Programs are generated from a [`ProgSpec`], which is converted to a
+//! well-formed [`Ast`] (range-restricted, stratified, well-typed) however it
+//! gets shrunk. Failing programs are printed in Datalog syntax. Floats and
+//! symbols are checked on a fixed program instead (see [`consts`]), where the
+//! backends must agree with [`Interp`].
+
+use std::cmp::Ordering;
+
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::ast::{
+    AggFunc, Aggregate, ArithOp, Ast, Atom, CmpOp, Column, Comparison, Const, Expr, Float, Literal,
+    Rel, RelDecl, Rule, Term, Type, Var,
+};
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+use crate::eval::Eval;
+use crate::interp::Interp;
+use crate::mir::Mir;
+
+/// Some relation names are prefixes of others, or look like the names of the
+/// tables, indices and sequences of others. All columns have type `int`.
+const NAMES: [&str; 4] = ["r", "r_1", "r_idx", "r_1_idx"];
+const RELS: usize = NAMES.len();
+const MAX_ARITY: usize = 2;
+const VARS: [&str; 3] = ["X", "Y", "Z"];
+/// The result of an aggregate in a rule body
+const RESULT: &str = "A";
+/// Constants are `0`, `1`, ...
+const CONSTS: i64 = 3;
+
+const AGG_FUNCS: [AggFunc; 4] = [AggFunc::Count, AggFunc::Sum, AggFunc::Min, AggFunc::Max];
+const ARITH_OPS: [ArithOp; 4] = [ArithOp::Add, ArithOp::Sub, ArithOp::Mul, ArithOp::Div];
+const CMP_OPS: [CmpOp; 6] = [
+    CmpOp::Eq,
+    CmpOp::Ne,
+    CmpOp::Lt,
+    CmpOp::Le,
+    CmpOp::Gt,
+    CmpOp::Ge,
+];
+
+#[derive(Clone, Debug)]
+enum TermSpec {
+    Var(usize),
+    Const(i64),
+    /// `_` in body atoms, `0` elsewhere
+    Wildcard,
+}
+
+#[derive(Clone, Debug)]
+struct AtomSpec {
+    rel: usize,
+    terms: Vec<TermSpec>,
+}
+
+#[derive(Clone, Debug)]
+struct CmpSpec {
+    op: usize,
+    lhs: TermSpec,
+    /// `lhs <op> term`
+    arith: Option<(usize, TermSpec)>,
+    rhs: TermSpec,
+}
+
+/// `A = <func> <var> : <atom>`, where `A` is [`RESULT`]
+#[derive(Clone, Debug)]
+struct BodyAggSpec {
+    func: usize,
+    /// The aggregated variable, if it's in the atom
+    var: usize,
+    atom: AtomSpec,
+    /// The head column that becomes `A`
+    col: usize,
+    /// `A <op> <const>`
+    filter: Option<(usize, i64)>,
+}
+
+#[derive(Clone, Debug)]
+struct RuleSpec {
+    head: AtomSpec,
+    /// Aggregate function and head column
+    agg: Option<(usize, usize)>,
+    positive: Vec<AtomSpec>,
+    negative: Vec<AtomSpec>,
+    cmps: Vec<CmpSpec>,
+    body_agg: Option<BodyAggSpec>,
+}
+
+#[derive(Clone)]
+struct ProgSpec {
+    arities: Vec<usize>,
+    rules: Vec<RuleSpec>,
+    facts: Vec<(usize, Vec<i64>)>,
+}
+
+/// Shows the program, so that shrunk counterexamples are readable
+impl std::fmt::Debug for ProgSpec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "\n{}", self.ast())
+    }
+}
+
+fn term() -> impl Strategy<Value = TermSpec> {
+    prop_oneof![
+        3 => (0..VARS.len()).prop_map(TermSpec::Var),
+        2 => (0..CONSTS).prop_map(TermSpec::Const),
+        1 => Just(TermSpec::Wildcard),
+    ]
+}
+
+fn atom() -> impl Strategy<Value = AtomSpec> {
+    (0..RELS, vec(term(), 0..=MAX_ARITY)).prop_map(|(rel, terms)| AtomSpec { rel, terms })
+}
+
+fn cmp() -> impl Strategy<Value = CmpSpec> {
+    (
+        0..CMP_OPS.len(),
+        term(),
+        proptest::option::weighted(0.3, (0..ARITH_OPS.len(), term())),
+        term(),
+    )
+        .prop_map(|(op, lhs, arith, rhs)| CmpSpec {
+            op,
+            lhs,
+            arith,
+            rhs,
+        })
+}
+
+fn body_agg() -> impl Strategy<Value = BodyAggSpec> {
+    (
+        0..AGG_FUNCS.len(),
+        0..VARS.len(),
+        atom(),
+        0..MAX_ARITY,
+        proptest::option::weighted(0.5, (0..CMP_OPS.len(), 0..CONSTS)),
+    )
+        .prop_map(|(func, var, atom, col, filter)| BodyAggSpec {
+            func,
+            var,
+            atom,
+            col,
+            filter,
+        })
+}
+
+fn rule() -> impl Strategy<Value = RuleSpec> {
+    (
+        atom(),
+        proptest::option::weighted(0.2, (0..AGG_FUNCS.len(), 0..MAX_ARITY)),
+        vec(atom(), 1..=3),
+        vec(atom(), 0..=1),
+        vec(cmp(), 0..=1),
+        proptest::option::weighted(0.2, body_agg()),
+    )
+        .prop_map(|(head, agg, positive, negative, cmps, body_agg)| RuleSpec {
+            head,
+            agg,
+            positive,
+            negative,
+            cmps,
+            body_agg,
+        })
+}
+
+fn facts() -> impl Strategy<Value = Vec<(usize, Vec<i64>)>> {
+    vec((0..RELS, vec(0..CONSTS, MAX_ARITY)), 0..=8)
+}
+
+fn prog() -> impl Strategy<Value = ProgSpec> {
+    (vec(0..=MAX_ARITY, RELS), vec(rule(), 0..=4), facts()).prop_map(|(arities, rules, facts)| {
+        ProgSpec {
+            arities,
+            rules,
+            facts,
+        }
+    })
+}
+
+fn rel(i: usize) -> Rel {
+    Rel::new(String::from(NAMES[i]))
+}
+
+fn var(i: usize) -> Var {
+    Var::new(String::from(VARS[i])).unwrap()
+}
+
+impl ProgSpec {
+    /// Variables not in `bound` become constants, to keep rules
+    /// range-restricted. Wildcards become fresh variables, numbered by
+    /// `wildcards`, in body atoms, and constants elsewhere.
+    fn term(t: &TermSpec, bound: Option<&HashSet<usize>>, wildcards: Option<&mut usize>) -> Term {
+        match t {
+            TermSpec::Var(v) if bound.is_none_or(|b| b.contains(v)) => Term::Var(var(*v)),
+            TermSpec::Var(v) => Term::Const(Const::Int(*v as i64)),
+            TermSpec::Const(c) => Term::Const(Const::Int(*c)),
+            TermSpec::Wildcard => match wildcards {
+                Some(n) => {
+                    *n += 1;
+                    Term::Var(Var::new_unchecked(format!("_{n}")))
+                }
+                None => Term::Const(Const::Int(0)),
+            },
+        }
+    }
+
+    fn expr(t: &TermSpec, bound: &HashSet<usize>) -> Expr {
+        match Self::term(t, Some(bound), None) {
+            Term::Var(v) => Expr::Var(v),
+            Term::Const(c) => Expr::Const(c),
+            Term::Aggregate(_) => unreachable!(),
+        }
+    }
+
+    fn atom(
+        &self,
+        a: &AtomSpec,
+        rel_idx: usize,
+        bound: Option<&HashSet<usize>>,
+        mut wildcards: Option<&mut usize>,
+    ) -> Atom {
+        let terms = (0..self.arities[rel_idx])
+            .map(|i| {
+                let t = a.terms.get(i).unwrap_or(&TermSpec::Const(0));
+                Self::term(t, bound, wildcards.as_deref_mut())
+            })
+            .collect();
+        Atom::new(rel(rel_idx), terms)
+    }
+
+    /// The lowering of `A = <func> <var> : <atom>` by the parser: an atom of
+    /// a new relation, grouped by the variables of the atom in `bound`, and
+    /// the rule that defines it. `None` if the atom has no variables.
+    fn body_agg(
+        &self,
+        a: &BodyAggSpec,
+        rel_idx: usize,
+        bound: &HashSet<usize>,
+        wildcards: &mut usize,
+    ) -> Option<(Atom, Rule)> {
+        let inner = self.atom(&a.atom, rel_idx, None, Some(wildcards));
+        let vars: Vec<&Var> = inner.terms.iter().filter_map(Term::var).collect();
+        let func = AGG_FUNCS[a.func];
+        let agg_var = match func {
+            // Like the parser, count the first variable
+            AggFunc::Count => vars.first()?,
+            _ => vars.iter().find(|v| **v == &var(a.var)).or(vars.first())?,
+        };
+        let groups: Vec<Term> = (0..VARS.len())
+            .filter(|v| bound.contains(v) && vars.contains(&&var(*v)))
+            .map(|v| Term::Var(var(v)))
+            .collect();
+        let mut terms = groups.clone();
+        terms.push(Term::Aggregate(Aggregate::new(func, (*agg_var).clone())));
+        let def = Rule::new(
+            Atom::new(Rel::new(String::new()), terms.clone()),
+            vec![inner.clone()],
+        );
+        let aux = Rel::aggregate(func, fxhash::hash64(&def));
+        let def = Rule::new(Atom::new(aux.clone(), terms), vec![inner]);
+        let mut terms = groups;
+        terms.push(Term::Var(Var::new(String::from(RESULT)).unwrap()));
+        Some((Atom::new(aux, terms), def))
+    }
+
+    /// Relation `NAMES[i]` only negates or aggregates over `NAMES[j]` for
+    /// `j < i`, and only uses `NAMES[j]` for `j <= i`, so the program is
+    /// stratified. Aggregates in the body add a rule, which comes second.
+    fn rule(&self, r: &RuleSpec) -> Vec<Rule> {
+        let h = r.head.rel % RELS;
+        let agg = r.agg.filter(|_| h > 0 && self.arities[h] > 0);
+        let body_rels = if agg.is_some() { h } else { h + 1 };
+        let mut wildcards = 0;
+        let positive: Vec<Atom> = r
+            .positive
+            .iter()
+            .map(|a| self.atom(a, a.rel % body_rels, None, Some(&mut wildcards)))
+            .collect();
+        let bound: HashSet<usize> = r
+            .positive
+            .iter()
+            .zip(&positive)
+            .flat_map(|(spec, a)| spec.terms.iter().take(a.terms.len()))
+            .filter_map(|t| match t {
+                TermSpec::Var(v) => Some(*v),
+                TermSpec::Const(_) | TermSpec::Wildcard => None,
+            })
+            .collect();
+
+        let mut body: Vec<Literal> = positive.into_iter().map(Literal::Atom).collect();
+        if h > 0 {
+            for a in &r.negative {
+                let atom = self.atom(a, a.rel % h, Some(&bound), Some(&mut wildcards));
+                body.push(Literal::Not(atom));
+            }
+        }
+        let body_agg = r.body_agg.as_ref().filter(|_| h > 0).and_then(|a| {
+            let (atom, def) = self.body_agg(a, a.atom.rel % h, &bound, &mut wildcards)?;
+            Some((a, atom, def))
+        });
+        let mut defs = Vec::new();
+        if let Some((a, atom, def)) = body_agg.clone() {
+            body.push(Literal::Atom(atom));
+            if let Some((op, c)) = a.filter {
+                let result = Expr::Var(Var::new(String::from(RESULT)).unwrap());
+                body.push(Literal::Cmp(Comparison::new(
+                    CMP_OPS[op],
+                    result,
+                    Expr::Const(Const::Int(c)),
+                )));
+            }
+            defs.push(def);
+        }
+        for c in &r.cmps {
+            let mut lhs = Self::expr(&c.lhs, &bound);
+            if let Some((op, t)) = &c.arith {
+                lhs = Expr::binary(ARITH_OPS[*op], lhs, Self::expr(t, &bound));
+            }
+            let rhs = Self::expr(&c.rhs, &bound);
+            body.push(Literal::Cmp(Comparison::new(CMP_OPS[c.op], lhs, rhs)));
+        }
+
+        let mut head = self.atom(&r.head, h, Some(&bound), None);
+        if let Some((a, _, _)) = body_agg.filter(|_| !head.terms.is_empty()) {
+            let col = a.col % head.terms.len();
+            head.terms[col] = Term::Var(Var::new(String::from(RESULT)).unwrap());
+        }
+        if let (Some((func, col)), Some(v)) = (agg, bound.iter().min()) {
+            let col = col % head.terms.len();
+            let v = match &r.head.terms.get(col) {
+                Some(TermSpec::Var(w)) if bound.contains(w) => *w,
+                _ => *v,
+            };
+            head.terms[col] = Term::Aggregate(Aggregate::new(AGG_FUNCS[func], var(v)));
+        }
+        defs.insert(0, Rule::new_with_literals(head, body));
+        defs
+    }
+
+    fn ast(&self) -> Ast {
+        let decls = (0..RELS)
+            .map(|i| {
+                let cols = (0..self.arities[i])
+                    .map(|j| Column::new(format!("x{j}"), Type::Int))
+                    .collect();
+                RelDecl::new(rel(i), cols)
+            })
+            .collect();
+        let mut rules: Vec<Rule> = self.rules.iter().flat_map(|r| self.rule(r)).collect();
+        for (r, consts) in &self.facts {
+            let (rel, consts) = self.fact(*r, consts);
+            let terms = consts.into_iter().map(Term::Const).collect();
+            rules.push(Rule::new(Atom::new(rel, terms), Vec::new()));
+        }
+        Ast::new_with_decls(decls, rules).expect("generated programs are valid")
+    }
+
+    fn fact(&self, r: usize, consts: &[i64]) -> (Rel, Vec<Const>) {
+        let r = r % RELS;
+        let consts = consts
+            .iter()
+            .take(self.arities[r])
+            .map(|c| Const::Int(*c))
+            .collect();
+        (rel(r), consts)
+    }
+}
+
+type Model = HashMap<Rel, HashSet<Vec<Const>>>;
+type Env = HashMap<Var, Const>;
+
+/// All the ways to extend `env` so that the positive `atoms` are in `db`
+fn matches(db: &Model, atoms: &[&Atom], env: Env, out: &mut Vec<Env>) {
+    let Some((atom, rest)) = atoms.split_first() else {
+        out.push(env);
+        return;
+    };
+    'tuples: for tuple in &db[&atom.rel] {
+        let mut env = env.clone();
+        for (term, c) in atom.terms.iter().zip(tuple) {
+            match term {
+                Term::Const(d) if c != d => continue 'tuples,
+                Term::Var(v) => {
+                    if env.get(v).is_some_and(|d| c != d) {
+                        continue 'tuples;
+                    }
+                    env.insert(v.clone(), c.clone());
+                }
+                _ => (),
+            }
+        }
+        matches(db, rest, env, out);
+    }
+}
+
+fn value(env: &Env, e: &Expr) -> Option<i64> {
+    match e {
+        Expr::Const(Const::Int(i)) => Some(*i),
+        Expr::Var(v) => match env[v] {
+            Const::Int(i) => Some(i),
+            _ => unreachable!("generated programs only use `int`"),
+        },
+        Expr::Binary(op, l, r) => {
+            let (l, r) = (value(env, l)?, value(env, r)?);
+            match op {
+                ArithOp::Add => l.checked_add(r),
+                ArithOp::Sub => l.checked_sub(r),
+                ArithOp::Mul => l.checked_mul(r),
+                ArithOp::Div => l.checked_div(r),
+            }
+        }
+        _ => unreachable!("generated programs only use `int`"),
+    }
+}
+
+fn holds(env: &Env, c: &Comparison) -> bool {
+    let (Some(l), Some(r)) = (value(env, c.lhs()), value(env, c.rhs())) else {
+        return false;
+    };
+    let ord = l.cmp(&r);
+    match c.op() {
+        CmpOp::Eq => ord == Ordering::Equal,
+        CmpOp::Ne => ord != Ordering::Equal,
+        CmpOp::Lt => ord == Ordering::Less,
+        CmpOp::Le => ord != Ordering::Greater,
+        CmpOp::Gt => ord == Ordering::Greater,
+        CmpOp::Ge => ord != Ordering::Less,
+    }
+}
+
+fn ground(env: &Env, atom: &Atom) -> Vec<Const> {
+    atom.terms
+        .iter()
+        .map(|t| match t {
+            Term::Const(c) => c.clone(),
+            Term::Var(v) => env[v].clone(),
+            Term::Aggregate(_) => unreachable!(),
+        })
+        .collect()
+}
+
+/// The immediate consequences of a rule
+fn consequences(db: &Model, rule: &Rule) -> HashSet<Vec<Const>> {
+    let (totals, positive): (Vec<&Atom>, Vec<&Atom>) =
+        rule.positive().partition(|atom| atom.rel.is_total());
+    let mut envs = Vec::new();
+    matches(db, &positive, Env::default(), &mut envs);
+    // `count` and `sum` of nothing are 0
+    for atom in totals {
+        let mut out = Vec::new();
+        for env in envs {
+            let n = out.len();
+            matches(db, &[atom], env.clone(), &mut out);
+            if out.len() == n {
+                let mut env = env;
+                match atom.terms.last() {
+                    Some(Term::Var(v)) => {
+                        env.insert(v.clone(), Const::Int(0));
+                    }
+                    Some(Term::Const(c)) if *c != Const::Int(0) => continue,
+                    _ => (),
+                }
+                out.push(env);
+            }
+        }
+        envs = out;
+    }
+    envs.retain(|env| {
+        rule.body.iter().all(|lit| match lit {
+            Literal::Atom(_) => true,
+            Literal::Not(a) => {
+                // Wildcards match anything
+                let mut out = Vec::new();
+                matches(db, &[a], env.clone(), &mut out);
+                out.is_empty()
+            }
+            Literal::Cmp(c) => holds(env, c),
+        })
+    });
+    if !rule.is_aggregate() {
+        return envs.iter().map(|env| ground(env, &rule.head)).collect();
+    }
+
+    let envs: HashSet<Vec<(Var, Const)>> = envs
+        .into_iter()
+        .map(|env| {
+            let mut env: Vec<_> = env.into_iter().collect();
+            env.sort();
+            env
+        })
+        .collect();
+    let mut groups: HashMap<Vec<Const>, Vec<Env>> = HashMap::default();
+    for env in envs {
+        let env: Env = env.into_iter().collect();
+        let key = rule
+            .head
+            .terms
+            .iter()
+            .filter_map(|t| match t {
+                Term::Var(v) => Some(env[v].clone()),
+                _ => None,
+            })
+            .collect();
+        groups.entry(key).or_default().push(env);
+    }
+    let mut out = HashSet::default();
+    for group in groups.values() {
+        let tuple = rule
+            .head
+            .terms
+            .iter()
+            .map(|t| match t {
+                Term::Const(c) => c.clone(),
+                Term::Var(v) => group[0][v].clone(),
+                Term::Aggregate(a) => {
+                    let vals = group.iter().map(|env| match env[a.var()] {
+                        Const::Int(i) => i,
+                        _ => unreachable!("generated programs only use `int`"),
+                    });
+                    Const::Int(match a.func() {
+                        AggFunc::Count => group.len() as i64,
+                        AggFunc::Sum => vals.sum(),
+                        AggFunc::Min => vals.min().unwrap(),
+                        AggFunc::Max => vals.max().unwrap(),
+                    })
+                }
+            })
+            .collect();
+        out.insert(tuple);
+    }
+    out
+}
+
+/// Apply every rule of each stratum to everything known so far, until
+/// nothing changes
+fn naive(mir: &Mir) -> Model {
+    let mut db: Model = mir
+        .decls()
+        .into_keys()
+        .map(|rel| (rel, HashSet::default()))
+        .collect();
+    for (rel, facts) in mir.facts() {
+        db.get_mut(rel).unwrap().extend(facts.cloned());
+    }
+    for stratum in mir.strata() {
+        loop {
+            let mut changed = false;
+            for rule in &stratum {
+                for tuple in consequences(&db, rule) {
+                    changed |= db.get_mut(&rule.head.rel).unwrap().insert(tuple);
+                }
+            }
+            if !changed {
+                break;
+            }
+        }
+    }
+    db
+}
+
+/// The facts of `db` that match `query`
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+fn answers(db: &Model, query: &Atom) -> HashSet<Vec<Const>> {
+    let mut envs = Vec::new();
+    matches(db, &[query], Env::default(), &mut envs);
+    envs.iter().map(|env| ground(env, query)).collect()
+}
+
+#[cfg(feature = "duckdb")]
+fn duckdb(mir: Mir) -> Model {
+    let eval = Eval::new(::duckdb::Connection::open_in_memory().unwrap(), mir).unwrap();
+    eval.go().unwrap();
+    eval.model().unwrap()
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite(mir: Mir) -> Model {
+    let eval = Eval::new(::rusqlite::Connection::open_in_memory().unwrap(), mir).unwrap();
+    eval.go().unwrap();
+    eval.model().unwrap()
+}
+
+/// Evaluate, then add each batch of facts and evaluate again
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+fn incremental<B: crate::backend::Backend>(
+    backend: B,
+    mir: Mir,
+    batches: &[Vec<(Rel, Vec<Const>)>],
+) -> Model {
+    let eval = Eval::new(backend, mir).unwrap();
+    eval.go().unwrap();
+    for batch in batches {
+        for (rel, fact) in batch {
+            eval.insert_facts(rel, [fact.as_slice()]).unwrap();
+        }
+        eval.go().unwrap();
+    }
+    eval.model().unwrap()
+}
+
+/// Evaluate, retract a batch of facts, add a batch of facts and evaluate
+/// again, then retract another batch, returning the model after each
+/// retraction. `mir` has the facts of the first batch of additions.
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+fn retract<B: crate::backend::Backend>(
+    backend: B,
+    mir: Mir,
+    retract1: &[(Rel, Vec<Const>)],
+    insert: &[(Rel, Vec<Const>)],
+    retract2: &[(Rel, Vec<Const>)],
+) -> (Model, Model) {
+    let eval = Eval::new(backend, mir).unwrap();
+    eval.go().unwrap();
+    for (rel, fact) in retract1 {
+        eval.retract_facts(rel, [fact.as_slice()]).unwrap();
+    }
+    let m1 = eval.model().unwrap();
+    for (rel, fact) in insert {
+        eval.insert_facts(rel, [fact.as_slice()]).unwrap();
+    }
+    eval.go().unwrap();
+    for (rel, fact) in retract2 {
+        eval.retract_facts(rel, [fact.as_slice()]).unwrap();
+    }
+    (m1, eval.model().unwrap())
+}
+
+/// `mir` with the given facts added and removed
+fn with_facts(mir: &Mir, add: &[(Rel, Vec<Const>)], remove: &[(Rel, Vec<Const>)]) -> Mir {
+    let mut facts: HashSet<(Rel, Vec<Const>)> = mir
+        .facts()
+        .flat_map(|(rel, facts)| facts.map(|f| (rel.clone(), f.clone())))
+        .collect();
+    facts.extend(add.iter().cloned());
+    for fact in remove {
+        facts.remove(fact);
+    }
+    let mut mir = mir.clone();
+    mir.clear_facts();
+    for (rel, fact) in facts {
+        mir.add_fact(&rel, fact);
+    }
+    mir
+}
+
+#[cfg(feature = "duckdb")]
+fn duckdb_query(mir: Mir, query: &Atom) -> HashSet<Vec<Const>> {
+    let eval = Eval::new(::duckdb::Connection::open_in_memory().unwrap(), mir).unwrap();
+    eval.query(query).unwrap()
+}
+
+#[cfg(feature = "sqlite")]
+fn sqlite_query(mir: Mir, query: &Atom) -> HashSet<Vec<Const>> {
+    let eval = Eval::new(::rusqlite::Connection::open_in_memory().unwrap(), mir).unwrap();
+    eval.query(query).unwrap()
+}
+
+/// Evaluate `mir`, which fails, then retract `bad`, add `facts`, and evaluate
+/// again. Returns whether the first evaluation failed, and the final model.
+#[cfg(any(feature = "duckdb", feature = "sqlite"))]
+fn after_error<B: crate::backend::Backend>(
+    backend: B,
+    mir: Mir,
+    bad: &(Rel, Vec<Const>),
+    facts: &[(Rel, Vec<Const>)],
+) -> (bool, Model) {
+    let eval = Eval::new(backend, mir).unwrap();
+    let failed = eval.go().is_err();
+    eval.retract_facts(&bad.0, [bad.1.as_slice()]).unwrap();
+    for (rel, fact) in facts {
+        eval.insert_facts(rel, [fact.as_slice()]).unwrap();
+    }
+    eval.go().unwrap();
+    (failed, eval.model().unwrap())
+}
+
+fn float() -> impl Strategy<Value = f64> {
+    prop_oneof![
+        any::<f64>().prop_filter("finite", |x| x.is_finite()),
+        Just(-0.0),
+        Just(0.5),
+        Just(1e300),
+        Just(f64::MIN_POSITIVE),
+    ]
+}
+
+fn sym() -> impl Strategy<Value = String> {
+    prop_oneof![
+        "\\PC{0,6}",
+        Just(String::from("1.50")),
+        Just(String::from("'")),
+        Just(String::from("a\"b")),
+        Just(String::from("true")),
+    ]
+}
+
+/// A program over the floats `xs` and the symbols `ys`, which uses the first
+/// of each as constants in heads and comparisons
+fn consts(xs: &[f64], ys: &[String]) -> Mir {
+    let (f, s) = (Rel::new(String::from("f")), Rel::new(String::from("s")));
+    let decls = vec![
+        RelDecl::new(f.clone(), vec![Column::new(String::from("x"), Type::Float)]),
+        RelDecl::new(s.clone(), vec![Column::new(String::from("y"), Type::Sym)]),
+    ];
+    let x = Var::new(String::from("X")).unwrap();
+    let float = |x: f64| Const::Float(Float(x));
+    let c = float(xs.first().copied().unwrap_or(0.0));
+    let d = Const::Sym(ys.first().cloned().unwrap_or_default());
+    let rule = |head: &str, terms: Vec<Term>, body: Vec<Literal>| {
+        Rule::new_with_literals(Atom::new(Rel::new(String::from(head)), terms), body)
+    };
+    let fx = Literal::Atom(Atom::new(f.clone(), vec![Term::Var(x.clone())]));
+    let sx = Literal::Atom(Atom::new(s.clone(), vec![Term::Var(x.clone())]));
+    let cmp = |op, c: &Const| {
+        Literal::Cmp(Comparison::new(
+            op,
+            Expr::Var(x.clone()),
+            Expr::Const(c.clone()),
+        ))
+    };
+    let agg = |func| vec![Term::Aggregate(Aggregate::new(func, x.clone()))];
+    let mut rules = vec![
+        rule(
+            "fc",
+            vec![Term::Var(x.clone()), Term::Const(c.clone())],
+            vec![fx.clone()],
+        ),
+        rule(
+            "sc",
+            vec![Term::Var(x.clone()), Term::Const(d.clone())],
+            vec![sx.clone()],
+        ),
+        rule(
+            "flt",
+            vec![Term::Var(x.clone())],
+            vec![fx.clone(), cmp(CmpOp::Lt, &c)],
+        ),
+        rule(
+            "sne",
+            vec![Term::Var(x.clone())],
+            vec![sx.clone(), cmp(CmpOp::Ne, &d)],
+        ),
+        rule("fmin", agg(AggFunc::Min), vec![fx.clone()]),
+        rule("fmax", agg(AggFunc::Max), vec![fx]),
+        rule("smax", agg(AggFunc::Max), vec![sx.clone()]),
+        rule("scount", agg(AggFunc::Count), vec![sx]),
+    ];
+    for x in xs {
+        rules.push(Rule::new(
+            Atom::new(f.clone(), vec![Term::Const(float(*x))]),
+            Vec::new(),
+        ));
+    }
+    for y in ys {
+        let y = Term::Const(Const::Sym(y.clone()));
+        rules.push(Rule::new(Atom::new(s.clone(), vec![y]), Vec::new()));
+    }
+    Mir::new(Ast::new_with_decls(decls, rules).unwrap()).unwrap()
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn same_model(spec in prog()) {
+        let ast = spec.ast();
+        let mir = Mir::new(ast.clone()).unwrap();
+        let expected = naive(&mir);
+
+        let mut interp = Interp::new(mir.clone());
+        interp.go();
+        prop_assert_eq!(&expected, &interp.model(), "in-memory evaluator, on:\n{}", ast);
+        #[cfg(feature = "duckdb")]
+        prop_assert_eq!(&expected, &duckdb(mir.clone()), "DuckDB, on:\n{}", ast);
+        #[cfg(feature = "sqlite")]
+        prop_assert_eq!(&expected, &sqlite(mir), "SQLite, on:\n{}", ast);
+    }
+
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn same_answers(spec in prog(), query in atom()) {
+        let ast = spec.ast();
+        let mir = Mir::new(ast.clone()).unwrap();
+        let query = spec.atom(&query, query.rel % RELS, None, Some(&mut 0));
+        let expected = answers(&naive(&mir), &query);
+
+        #[cfg(feature = "duckdb")]
+        prop_assert_eq!(
+            &expected,
+            &duckdb_query(mir.clone(), &query),
+            "DuckDB, {} on:\n{}",
+            query,
+            ast
+        );
+        #[cfg(feature = "sqlite")]
+        prop_assert_eq!(
+            &expected,
+            &sqlite_query(mir, &query),
+            "SQLite, {} on:\n{}",
+            query,
+            ast
+        );
+    }
+
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn same_model_incremental(spec in prog(), batch1 in facts(), batch2 in facts()) {
+        let ast = spec.ast();
+        let mir = Mir::new(ast.clone()).unwrap();
+        let batches: Vec<Vec<(Rel, Vec<Const>)>> = [batch1, batch2]
+            .iter()
+            .map(|b| b.iter().map(|(r, consts)| spec.fact(*r, consts)).collect())
+            .collect();
+        let mut all = mir.clone();
+        for (rel, fact) in batches.iter().flatten() {
+            all.add_fact(rel, fact.clone());
+        }
+        let expected = naive(&all);
+
+        #[cfg(feature = "duckdb")]
+        prop_assert_eq!(
+            &expected,
+            &incremental(::duckdb::Connection::open_in_memory().unwrap(), mir.clone(), &batches),
+            "DuckDB, adding {:?} on:\n{}",
+            batches,
+            ast
+        );
+        #[cfg(feature = "sqlite")]
+        prop_assert_eq!(
+            &expected,
+            &incremental(::rusqlite::Connection::open_in_memory().unwrap(), mir, &batches),
+            "SQLite, adding {:?} on:\n{}",
+            batches,
+            ast
+        );
+    }
+
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn same_model_retract(
+        spec in prog(),
+        retract1 in facts(),
+        insert in facts(),
+        retract2 in facts(),
+    ) {
+        let ast = spec.ast();
+        let mir = Mir::new(ast.clone()).unwrap();
+        let facts = |b: Vec<(usize, Vec<i64>)>| -> Vec<(Rel, Vec<Const>)> {
+            b.iter().map(|(r, consts)| spec.fact(*r, consts)).collect()
+        };
+        let (retract1, insert, retract2) = (facts(retract1), facts(insert), facts(retract2));
+        let mir1 = with_facts(&mir, &[], &retract1);
+        let mir2 = with_facts(&mir1, &insert, &retract2);
+        let expected = (naive(&mir1), naive(&mir2));
+
+        #[cfg(feature = "duckdb")]
+        prop_assert_eq!(
+            &expected,
+            &retract(
+                ::duckdb::Connection::open_in_memory().unwrap(),
+                mir.clone(),
+                &retract1,
+                &insert,
+                &retract2
+            ),
+            "DuckDB, retracting {:?}, adding {:?}, retracting {:?} on:\n{}",
+            retract1,
+            insert,
+            retract2,
+            ast
+        );
+        #[cfg(feature = "sqlite")]
+        prop_assert_eq!(
+            &expected,
+            &retract(
+                ::rusqlite::Connection::open_in_memory().unwrap(),
+                mir,
+                &retract1,
+                &insert,
+                &retract2
+            ),
+            "SQLite, retracting {:?}, adding {:?}, retracting {:?} on:\n{}",
+            retract1,
+            insert,
+            retract2,
+            ast
+        );
+    }
+
+    /// Floats and symbols, as facts and as constants in rules
+    #[test]
+    fn same_model_consts(xs in vec(float(), 0..=4), ys in vec(sym(), 0..=4)) {
+        let mir = consts(&xs, &ys);
+        let mut interp = Interp::new(mir.clone());
+        interp.go();
+        let expected = interp.model();
+        #[cfg(feature = "duckdb")]
+        prop_assert_eq!(&expected, &duckdb(mir.clone()), "DuckDB, on {:?} and {:?}", xs, ys);
+        #[cfg(feature = "sqlite")]
+        prop_assert_eq!(&expected, &sqlite(mir), "SQLite, on {:?} and {:?}", xs, ys);
+    }
+
+    /// Evaluation that fails, because `sum` overflows, is started over after
+    /// the fact that caused it is retracted
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn same_model_after_error(spec in prog(), batch in facts()) {
+        let big = Rel::new(String::from("big"));
+        let x = Var::new(String::from("X")).unwrap();
+        let mut ast = spec.ast();
+        ast.decls.push(RelDecl::new(big.clone(), vec![Column::new(String::from("x"), Type::Int)]));
+        ast.rules.push(Rule::new(
+            Atom::new(
+                Rel::new(String::from("total")),
+                vec![Term::Aggregate(Aggregate::new(AggFunc::Sum, x.clone()))],
+            ),
+            vec![Atom::new(big.clone(), vec![Term::Var(x)])],
+        ));
+        let bad = (big.clone(), vec![Const::Int(i64::MAX)]);
+        let mut mir = Mir::new(ast.clone()).unwrap();
+        mir.add_fact(&big, vec![Const::Int(1)]);
+        let batch: Vec<(Rel, Vec<Const>)> =
+            batch.iter().map(|(r, consts)| spec.fact(*r, consts)).collect();
+        let expected = naive(&with_facts(&mir, &batch, &[]));
+        mir.add_fact(&bad.0, bad.1.clone());
+
+        #[cfg(feature = "duckdb")]
+        prop_assert_eq!(
+            &expected,
+            &after_error(
+                ::duckdb::Connection::open_in_memory().unwrap(),
+                mir.clone(),
+                &bad,
+                &batch
+            ).1,
+            "DuckDB, adding {:?} on:\n{}",
+            batch,
+            ast
+        );
+        #[cfg(feature = "sqlite")]
+        prop_assert_eq!(
+            (true, expected),
+            after_error(::rusqlite::Connection::open_in_memory().unwrap(), mir, &bad, &batch),
+            "SQLite, adding {:?} on:\n{}",
+            batch,
+            ast
+        );
+    }
+}
+
+/// Integer division truncates on every backend, like in the in-memory
+/// evaluator
+#[test]
+fn same_model_division() {
+    let src = r"
+        n(7). n(-7). n(0). x(7.0).
+        half(N, M) :- n(N), M = N / 2.
+        inv(N, M) :- n(N), M = 7 / N.
+        fhalf(X, Y) :- x(X), Y = X / 2.0.
+        ";
+    let mir = Mir::new(crate::parse::parse(src).unwrap()).unwrap();
+    let mut interp = Interp::new(mir.clone());
+    interp.go();
+    let expected = interp.model();
+    let half = &expected[&Rel::new(String::from("half"))];
+    assert!(half.contains(&vec![Const::Int(7), Const::Int(3)]));
+    assert!(half.contains(&vec![Const::Int(-7), Const::Int(-3)]));
+    #[cfg(feature = "duckdb")]
+    assert_eq!(expected, duckdb(mir.clone()));
+    #[cfg(feature = "sqlite")]
+    assert_eq!(expected, sqlite(mir));
+}
@@ -1,287 +1,396 @@
-#[cfg(feature = "duckdb")]
-use duckdb::{Connection, Result};
-#[cfg(feature = "sqlite")]
-use rusqlite::{Connection, Result};
+use std::cell::{Cell, RefCell};
+use std::path::Path;
 
 use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
 
-use crate::ast::{Const, Rel, Rule, Term};
-use crate::mir::Mir;
+use crate::ast::{
+    AggFunc, ArithOp, Ast, Atom, CmpOp, Comparison, Const, Expr, Literal, Rel, RelDecl, Rule, Term,
+    Var,
+};
+use crate::backend::{self, ident, literal, Backend, Dialect, ExportFormat, Merge, Query, Source};
+use crate::magic;
+use crate::mir::{Mir, Scc};
 
+/// Evaluates a [`Mir`] program using some database [`Backend`]
 #[derive(Debug)]
-pub struct Eval {
-    conn: Connection,
+pub struct Eval<B> {
+    backend: B,
     prog: Mir,
+    /// Every relation in `prog`, see [`Mir::decls`]
+    decls: HashMap<Rel, RelDecl>,
+    /// The last iteration, stamps new facts (see [`backend::merge`])
+    it: Cell<usize>,
+    /// The last `id` of each relation after the last [`Eval::go`], if any.
+    /// Later rows have facts that haven't been propagated yet.
+    marks: RefCell<Option<HashMap<Rel, i64>>>,
 }
 
-fn create_table(rel: &Rel, arity: usize) -> String {
-    let mut attrs = Vec::with_capacity(arity);
-    let mut indices = Vec::new();
-    for i in 0..arity {
-        attrs.push(format!("x{i}  TEXT NOT NULL"));
-        indices.push(format!("CREATE INDEX {rel}{i}_idx ON {rel} (x{i})"));
-    }
-
-    // `it` is the iteration number, for semi-naive evaluation
-    if cfg!(feature = "duckdb") {
-        format!(
-            r"CREATE SEQUENCE {0}_seq;
-          CREATE TABLE {0} (
-              id  INTEGER PRIMARY KEY DEFAULT NEXTVAL('{0}_seq'),
-              it  INTEGER,
-              {1}
-          );
-          CREATE INDEX {0}_delta_idx ON {0} (it);
-          {2}{3}
-         ",
-            rel,
-            attrs.join(",\n"),
-            indices.join(";\n"),
-            if indices.is_empty() { "" } else { ";" }
-        )
-    } else {
-        format!(
-            r"CREATE TABLE {0} (
-              id  INTEGER PRIMARY KEY AUTOINCREMENT,
-              it  INTEGER{1}
-              {2}
-          );
-          CREATE INDEX {0}_delta_idx ON {0} (it);
-          {3}{4}
-         ",
-            rel,
-            if attrs.is_empty() { "" } else { "," },
-            attrs.join(",\n"),
-            indices.join(";\n"),
-            if indices.is_empty() { "" } else { ";" }
-        )
+/// Non-recursive Datalog is equivalent to unions of conjunctive queries :-)
+///
+/// The queries insert into the `new` tables (see [`backend::new_table`]), for
+/// semi-naive evaluation. The first iteration of each stratum (`recursive` is
+/// `None`) evaluates each rule against the full tables. Later iterations only
+/// need the facts derived in the previous iteration, so there's one query for
+/// each body atom whose relation is in `recursive` (the relations of the
+/// current stratum, or those with new facts, see [`Eval::go`]), which reads
+/// that atom from its `delta` table.
+///
+/// See also https://github.com/philzook58/duckegg/blob/e6c9fc106098e837095c461521c451c18e53c091/duckegg.py#L101
+fn eval_rule_query(
+    dialect: Dialect,
+    rule: &Rule,
+    recursive: Option<&HashSet<&Rel>>,
+    decls: &HashMap<Rel, RelDecl>,
+) -> Vec<Query> {
+    match recursive {
+        None => vec![rule_query(dialect, rule, None, decls)],
+        Some(rels) => rule
+            .body
+            .iter()
+            .enumerate()
+            .filter(|(_, lit)| lit.positive().is_some_and(|a| rels.contains(&a.rel)))
+            .map(|(i, _)| rule_query(dialect, rule, Some(i), decls))
+            .collect(),
     }
 }
 
-fn create_tables(conn: &Connection, prog: &Mir) -> Result<()> {
-    // eprintln!("BEGIN;");
-    conn.execute_batch("BEGIN;")?;
-    for (rel, arity) in prog.arities() {
-        let stmt = create_table(&rel, arity);
-        // eprintln!("{stmt}");
-        conn.execute_batch(&stmt)?;
-    }
-    // eprintln!("COMMIT;");
-    conn.execute_batch("COMMIT;")?;
-    Ok(())
-}
-
-fn exists(conn: &Connection, rel: &Rel, consts: &Vec<Const>) -> Result<bool> {
-    let mut q = format!("SELECT COUNT(*) from {}", rel);
-    if !consts.is_empty() {
-        q += " WHERE ";
-        for (i, c) in consts.iter().enumerate() {
-            if i != 0 {
-                q += " AND ";
+/// The query for one rule, reading body literal `delta` (if any) from its
+/// `delta` table. See [`eval_rule_query`].
+fn rule_query(
+    dialect: Dialect,
+    rule: &Rule,
+    delta: Option<usize>,
+    decls: &HashMap<Rel, RelDecl>,
+) -> Query {
+    let head_decl = &decls[&rule.head.rel];
+    let mut params = Query::default();
+    // For each relation in the body, select from that relation's table
+    let mut tables: Vec<String> = Vec::new();
+    let mut bindings: HashMap<&Var, Vec<String>> = HashMap::default();
+    let mut selections = Vec::new();
+    // `count`s and `sum`s in the body, see below
+    let mut totals = Vec::new();
+    for (i, lit) in rule.body.iter().enumerate() {
+        let atom = match lit.positive() {
+            Some(atom) => atom,
+            None => continue,
+        };
+        if atom.rel.is_total() && delta != Some(i) {
+            totals.push((i, atom));
+            continue;
+        }
+        let table = format!("t{i}");
+        // Semi-naive: only use the facts from the previous iteration
+        let source = if delta == Some(i) {
+            backend::delta_table(&atom.rel)
+        } else {
+            backend::table(&atom.rel)
+        };
+        tables.push(format!("{source} AS {table}"));
+        for (term, col) in atom.terms.iter().zip(&decls[&atom.rel].cols) {
+            let name = format!("{table}.{}", ident(&col.name));
+            match term {
+                // Constants select the rows with that value
+                Term::Const(c) => selections.push(format!("{name} = {}", params.param(dialect, c))),
+                Term::Var(v) => {
+                    bindings
+                        .entry(v)
+                        .or_insert_with(|| Vec::with_capacity(1))
+                        .push(name);
+                }
+                Term::Aggregate(_) => unreachable!("aggregate in body"),
             }
-            q += &format!("{}.x{} = '{}'", rel, i, c);
         }
     }
-    q += ";";
 
-    // eprintln!("{q}");
-    let mut entries = conn.prepare_cached(&q).unwrap();
-    let n: usize = entries
-        .query([])?
-        .next()?
-        .expect("No rows for COUNT?")
-        .get(0)?;
-    debug_assert!(n == 0 || n == 1);
-    Ok(n >= 1)
-}
+    // Built-in comparisons are conditions, except for equations that bind a
+    // variable to the value of an expression (see Comparison::binds).
+    // `count`s and `sum`s are left joined once their groups are bound, so
+    // that groups without a fact get 0 (see Rel::is_total).
+    let mut filters = Vec::new();
+    let mut joins = Vec::new();
+    let mut pending: Vec<&Comparison> = rule.comparisons().collect();
+    while !pending.is_empty() || !totals.is_empty() {
+        let before = pending.len() + totals.len();
+        totals.retain(|(i, atom)| {
+            let (result, groups) = atom.terms.split_last().expect("aggregate result");
+            let bound = |t: &Term| t.var().is_none_or(|v| bindings.contains_key(v));
+            if !groups.iter().all(bound) {
+                return true;
+            }
+            let table = format!("t{i}");
+            let cols = &decls[&atom.rel].cols;
+            let mut on = Vec::with_capacity(groups.len());
+            for (term, col) in groups.iter().zip(cols) {
+                let val = match term {
+                    Term::Const(c) => params.param(dialect, c),
+                    Term::Var(v) => bindings[v][0].clone(),
+                    Term::Aggregate(_) => unreachable!("aggregate in body"),
+                };
+                on.push(format!("{table}.{} = {val}", ident(&col.name)));
+            }
+            if on.is_empty() {
+                on.push(String::from("TRUE"));
+            }
+            joins.push(format!(
+                " LEFT JOIN {} AS {table} ON {}",
+                backend::table(&atom.rel),
+                on.join(" AND ")
+            ));
+            let col = cols.last().expect("aggregate result");
+            let value = format!(
+                "COALESCE({table}.{}, {})",
+                ident(&col.name),
+                literal(dialect, &Const::zero(col.ty))
+            );
+            match result {
+                Term::Const(c) => filters.push(format!("{value} = {}", params.param(dialect, c))),
+                Term::Var(v) => bindings.entry(v).or_default().push(value),
+                Term::Aggregate(_) => unreachable!("aggregate in body"),
+            }
+            false
+        });
+        pending.retain(|cmp| {
+            let bound: std::collections::HashSet<&Var> = bindings.keys().copied().collect();
+            if let Some((v, e)) = cmp.binds(&bound) {
+                let sql = expr_sql(dialect, e, &bindings, &mut params);
+                // e.g., division by zero
+                filters.push(format!("{sql} IS NOT NULL"));
+                bindings.insert(v, vec![sql]);
+                false
+            } else if cmp.vars().iter().all(|v| bound.contains(v)) {
+                filters.push(format!(
+                    "{} {} {}",
+                    expr_sql(dialect, &cmp.lhs, &bindings, &mut params),
+                    cmp_sql(cmp.op),
+                    expr_sql(dialect, &cmp.rhs, &bindings, &mut params)
+                ));
+                false
+            } else {
+                true
+            }
+        });
+        assert!(
+            pending.len() + totals.len() < before,
+            "Mir rules are range-restricted"
+        );
+    }
 
-// TODO(lb, low): Group facts by relation, use Appender
-fn insert_fact(conn: &Connection, rel: &Rel, consts: &Vec<Const>) -> Result<()> {
-    let mut q = if cfg!(feature = "duckdb") {
-        format!(r"INSERT INTO {0} VALUES (nextval('{0}_seq'), 0", rel)
-    } else {
-        let mut attrs = Vec::with_capacity(consts.len());
-        attrs.push(String::from("it"));
-        for i in 0..consts.len() {
-            attrs.push(format!("x{i}"));
-        }
-        format!(r"INSERT INTO {0} ({1}) VALUES (0", rel, attrs.join(", "))
+    // Any of the bindings will do, they're all asserted equal in WHERE
+    let bound = |v: &Var| -> String {
+        bindings
+            .get(v)
+            .expect("Mir rules are range-restricted")
+            .first()
+            .unwrap()
+            .clone()
     };
-    for c in consts {
-        q += &format!(", '{c}'");
-    }
-    q += ");";
 
-    // eprintln!("{q}");
-    let mut stmt = conn.prepare_cached(&q)?;
-    stmt.execute([])?;
-    conn.flush_prepared_statement_cache();
-    Ok(())
-}
+    // Let SQL do the unification by building WHERE clauses that equate the
+    // different SQL names of the same Datalog variable
+    let mut conds = selections;
+    for binds in bindings.values() {
+        let mut iter = binds.iter();
+        let first = iter.next().unwrap();
+        for bind in iter {
+            conds.push(format!("{first} = {bind}"));
+        }
+    }
+    conds.extend(filters);
 
-fn insert_fact_if_not_exists(conn: &Connection, rel: &Rel, consts: &Vec<Const>) -> Result<()> {
-    if exists(conn, rel, consts)? {
-        return Ok(());
+    // Negated atoms: there's no matching row. Their variables are bound by
+    // the rest of the body, because Mir rules are range-restricted, except
+    // for wildcards (see Rule::local), which match anything.
+    for (i, lit) in rule.body.iter().enumerate() {
+        if !lit.is_negative() {
+            continue;
+        }
+        let atom = lit.atom().unwrap();
+        let table = format!("t{i}");
+        let mut eqs = Vec::new();
+        let mut local: HashMap<&Var, String> = HashMap::default();
+        for (term, col) in atom.terms.iter().zip(&decls[&atom.rel].cols) {
+            let name = format!("{table}.{}", ident(&col.name));
+            let val = match term {
+                Term::Const(c) => params.param(dialect, c),
+                Term::Var(v) if bindings.contains_key(v) => bound(v),
+                Term::Var(v) => match local.get(v) {
+                    Some(first) => first.clone(),
+                    None => {
+                        local.insert(v, name);
+                        continue;
+                    }
+                },
+                Term::Aggregate(_) => unreachable!("aggregate in body"),
+            };
+            eqs.push(format!("{name} = {val}"));
+        }
+        let mut q = format!("SELECT 1 FROM {} AS {table}", backend::table(&atom.rel));
+        if !eqs.is_empty() {
+            q += " WHERE ";
+            q += &eqs.join(" AND ");
+        }
+        conds.push(format!("NOT EXISTS ({q})"));
     }
-    insert_fact(conn, rel, consts)
-}
 
-/// Non-recursive Datalog is equivalent to unions of conjunctive queries :-)
-///
-/// `it` is the current iteration number, for semi-naive evaluation.
-///
-/// See also https://github.com/philzook58/duckegg/blob/e6c9fc106098e837095c461521c451c18e53c091/duckegg.py#L101
-fn eval_rule_query(rule: &Rule, it: usize) -> Vec<String> {
-    let rel = &rule.head.rel;
-    let mut rules = Vec::new();
-    for delta in 0..rule.body.len() {
-        // For each relation in the body, select from that relation's table
-        let mut tables: Vec<String> = Vec::new();
-        let mut bindings: HashMap<Term, Vec<String>> = HashMap::default();
-        let mut delta_cond = String::new();
-        for (i, atom) in rule.body.iter().enumerate() {
-            // TODO: Make the SQL a bit clearer:
-            // if i == delta {
-            //     let table = format!("delta_{}{i}", atom.rel);
-            // } else {
-            //     let table = format!("{}{i}", atom.rel);
-            // }
-            let table = format!("{}{i}", atom.rel);
-            tables.push(format!("{} AS {table}", atom.rel));
-            for (field, term) in atom.terms.iter().enumerate() {
-                bindings
-                    .entry(term.clone())
-                    .or_insert_with(|| Vec::with_capacity(1))
-                    .push(format!("{table}.x{field}"))
-            }
-            // Semi-naive: only use the facts from the previous generation
-            if i == delta && it > 0 {
-                delta_cond = format!("{table}.it = {}", it - 1);
-            }
+    let mut from = String::new();
+    if !joins.is_empty() {
+        // `JOIN` binds more tightly than `,`, so the conditions of the left
+        // joins couldn't refer to the tables before a comma
+        from += " FROM ";
+        if tables.is_empty() {
+            from += "(SELECT 1) AS unit";
         }
+        from += &tables.join(" CROSS JOIN ");
+        from += &joins.concat();
+    } else if !tables.is_empty() {
+        from += " FROM ";
+        from += &tables.join(",");
+    }
 
+    // Duplicates are removed when merging the `new` table, see
+    // [`backend::merge`]
+    let select = if rule.is_aggregate() {
+        aggregate_query(dialect, rule, &bindings, &from, &conds, &mut params)
+    } else {
         // Project out the variables that are needed by the head
         let mut selects = Vec::new();
         for term in &rule.head.terms {
             selects.push(match term {
-                Term::Const(c) => format!("'{}'", c),
-                // Any of the bindings will do, they're all asserted equal in WHERE
-                v @ Term::Var(_) => bindings
-                    .get(v)
-                    .expect("Range restriction violation!")
-                    .get(0)
-                    .unwrap()
-                    .clone(),
+                Term::Const(c) => params.typed_param(dialect, c),
+                Term::Var(v) => bound(v),
+                Term::Aggregate(_) => unreachable!("checked by Rule::is_aggregate"),
             })
         }
-
-        // Let SQL do the unification by building WHERE clauses that equate the
-        // different SQL names of the same Datalog variable
-        let mut unifications = Vec::new();
-        for binds in bindings.values() {
-            let mut iter = binds.iter();
-            let first = iter.next().unwrap();
-            for bind in iter {
-                unifications.push(format!("{first} = {bind}"));
-            }
-        }
-        let unification_conds = if unifications.is_empty() {
-            String::from("true")
-        } else {
-            unifications.join(" AND ")
-        };
-
-        // Ensure the entry doesn't already exist (set semantics)
-        let mut eqs = Vec::new();
-        for (i, col) in selects.iter().enumerate() {
-            eqs.push(format!("pre.x{i} = {col}"));
+        if selects.is_empty() {
+            // Nullary heads: at most one row, whatever it contains
+            selects.push(String::from("1"));
         }
-        let mut not_exists = format!("SELECT * from {} AS pre", rel);
-        if !eqs.is_empty() {
-            not_exists += " WHERE ";
-            not_exists += &eqs.join(" AND ");
+        let mut select = format!("SELECT DISTINCT {}{from}", selects.join(", "));
+        if !conds.is_empty() {
+            select += " WHERE ";
+            select += &conds.join(" AND ");
         }
+        select
+    };
 
-        // Assign each selected column of the subquery a name
-        let mut selects_as = Vec::with_capacity(selects.len());
-        for (i, sel) in selects.into_iter().enumerate() {
-            selects_as.push(format!("{sel} AS y{i}"));
-        }
-        if selects_as.is_empty() {
-            selects_as.push(String::from("*"));
+    params.sql = format!(
+        "INSERT INTO {} ({}) {select};",
+        backend::new_table(&rule.head.rel),
+        backend::delta_cols(head_decl).join(", "),
+    );
+    params
+}
+
+/// SQL for an arithmetic expression, given the SQL of its variables. Division
+/// of integers truncates, on both backends.
+fn expr_sql(
+    dialect: Dialect,
+    expr: &Expr,
+    bindings: &HashMap<&Var, Vec<String>>,
+    params: &mut Query,
+) -> String {
+    match expr {
+        Expr::Const(c) => params.param(dialect, c),
+        Expr::Var(v) => bindings[v][0].clone(),
+        Expr::Binary(op, l, r) => {
+            let op = match op {
+                ArithOp::Add => "+",
+                ArithOp::Sub => "-",
+                ArithOp::Mul => "*",
+                ArithOp::Div => "/",
+            };
+            format!(
+                "({} {op} {})",
+                expr_sql(dialect, l, bindings, params),
+                expr_sql(dialect, r, bindings, params)
+            )
         }
+    }
+}
 
-        let subquery = format!(
-            "SELECT DISTINCT {} FROM {} WHERE {} AND {} AND NOT EXISTS ({})",
-            selects_as.join(", "),
-            tables.join(","),
-            delta_cond,
-            unification_conds,
-            not_exists,
-        );
+fn cmp_sql(op: CmpOp) -> &'static str {
+    match op {
+        CmpOp::Eq => "=",
+        CmpOp::Ne => "<>",
+        CmpOp::Lt => "<",
+        CmpOp::Le => "<=",
+        CmpOp::Gt => ">",
+        CmpOp::Ge => ">=",
+    }
+}
 
-        // Select out the necessary columns from the subquery
-        let mut selected = Vec::with_capacity(selects_as.len());
-        let n_selected = if selects_as[0] == "*" {
-            0
-        } else {
-            selects_as.len()
-        };
-        for i in 0..n_selected {
-            selected.push(format!("y{i}"));
-        }
+/// Aggregates are computed over the distinct assignments of the body's
+/// variables (set semantics), grouped by the other terms of the head. Selects
+/// the columns of the head, like the query in [`rule_query`].
+fn aggregate_query(
+    dialect: Dialect,
+    rule: &Rule,
+    bindings: &HashMap<&Var, Vec<String>>,
+    from: &str,
+    conds: &[String],
+    params: &mut Query,
+) -> String {
+    let vars: Vec<&Var> = bindings.keys().copied().collect();
+    let mut inner = Vec::with_capacity(vars.len());
+    for (i, v) in vars.iter().enumerate() {
+        inner.push(format!("{} AS v{i}", bindings[v][0]));
+    }
+    let name = |v: &Var| {
+        let i = vars.iter().position(|w| *w == v);
+        format!("v{}", i.expect("Mir rules are range-restricted"))
+    };
 
-        rules.push(if cfg!(feature = "duckdb") {
-            format!(
-                r"INSERT INTO {0} SELECT nextval('{0}_seq'), it{1} FROM ({2});",
-                rel,
-                if selected.is_empty() {
-                    String::from("")
-                } else {
-                    format!(", {}", selected.join(", "))
-                },
-                subquery
-            )
-        } else {
-            let mut attrs = Vec::with_capacity(selected.len());
-            for i in 0..selected.len() {
-                attrs.push(format!("x{i}"));
+    let mut selects = Vec::with_capacity(rule.head.terms.len());
+    let mut groups = Vec::new();
+    for (i, term) in rule.head.terms.iter().enumerate() {
+        let sel = match term {
+            Term::Const(c) => params.typed_param(dialect, c),
+            Term::Var(v) => {
+                groups.push(name(v));
+                name(v)
             }
-            format!(
-                r"INSERT INTO {0} (it{1}) SELECT {it}{2} FROM ({3});",
-                rel,
-                if attrs.is_empty() {
-                    String::from("")
-                } else {
-                    format!(", {}", attrs.join(", "))
-                },
-                if selected.is_empty() {
-                    String::from("")
-                } else {
-                    format!(", {}", selected.join(", "))
-                },
-                subquery
-            )
-        });
+            Term::Aggregate(a) => {
+                let func = match a.func() {
+                    AggFunc::Count => "COUNT",
+                    AggFunc::Sum => "SUM",
+                    AggFunc::Min => "MIN",
+                    AggFunc::Max => "MAX",
+                };
+                format!("{func}({})", name(a.var()))
+            }
+        };
+        selects.push(format!("{sel} AS y{i}"));
     }
-    rules
-}
+    // Without groups, SQL aggregates an empty body to a single row (e.g.,
+    // `COUNT` is 0), but there should be no facts
+    selects.push(String::from("COUNT(*) AS n"));
 
-fn insert_facts(conn: &Connection, prog: &Mir) -> Result<()> {
-    // eprintln!("BEGIN;");
-    conn.execute_batch("BEGIN;")?;
-    conn.set_prepared_statement_cache_capacity(512); // just a guess
-    for (rel, facts) in prog.facts() {
-        for fact in facts {
-            insert_fact_if_not_exists(conn, rel, fact)?;
-        }
+    let mut agg = format!(
+        "SELECT {} FROM (SELECT DISTINCT {}{from} WHERE {})",
+        selects.join(", "),
+        inner.join(", "),
+        if conds.is_empty() {
+            String::from("true")
+        } else {
+            conds.join(" AND ")
+        },
+    );
+    if !groups.is_empty() {
+        agg += " GROUP BY ";
+        agg += &groups.join(", ");
     }
-    // eprintln!("COMMIT;");
-    conn.execute_batch("COMMIT;")?;
-    Ok(())
+
+    let ys: Vec<String> = (0..rule.head.terms.len())
+        .map(|i| format!("agg.y{i}"))
+        .collect();
+    format!(
+        "SELECT {} FROM ({agg}) AS agg WHERE agg.n > 0",
+        ys.join(", ")
+    )
 }
 
-impl Eval {
+impl<B: Backend> Eval<B> {
     /// Clear facts from the embedded [`Mir`] program.
     pub fn clear_facts(&mut self) {
         self.prog.clear_facts()
@@ -291,75 +400,517 @@ impl Eval {
     ///
     /// If it makes sense for your time/space trade-off, you can call
     /// [`Eval::clear_facts`] after this.
-    pub fn new(conn: Connection, prog: Mir) -> Result<Self> {
-        create_tables(&conn, &prog)?;
-        insert_facts(&conn, &prog)?;
-        Ok(Self { conn, prog })
+    pub fn new(backend: B, prog: Mir) -> Result<Self, backend::Error> {
+        let decls = prog.decls();
+        for decl in decls.values() {
+            backend.create_relation(decl)?;
+        }
+        for (rel, facts) in prog.facts() {
+            backend.insert(&decls[rel], facts.map(Vec::as_slice))?;
+        }
+        Ok(Self {
+            backend,
+            prog,
+            decls,
+            it: Cell::new(0),
+            marks: RefCell::new(None),
+        })
+    }
+
+    /// Add facts to a relation. The next call to [`Eval::go`] propagates
+    /// them, reusing what was derived before.
+    ///
+    /// Returns an error, and adds nothing, if a fact doesn't match the
+    /// relation's declaration.
+    ///
+    /// # Panics
+    ///
+    /// If `rel` isn't a relation of the program.
+    pub fn insert_facts<'a>(
+        &self,
+        rel: &Rel,
+        facts: impl IntoIterator<Item = &'a [Const]>,
+    ) -> Result<(), backend::Error> {
+        self.backend.insert(&self.decls[rel], facts)
+    }
+
+    /// Add the facts in a file to a relation, like [`Eval::insert_facts`].
+    /// The backend reads the file itself: DuckDB reads CSV and Parquet files,
+    /// SQLite only CSV files.
+    ///
+    /// # Panics
+    ///
+    /// If `rel` isn't a relation of the program.
+    pub fn load(&self, rel: &Rel, source: &Source) -> Result<(), backend::Error> {
+        self.backend.load(&self.decls[rel], source)
+    }
+
+    /// Evaluate the program to a fixpoint, one strongly connected component
+    /// at a time (see [`Mir::sccs`]). Returns the relations of each component
+    /// and the number of iterations it took.
+    ///
+    /// After the first call, only the facts added since the last call (see
+    /// [`Eval::insert_facts`]) are propagated, and components that don't
+    /// depend on them are skipped. Components that negate or aggregate over
+    /// relations with new facts are evaluated again from scratch, and so are
+    /// the components that depend on them.
+    pub fn go(&self) -> Result<Vec<(Vec<Rel>, usize)>, backend::Error> {
+        // If evaluation fails, start over next time
+        let marks = self.marks.take();
+        let iters = match &marks {
+            None => {
+                // Drop what an earlier, failed evaluation derived
+                let clear: Vec<Query> = self.decls.values().map(backend::clear_derived).collect();
+                self.backend.batch(&clear)?;
+                self.run(&self.prog, &self.decls)?
+            }
+            Some(marks) => self.run_incremental(marks)?,
+        };
+        *self.marks.borrow_mut() = Some(self.last_ids()?);
+        Ok(iters)
+    }
+
+    /// The last `id` of each relation, see [`Eval::go`]
+    fn last_ids(&self) -> Result<HashMap<Rel, i64>, backend::Error> {
+        let mut ids = HashMap::default();
+        for (rel, decl) in &self.decls {
+            ids.insert(rel.clone(), self.backend.last_id(decl)?);
+        }
+        Ok(ids)
+    }
+
+    /// Remove facts from a relation, which were added by [`Eval::new`] or
+    /// [`Eval::insert_facts`], and update the facts derived by [`Eval::go`]
+    /// (if it was called) to match.
+    ///
+    /// This uses delete-and-rederive (DRed): each component first deletes the
+    /// facts that it derived from deleted facts, then derives those of them
+    /// that can still be derived from the remaining facts. Like in
+    /// [`Eval::go`], components that negate or aggregate over relations that
+    /// lost facts are evaluated again from scratch, and so are the components
+    /// that depend on them.
+    ///
+    /// Returns an error, and removes nothing, if a fact doesn't match the
+    /// relation's declaration.
+    ///
+    /// # Panics
+    ///
+    /// If `rel` isn't a relation of the program.
+    pub fn retract_facts<'a>(
+        &self,
+        rel: &Rel,
+        facts: impl IntoIterator<Item = &'a [Const]>,
+    ) -> Result<(), backend::Error> {
+        let decl = &self.decls[rel];
+        let dialect = self.backend.dialect();
+        let retract: Vec<Query> = facts
+            .into_iter()
+            .map(|fact| {
+                backend::check_fact(decl, fact)?;
+                Ok(backend::retract(dialect, decl, fact))
+            })
+            .collect::<Result<_, backend::Error>>()?;
+        self.backend.batch(&retract)?;
+        let Some(marks) = self.marks.take() else {
+            // Nothing was derived
+            return self.backend.batch(&[backend::purge(decl)]);
+        };
+        // Facts added since the last evaluation are left for the next one
+        let last_ids = self.last_ids()?;
+        let pending = last_ids.iter().any(|(rel, id)| *id > marks[rel]);
+        self.dred(rel)?;
+        *self.marks.borrow_mut() = Some(if pending { marks } else { last_ids });
+        Ok(())
+    }
+
+    /// Delete-and-rederive, after deleting facts of `rel`. See
+    /// [`Eval::retract_facts`].
+    fn dred(&self, rel: &Rel) -> Result<(), backend::Error> {
+        let sccs = self.prog.sccs();
+        // Relations that may have lost facts
+        let mut deleted: HashSet<&Rel> = HashSet::from_iter([rel]);
+        // Relations that will be evaluated from scratch
+        let mut redo: HashSet<&Rel> = HashSet::default();
+
+        // Over-delete
+        for scc in &sccs {
+            let redo_scc = scc.rules.iter().any(|rule| {
+                rule.body.iter().any(|lit| match lit {
+                    Literal::Atom(atom) => {
+                        redo.contains(&atom.rel)
+                            || (rule.is_aggregate() && deleted.contains(&atom.rel))
+                    }
+                    Literal::Not(atom) => redo.contains(&atom.rel) || deleted.contains(&atom.rel),
+                    Literal::Cmp(_) => false,
+                })
+            });
+            if redo_scc {
+                redo.extend(scc.rels.iter().copied());
+                continue;
+            }
+            let body: HashSet<&Rel> = scc
+                .rules
+                .iter()
+                .flat_map(|rule| rule.positive())
+                .map(|atom| &atom.rel)
+                .filter(|rel| deleted.contains(rel))
+                .collect();
+            if body.is_empty() {
+                continue;
+            }
+            let vanished: Vec<Query> = body
+                .iter()
+                .map(|rel| backend::vanished(&self.decls[*rel]))
+                .collect();
+            self.backend.batch(&vanished)?;
+            let first = self.queries(scc, &self.decls, Some(&body));
+            self.fixpoint(scc, &self.decls, first, |decl, _| backend::overdelete(decl))?;
+            deleted.extend(scc.rels.iter().copied());
+        }
+
+        // Delete, keeping the deleted facts in the `delta` tables
+        let mut gone = Vec::with_capacity(2 * deleted.len());
+        for rel in &deleted {
+            gone.push(backend::vanished(&self.decls[*rel]));
+            gone.push(backend::purge(&self.decls[*rel]));
+        }
+        self.backend.batch(&gone)?;
+
+        // Rederive
+        let dialect = self.backend.dialect();
+        for scc in &sccs {
+            if scc.rels.iter().any(|rel| redo.contains(rel)) {
+                let clear: Vec<Query> = scc
+                    .rels
+                    .iter()
+                    .map(|rel| backend::clear_derived(&self.decls[*rel]))
+                    .collect();
+                self.backend.batch(&clear)?;
+                let first = self.queries(scc, &self.decls, None);
+                self.fixpoint(scc, &self.decls, first, backend::merge)?;
+                continue;
+            }
+            let mut first = Vec::new();
+            for rule in &scc.rules {
+                if !deleted.contains(&rule.head.rel) {
+                    continue;
+                }
+                if rule.is_aggregate() {
+                    first.push(rule_query(dialect, rule, None, &self.decls));
+                    continue;
+                }
+                // Only the deleted facts, by reading the head from `delta`
+                let mut body = Vec::with_capacity(rule.body.len() + 1);
+                body.push(Literal::Atom(rule.head.clone()));
+                body.extend(rule.body.iter().cloned());
+                let rederive = Rule::new_with_literals(rule.head.clone(), body);
+                first.push(rule_query(dialect, &rederive, Some(0), &self.decls));
+            }
+            if !first.is_empty() {
+                self.fixpoint(scc, &self.decls, first, backend::merge)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluate `prog`, whose relations are in `decls` and have tables.
+    fn run(
+        &self,
+        prog: &Mir,
+        decls: &HashMap<Rel, RelDecl>,
+    ) -> Result<Vec<(Vec<Rel>, usize)>, backend::Error> {
+        let mut iters = Vec::new();
+        for scc in prog.sccs() {
+            let first = self.queries(&scc, decls, None);
+            let n = self.fixpoint(&scc, decls, first, backend::merge)?;
+            iters.push((scc.rels.into_iter().cloned().collect(), n));
+        }
+        Ok(iters)
+    }
+
+    /// Like [`Eval::run`] on the whole program, but only propagate the facts
+    /// in rows after `marks`, see [`Eval::go`].
+    fn run_incremental(
+        &self,
+        marks: &HashMap<Rel, i64>,
+    ) -> Result<Vec<(Vec<Rel>, usize)>, backend::Error> {
+        let mut iters = Vec::new();
+        // Relations that were evaluated from scratch, and may have lost facts
+        let mut redone: HashSet<&Rel> = HashSet::default();
+        let mut changed: HashSet<&Rel> = HashSet::default();
+        for scc in self.prog.sccs() {
+            for rule in &scc.rules {
+                for atom in rule.body.iter().filter_map(|lit| lit.atom()) {
+                    let decl = &self.decls[&atom.rel];
+                    if !changed.contains(&atom.rel)
+                        && self.backend.last_id(decl)? > marks[&atom.rel]
+                    {
+                        changed.insert(&atom.rel);
+                    }
+                }
+            }
+            let redo = scc.rules.iter().any(|rule| {
+                rule.body.iter().any(|lit| match lit {
+                    Literal::Atom(atom) => {
+                        redone.contains(&atom.rel)
+                            || (rule.is_aggregate() && changed.contains(&atom.rel))
+                    }
+                    Literal::Not(atom) => redone.contains(&atom.rel) || changed.contains(&atom.rel),
+                    Literal::Cmp(_) => false,
+                })
+            });
+            let n = if redo {
+                let heads: Vec<Query> = scc
+                    .rels
+                    .iter()
+                    .map(|rel| backend::clear_derived(&self.decls[*rel]))
+                    .collect();
+                self.backend.batch(&heads)?;
+                redone.extend(scc.rels.iter().copied());
+                let first = self.queries(&scc, &self.decls, None);
+                self.fixpoint(&scc, &self.decls, first, backend::merge)?
+            } else {
+                let body: HashSet<&Rel> = scc
+                    .rules
+                    .iter()
+                    .flat_map(|rule| rule.positive())
+                    .map(|atom| &atom.rel)
+                    .filter(|rel| changed.contains(rel))
+                    .collect();
+                if body.is_empty() {
+                    continue;
+                }
+                let deltas: Vec<Query> = body
+                    .iter()
+                    .map(|rel| backend::delta_since(&self.decls[*rel], marks[*rel]))
+                    .collect();
+                self.backend.batch(&deltas)?;
+                let first = self.queries(&scc, &self.decls, Some(&body));
+                self.fixpoint(&scc, &self.decls, first, backend::merge)?
+            };
+            iters.push((scc.rels.into_iter().cloned().collect(), n));
+        }
+        Ok(iters)
+    }
+
+    /// The queries for an iteration of a component, see [`eval_rule_query`]
+    fn queries(
+        &self,
+        scc: &Scc<'_>,
+        decls: &HashMap<Rel, RelDecl>,
+        recursive: Option<&HashSet<&Rel>>,
+    ) -> Vec<Query> {
+        let mut rule_queries = Vec::with_capacity(scc.rules.len());
+        for rule in &scc.rules {
+            rule_queries.extend(eval_rule_query(
+                self.backend.dialect(),
+                rule,
+                recursive,
+                decls,
+            ));
+        }
+        rule_queries
     }
 
-    pub fn go(&self) -> Result<usize> {
-        let mut iters = 0;
+    /// Evaluate a component to a fixpoint, starting with `first`, and
+    /// returning the number of iterations. Later iterations read the
+    /// component's relations from their `delta` tables. `merge` is
+    /// [`backend::merge`] or [`backend::overdelete`].
+    fn fixpoint(
+        &self,
+        scc: &Scc<'_>,
+        decls: &HashMap<Rel, RelDecl>,
+        first: Vec<Query>,
+        merge: fn(&RelDecl, usize) -> Merge,
+    ) -> Result<usize, backend::Error> {
+        let rels: HashSet<&Rel> = scc.rels.iter().copied().collect();
+        let mut rule_queries = first;
+        let mut n = 0;
         // Execute the queries until fixpoint
         loop {
-            iters += 1;
-            // Build the conjunctive query for each rule
-            let mut rule_queries = Vec::with_capacity(self.prog.rules().count());
-            for rule in self.prog.rules() {
-                rule_queries.extend(eval_rule_query(rule, iters));
+            n += 1;
+            let it = self.it.get() + 1;
+            self.it.set(it);
+            let merges: Vec<Merge> = scc.rels.iter().map(|rel| merge(&decls[*rel], it)).collect();
+            let changed = self.backend.step(&rule_queries, &merges)?;
+            if !changed || !scc.recursive {
+                return Ok(n);
             }
+            rule_queries = self.queries(scc, decls, Some(&rels));
+        }
+    }
 
-            let mut changed = false;
-            // eprintln!("BEGIN;");
-            self.conn.execute_batch("BEGIN;")?;
-            for q in &rule_queries {
-                // eprintln!("{q}");
-                let n_changed = self.conn.execute(q, [])?;
-                changed |= n_changed > 0;
+    /// Replace the program, e.g., to add rules to it. The facts given so far
+    /// are kept, along with the tables of relations that aren't in `prog`,
+    /// and the facts of `prog` are added to them. The next call to
+    /// [`Eval::go`] evaluates the new program from scratch.
+    ///
+    /// # Panics
+    ///
+    /// If `prog` declares a relation of the current program with different
+    /// columns.
+    pub fn set_program(&mut self, prog: Mir) -> Result<(), backend::Error> {
+        for (rel, decl) in prog.decls() {
+            match self.decls.get(&rel) {
+                Some(old) => assert_eq!(old.cols, decl.cols, "columns of `{rel}` changed"),
+                None => {
+                    self.backend.create_relation(&decl)?;
+                    self.decls.insert(rel, decl);
+                }
             }
-            // eprintln!("END;");
-            self.conn.execute_batch("END;")?;
-            if !changed {
-                break;
+        }
+        for (rel, facts) in prog.facts() {
+            self.backend
+                .insert(&self.decls[rel], facts.map(Vec::as_slice))?;
+        }
+        let clear: Vec<Query> = self.decls.values().map(backend::clear_derived).collect();
+        self.backend.batch(&clear)?;
+        self.prog = prog;
+        *self.marks.borrow_mut() = None;
+        Ok(())
+    }
+
+    /// The queries that evaluate `rule`: first against the full tables, then
+    /// one for each positive body atom, reading that atom from its `delta`
+    /// table (see [`eval_rule_query`]).
+    ///
+    /// # Panics
+    ///
+    /// If `rule` uses relations that aren't in the program, or isn't
+    /// range-restricted.
+    pub fn rule_sql(&self, rule: &Rule) -> Vec<Query> {
+        let dialect = self.backend.dialect();
+        let body: HashSet<&Rel> = rule.positive().map(|atom| &atom.rel).collect();
+        let mut queries = eval_rule_query(dialect, rule, None, &self.decls);
+        queries.extend(eval_rule_query(dialect, rule, Some(&body), &self.decls));
+        queries
+    }
+
+    /// The facts that match `atom`, i.e., that agree with its constants and
+    /// have equal values wherever it repeats a variable.
+    ///
+    /// Rather than evaluating the whole program, this evaluates a version
+    /// that's rewritten with magic sets to only derive facts that may be
+    /// relevant, using the constants of `atom`. The relations that are
+    /// negated or aggregated by the rewritten program are evaluated in full,
+    /// in their own tables, as [`Eval::go`] would. The other relations are
+    /// evaluated in temporary tables, so this doesn't require [`Eval::go`],
+    /// and only adds facts that it would derive anyway.
+    ///
+    /// Returns an error if `atom` doesn't match the relation's declaration.
+    pub fn query(&self, atom: &Atom) -> Result<HashSet<Vec<Const>>, backend::Error> {
+        let decl = self
+            .decls
+            .get(&atom.rel)
+            .ok_or_else(|| backend::Error::QueryRelation {
+                relation: atom.rel.clone(),
+            })?;
+        if decl.arity() != atom.terms.len() {
+            return Err(backend::Error::QueryArity {
+                relation: atom.rel.clone(),
+                arity: decl.arity(),
+            });
+        }
+        for (column, (col, t)) in decl.cols.iter().zip(&atom.terms).enumerate() {
+            if let Term::Const(c) = t {
+                if c.ty() != col.ty {
+                    return Err(backend::Error::QueryType {
+                        relation: atom.rel.clone(),
+                        column,
+                        expected: col.ty,
+                        found: c.ty(),
+                    });
+                }
             }
         }
-        Ok(iters)
+
+        let m = magic::rewrite(&self.prog, &self.decls, atom);
+        let mut all = self.decls.clone();
+        for decl in &m.decls {
+            self.backend.create_relation(decl)?;
+            all.insert(decl.rel.clone(), decl.clone());
+        }
+        let result = self.run_magic(m.rules, &all, &m.answer);
+        for decl in &m.decls {
+            self.backend.drop_relation(decl)?;
+        }
+        let facts = result?;
+
+        Ok(facts
+            .into_iter()
+            .filter(|fact| {
+                let mut vars: HashMap<&Var, &Const> = HashMap::default();
+                atom.terms.iter().zip(fact).all(|(t, c)| match t {
+                    Term::Const(k) => k == c,
+                    Term::Var(v) => *vars.entry(v).or_insert(c) == c,
+                    Term::Aggregate(_) => true,
+                })
+            })
+            .collect())
+    }
+
+    /// Evaluate the output of [`magic::rewrite`] and read the answers.
+    fn run_magic(
+        &self,
+        rules: Vec<Rule>,
+        decls: &HashMap<Rel, RelDecl>,
+        answer: &Rel,
+    ) -> Result<HashSet<Vec<Const>>, backend::Error> {
+        // Magic sets preserve validity, but a bug shouldn't leave the magic
+        // tables behind, see Eval::query
+        let magic = |source| backend::Error::Magic { source };
+        let ast = Ast::new_with_decls(decls.values().cloned().collect(), rules)
+            .map_err(|e| magic(e.into()))?;
+        let prog = Mir::new_unchecked(ast).map_err(magic)?;
+        for (rel, facts) in prog.facts() {
+            self.backend.insert(&decls[rel], facts.map(Vec::as_slice))?;
+        }
+        self.run(&prog, decls)?;
+        self.backend.read(&decls[answer])
     }
 
     /// The minimal Herbrand model (after calling [`Eval::go`]).
-    pub fn model(&self) -> Result<HashMap<Rel, HashSet<Vec<Const>>>> {
+    ///
+    /// Includes every relation, even those with no facts.
+    pub fn model(&self) -> Result<HashMap<Rel, HashSet<Vec<Const>>>, backend::Error> {
         let mut m = HashMap::default();
-        let mut arities = HashMap::default();
-        for (rel, mut facts) in self.prog.facts() {
-            m.entry(rel.clone()).or_insert_with(HashSet::default);
-            arities.insert(rel.clone(), facts.next().unwrap().len());
-        }
-        for rule in self.prog.rules() {
-            m.entry(rule.head.rel.clone())
-                .or_insert_with(HashSet::default);
-            arities.insert(rule.head.rel.clone(), rule.head.terms.len());
-        }
-        let keys = Vec::from_iter(m.keys().cloned());
-        for rel in keys {
-            let mut q = self
-                .conn
-                .prepare(&format!("SELECT DISTINCT * from {};", rel))
-                .unwrap();
-            let mut entries = q.query([]).unwrap();
-            let arity = *arities.get(&rel).unwrap();
-            while let Some(row) = entries.next()? {
-                let mut fact = Vec::with_capacity(arity);
-                for i in 0..arity {
-                    // + 2 for id, it
-                    fact.push(Const::new(row.get_unwrap(i + 2)).unwrap());
-                }
-                m.get_mut(&rel).unwrap().insert(fact);
-            }
+        for (rel, decl) in &self.decls {
+            m.insert(rel.clone(), self.backend.read(decl)?);
         }
         Ok(m)
     }
 
-    pub fn into_connection(self) -> Connection {
-        self.conn
+    /// The facts of one relation (after calling [`Eval::go`]).
+    ///
+    /// # Panics
+    ///
+    /// If `rel` isn't a relation of the program.
+    pub fn relation(&self, rel: &Rel) -> Result<HashSet<Vec<Const>>, backend::Error> {
+        self.backend.read(&self.decls[rel])
+    }
+
+    /// Write the facts of a relation to a file, without reading them into
+    /// memory. DuckDB writes CSV, Parquet, and JSON files, SQLite only CSV and
+    /// JSON files.
+    ///
+    /// # Panics
+    ///
+    /// If `rel` isn't a relation of the program.
+    pub fn export(
+        &self,
+        rel: &Rel,
+        path: impl AsRef<Path>,
+        format: ExportFormat,
+    ) -> Result<(), backend::Error> {
+        self.backend.export(&self.decls[rel], path.as_ref(), format)
+    }
+
+    pub fn program(&self) -> &Mir {
+        &self.prog
+    }
+
+    pub fn into_connection(self) -> B {
+        self.backend
     }
 
     pub fn into_program(self) -> Mir {
@@ -369,8 +920,14 @@ impl Eval {
 
 #[cfg(test)]
 mod tests {
-    use crate::ast::{Ast, Atom, Const, Rel, Rule, Term};
+    #[cfg(all(feature = "duckdb", not(feature = "sqlite")))]
+    use duckdb::Connection;
+    #[cfg(feature = "sqlite")]
+    use rusqlite::Connection;
+
+    use crate::ast::{Ast, Atom, Column, Const, Float, Rel, RelDecl, Rule, Term, Type, Var};
     use crate::mir::Mir;
+    use crate::parse::{parse, parse_rules};
 
     use super::*;
 
@@ -398,7 +955,7 @@ mod tests {
         let prog = Mir::new(Ast::new(vec![null_fact()]).unwrap()).unwrap();
         let conn = Connection::open_in_memory().unwrap();
         let eval = Eval::new(conn, prog).unwrap();
-        assert_eq!(1, eval.go().unwrap());
+        assert!(eval.go().unwrap().is_empty());
         let _m = eval.model().unwrap();
     }
 
@@ -407,7 +964,7 @@ mod tests {
         let prog = Mir::new(Ast::new(vec![unary_fact()]).unwrap()).unwrap();
         let conn = Connection::open_in_memory().unwrap();
         let eval = Eval::new(conn, prog).unwrap();
-        assert_eq!(1, eval.go().unwrap());
+        assert!(eval.go().unwrap().is_empty());
         let _m = eval.model().unwrap();
     }
 
@@ -448,7 +1005,1432 @@ mod tests {
         .unwrap();
         let conn = Connection::open_in_memory().unwrap();
         let eval = Eval::new(conn, prog).unwrap();
-        assert_eq!(2, eval.go().unwrap());
+        // Not recursive, so evaluated once
+        assert_eq!(
+            vec![(vec![Rel::new(String::from("s"))], 1)],
+            eval.go().unwrap()
+        );
         let _m = eval.model().unwrap();
     }
+
+    #[test]
+    fn test_ungrounded_rejected() {
+        // prog:
+        //
+        //   r.
+        //   s(X) :- r.
+        //
+        let ast = Ast::new_unchecked(vec![
+            null_fact(),
+            Rule::new(
+                Atom::new(
+                    Rel::new(String::from("s")),
+                    vec![Term::Var(Var::new_unchecked(String::from("X")))],
+                ),
+                vec![null_atom()],
+            ),
+        ]);
+        assert!(Mir::new(ast).is_err());
+
+        // prog:
+        //
+        //   s(X).
+        //
+        let ast = Ast::new_unchecked(vec![Rule::new(
+            Atom::new(
+                Rel::new(String::from("s")),
+                vec![Term::Var(Var::new_unchecked(String::from("X")))],
+            ),
+            Vec::new(),
+        )]);
+        assert!(Mir::new_unchecked(ast).is_err());
+    }
+
+    #[test]
+    fn test_mir_error_from_ast_error() {
+        // Every error converts, even those that Mir::new doesn't produce
+        let r = Rel::new(String::from("r"));
+        let e = crate::ast::Error::Redeclared {
+            relation: r.clone(),
+            span1: None,
+            span2: None,
+        };
+        assert_eq!(
+            crate::mir::Error::Redeclared {
+                relation: r.clone()
+            },
+            crate::mir::Error::from(e)
+        );
+        let e = crate::ast::Error::Column {
+            relation: r.clone(),
+            name: String::from("1"),
+            span: None,
+        };
+        assert_eq!(
+            "invalid column name `1` in declaration of relation `r`",
+            crate::mir::Error::from(e).to_string()
+        );
+    }
+
+    #[test]
+    fn test_infinite_head_const() {
+        // prog:
+        //
+        //   r(c).
+        //   s(inf) :- r(X).
+        //
+        let inf = Const::Float(Float(f64::INFINITY));
+        let ast = Ast::new(vec![
+            unary_fact(),
+            Rule::new(
+                Atom::new(Rel::new(String::from("s")), vec![Term::Const(inf.clone())]),
+                vec![Atom::new(
+                    Rel::new(String::from("r")),
+                    vec![Term::Var(Var::new_unchecked(String::from("X")))],
+                )],
+            ),
+        ])
+        .unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        let eval = Eval::new(conn, Mir::new(ast).unwrap()).unwrap();
+        eval.go().unwrap();
+        let m = eval.model().unwrap();
+        assert_eq!(
+            HashSet::from_iter([vec![inf]]),
+            m[&Rel::new(String::from("s"))]
+        );
+    }
+
+    #[test]
+    fn test_exact_head_const() {
+        // SQLite parses the literal to a different float
+        let m = eval("r(1). s(X, 4.6967140264688e-299) :- r(X).");
+        let x = Const::Float(Float(4.6967140264688e-299));
+        assert_eq!(
+            HashSet::from_iter([vec![Const::Int(1), x]]),
+            m[&Rel::new(String::from("s"))]
+        );
+    }
+
+    fn eval(src: &str) -> HashMap<Rel, HashSet<Vec<Const>>> {
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        let eval = Eval::new(conn, prog).unwrap();
+        eval.go().unwrap();
+        eval.model().unwrap()
+    }
+
+    fn tuples(m: &HashMap<Rel, HashSet<Vec<Const>>>, rel: &str) -> Vec<Vec<String>> {
+        let mut tuples: Vec<Vec<String>> = m
+            .get(&Rel::new(String::from(rel)))
+            .unwrap()
+            .iter()
+            .map(|t| t.iter().map(|c| c.to_string()).collect())
+            .collect();
+        tuples.sort();
+        tuples
+    }
+
+    #[test]
+    fn test_body_const() {
+        let m = eval(
+            r"
+            edge(a, b).
+            edge(b, c).
+            edge(a, d).
+            r(X) :- edge(a, X).
+            ",
+        );
+        assert_eq!(vec![vec!["b"], vec!["d"]], tuples(&m, "r"));
+    }
+
+    #[test]
+    fn test_body_repeated_var() {
+        let m = eval(
+            r"
+            edge(a, a).
+            edge(a, b).
+            edge(c, c).
+            r(X) :- edge(X, X).
+            ",
+        );
+        assert_eq!(vec![vec!["a"], vec!["c"]], tuples(&m, "r"));
+    }
+
+    #[test]
+    fn test_body_const_join() {
+        let m = eval(
+            r"
+            edge(a, b).
+            edge(b, c).
+            edge(c, d).
+            r(Y) :- edge(X, Y), edge(b, X).
+            ",
+        );
+        assert_eq!(vec![vec!["d"]], tuples(&m, "r"));
+    }
+
+    #[test]
+    fn test_head_const() {
+        let m = eval(
+            r"
+            edge(a, b).
+            r(X, c) :- edge(X, b).
+            ",
+        );
+        assert_eq!(vec![vec!["a", "c"]], tuples(&m, "r"));
+    }
+
+    #[test]
+    fn test_typed() {
+        let m = eval(
+            r#"
+            r(1, 2.5, true, "x y").
+            r(2, -0.5, false, z).
+            s(X, Y, Z, W) :- r(X, Y, Z, W).
+            t(W) :- r(2, Y, false, W).
+            "#,
+        );
+        let s = m.get(&Rel::new(String::from("s"))).unwrap();
+        assert!(s.contains(&vec![
+            Const::Int(1),
+            Const::Float(Float(2.5)),
+            Const::Bool(true),
+            Const::Sym(String::from("x y")),
+        ]));
+        assert!(s.contains(&vec![
+            Const::Int(2),
+            Const::Float(Float(-0.5)),
+            Const::Bool(false),
+            Const::Sym(String::from("z")),
+        ]));
+        assert_eq!(vec![vec!["z"]], tuples(&m, "t"));
+    }
+
+    #[test]
+    fn test_body_only_relation() {
+        // `s` has no facts and is never in a head
+        let m = eval("r(X) :- s(X).");
+        assert!(m.get(&Rel::new(String::from("r"))).unwrap().is_empty());
+        assert!(m.get(&Rel::new(String::from("s"))).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_negation() {
+        let m = eval(
+            r"
+            node(a). node(b). node(c). node(d).
+            edge(a, b). edge(b, c).
+            reach(a).
+            reach(Y) :- reach(X), edge(X, Y).
+            unreachable(X) :- node(X), !reach(X).
+            ",
+        );
+        assert_eq!(vec![vec!["d"]], tuples(&m, "unreachable"));
+    }
+
+    #[test]
+    fn test_negation_wildcard() {
+        let m = eval(
+            r"
+            node(a). node(b). node(c).
+            edge(a, b). edge(b, c).
+            sink(X) :- node(X), !edge(X, _).
+            source(X) :- node(X), !edge(_, X).
+            lonely(X) :- node(X), !edge(X, _), !edge(_, X).
+            ",
+        );
+        assert_eq!(vec![vec!["c"]], tuples(&m, "sink"));
+        assert_eq!(vec![vec!["a"]], tuples(&m, "source"));
+        assert!(tuples(&m, "lonely").is_empty());
+    }
+
+    #[test]
+    fn test_negation_strata() {
+        // Three strata: `b` needs all of `a`, `c` needs all of `b`
+        let m = eval(
+            r"
+            n(1). n(2). n(3). n(4).
+            succ(1, 2). succ(2, 3). succ(3, 4).
+            a(1).
+            a(Y) :- a(X), succ(X, Y), !stop(Y).
+            stop(3).
+            b(X) :- n(X), !a(X).
+            c(X) :- n(X), !b(X), !stop(X).
+            odd(X) :- b(X), !even(X, 0).
+            even(4, 0).
+            ",
+        );
+        assert_eq!(vec![vec!["1"], vec!["2"]], tuples(&m, "a"));
+        assert_eq!(vec![vec!["3"], vec!["4"]], tuples(&m, "b"));
+        assert_eq!(vec![vec!["1"], vec!["2"]], tuples(&m, "c"));
+        assert_eq!(vec![vec!["3"]], tuples(&m, "odd"));
+    }
+
+    #[test]
+    fn test_negation_nullary() {
+        let m = eval("p :- !q. r :- !p. s(a). t(X) :- s(X), !q.");
+        assert_eq!(1, tuples(&m, "p").len());
+        assert!(tuples(&m, "r").is_empty());
+        assert_eq!(vec![vec!["a"]], tuples(&m, "t"));
+    }
+
+    #[test]
+    fn test_aggregate_count() {
+        let m = eval(
+            r"
+            edge(a, b). edge(a, c). edge(b, c).
+            deg(X, count(Y)) :- edge(X, Y).
+            edges(count(X)) :- edge(X, Y).
+            none(count(X)) :- edge(X, X).
+            ",
+        );
+        assert_eq!(vec![vec!["a", "2"], vec!["b", "1"]], tuples(&m, "deg"));
+        // Set semantics: counts distinct assignments of the body's variables
+        assert_eq!(vec![vec!["3"]], tuples(&m, "edges"));
+        assert!(tuples(&m, "none").is_empty());
+    }
+
+    #[test]
+    fn test_aggregate_sum_min_max() {
+        let m = eval(
+            r"
+            salary(alice, eng, 10). salary(bob, eng, 10). salary(carol, ops, 7).
+            weight(a, 0.5). weight(b, 1.25).
+            total(D, sum(S)) :- salary(P, D, S).
+            lowest(D, min(P)) :- salary(P, D, S).
+            highest(max(S)) :- salary(P, D, S).
+            mass(sum(W)) :- weight(X, W).
+            big(D) :- total(D, S), salary(P, D, S2), !small(D).
+            small(ops).
+            ",
+        );
+        assert_eq!(
+            vec![vec!["eng", "20"], vec!["ops", "7"]],
+            tuples(&m, "total")
+        );
+        assert_eq!(
+            vec![vec!["eng", "alice"], vec!["ops", "carol"]],
+            tuples(&m, "lowest")
+        );
+        assert_eq!(vec![vec!["10"]], tuples(&m, "highest"));
+        assert_eq!(vec![vec!["1.75"]], tuples(&m, "mass"));
+        assert_eq!(vec![vec!["eng"]], tuples(&m, "big"));
+    }
+
+    #[test]
+    fn test_aggregate_recursive_body() {
+        // The aggregate waits until `path` is fully computed
+        let m = eval(
+            r"
+            edge(1, 2). edge(2, 3). edge(3, 4).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            reach(X, count(Y)) :- path(X, Y).
+            ",
+        );
+        assert_eq!(
+            vec![vec!["1", "3"], vec!["2", "2"], vec!["3", "1"]],
+            tuples(&m, "reach")
+        );
+    }
+
+    #[test]
+    fn test_aggregate_body() {
+        let m = eval(
+            r"
+            edge(a, b). edge(a, c). edge(b, c). node(a). node(b). node(c).
+            w(a, 1). w(b, 2). w(c, 4).
+            n(X, C) :- node(X), C = count : { edge(X, _) }.
+            edges(C) :- C = count : edge(_, _).
+            loops(C) :- C = count : edge(X, X).
+            busy(X) :- node(X), C = count : { edge(X, Y), node(Y) }, C > 1.
+            isolated(X) :- node(X), C = count : { edge(X, _) }, C = 0.
+            out(X, S) :- node(X), S = sum W : { edge(X, Y), w(Y, W) }.
+            heaviest(X, M) :- node(X), M = max W : { edge(X, Y), w(Y, W) }.
+            lightest(M) :- M = min W : { w(X, W), !edge(X, X) }.
+            ",
+        );
+        // As in Soufflé, `count` and `sum` of nothing are 0
+        assert_eq!(
+            vec![vec!["a", "2"], vec!["b", "1"], vec!["c", "0"]],
+            tuples(&m, "n")
+        );
+        assert_eq!(vec![vec!["3"]], tuples(&m, "edges"));
+        assert_eq!(vec![vec!["0"]], tuples(&m, "loops"));
+        assert_eq!(vec![vec!["a"]], tuples(&m, "busy"));
+        assert_eq!(vec![vec!["c"]], tuples(&m, "isolated"));
+        assert_eq!(
+            vec![vec!["a", "6"], vec!["b", "4"], vec!["c", "0"]],
+            tuples(&m, "out")
+        );
+        // ... but there's no `min` or `max` of nothing
+        assert_eq!(vec![vec!["a", "4"], vec!["b", "4"]], tuples(&m, "heaviest"));
+        assert_eq!(vec![vec!["1"]], tuples(&m, "lightest"));
+    }
+
+    #[test]
+    fn test_aggregate_body_float_sum() {
+        let m = eval(
+            r"
+            node(a). node(b). w(a, 0.5). w(a, 1.25).
+            mass(X, S) :- node(X), S = sum W : w(X, W).
+            half(X, H) :- node(X), S = sum W : w(X, W), H = (S + 1) / 2.
+            ",
+        );
+        assert_eq!(
+            vec![vec!["a", "1.75"], vec!["b", "0.0"]],
+            tuples(&m, "mass")
+        );
+        assert_eq!(
+            vec![vec!["a", "1.375"], vec!["b", "0.5"]],
+            tuples(&m, "half")
+        );
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let m = eval(
+            r"
+            edge(a, b). edge(b, a). edge(b, c). edge(c, c).
+            other(X, Y) :- edge(X, Y), X != Y.
+            n(1). n(2). n(3). n(4).
+            small(X) :- n(X), X < 3.
+            mid(X) :- n(X), 1 < X, X <= 3.
+            twin(X, Y) :- n(X), n(Y), X = Y.
+            ",
+        );
+        assert_eq!(
+            vec![vec!["a", "b"], vec!["b", "a"], vec!["b", "c"]],
+            tuples(&m, "other")
+        );
+        assert_eq!(vec![vec!["1"], vec!["2"]], tuples(&m, "small"));
+        assert_eq!(vec![vec!["2"], vec!["3"]], tuples(&m, "mid"));
+        assert_eq!(4, tuples(&m, "twin").len());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let m = eval(
+            r"
+            n(0).
+            n(M) :- n(N), M = N + 1, N < 5.
+            half(N, H) :- n(N), H = N / 2.
+            inv(N, I) :- n(N), I = 6 / N.
+            scaled(X) :- n(N), X = N * 0.5, X >= 2.
+            sq(N) :- n(N), N * N = N + N.
+            ",
+        );
+        assert_eq!(6, tuples(&m, "n").len());
+        // Integer division truncates
+        assert!(tuples(&m, "half").contains(&vec![String::from("5"), String::from("2")]));
+        // Division by zero has no result
+        assert_eq!(5, tuples(&m, "inv").len());
+        assert_eq!(vec![vec!["2.0"], vec!["2.5"]], tuples(&m, "scaled"));
+        assert_eq!(vec![vec!["0"], vec!["2"]], tuples(&m, "sq"));
+    }
+
+    #[test]
+    fn test_adversarial_consts() {
+        let evil = "x'); DROP TABLE edge; --";
+        let src = format!(
+            r#"
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+            from_evil(Y) :- path({evil:?}, Y).
+            named(X, "o'brien") :- edge(X, "o'brien"), X != "a'b".
+            "#
+        );
+        let mut prog = Mir::new(parse(&src).unwrap()).unwrap();
+        let edge = Rel::new(String::from("edge"));
+        let sym = |s: &str| Const::Sym(String::from(s));
+        prog.add_fact(&edge, vec![sym("a"), sym("o'brien")]);
+        prog.add_fact(&edge, vec![sym("o'brien"), sym(evil)]);
+        prog.add_fact(&edge, vec![sym(evil), sym("\"quoted\"")]);
+        let conn = Connection::open_in_memory().unwrap();
+        let eval = Eval::new(conn, prog).unwrap();
+        eval.go().unwrap();
+        let m = eval.model().unwrap();
+        assert_eq!(3, tuples(&m, "edge").len());
+        assert_eq!(6, tuples(&m, "path").len());
+        assert!(m
+            .get(&Rel::new(String::from("path")))
+            .unwrap()
+            .contains(&vec![sym("a"), sym(evil)]));
+        assert_eq!(vec![vec!["\"\\\"quoted\\\"\""]], tuples(&m, "from_evil"));
+        assert_eq!(vec![vec!["a", "\"o'brien\""]], tuples(&m, "named"));
+    }
+
+    #[test]
+    fn test_keyword_names() {
+        // Relation and column names are quoted
+        let decl = RelDecl::new(
+            Rel::new(String::from("select")),
+            vec![
+                Column::new(String::from("from"), Type::Int),
+                Column::new(String::from("order"), Type::Int),
+            ],
+        );
+        let rules = parse_rules(
+            r"
+            select(1, 2).
+            between(X) :- select(X, Y), !table(X).
+            table(3).
+            ",
+        )
+        .unwrap();
+        let prog = Mir::new(Ast::new_with_decls(vec![decl], rules).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        eval.go().unwrap();
+        let m = eval.model().unwrap();
+        assert_eq!(vec![vec!["1"]], tuples(&m, "between"));
+    }
+
+    #[test]
+    fn test_decl_columns() {
+        // .decl edge(src: int, dst: int)
+        // edge(1, 2).
+        // path(X, Y) :- edge(X, Y).
+        let edge = Rel::new(String::from("edge"));
+        let path = Rel::new(String::from("path"));
+        let x = Term::Var(Var::new_unchecked(String::from("X")));
+        let y = Term::Var(Var::new_unchecked(String::from("Y")));
+        let decl = RelDecl::new(
+            edge.clone(),
+            vec![
+                Column::new(String::from("src"), Type::Int),
+                Column::new(String::from("dst"), Type::Int),
+            ],
+        );
+        let ast = Ast::new_with_decls(
+            vec![decl],
+            vec![
+                Rule::new(
+                    Atom::new(
+                        edge.clone(),
+                        vec![Term::Const(Const::Int(1)), Term::Const(Const::Int(2))],
+                    ),
+                    Vec::new(),
+                ),
+                Rule::new(
+                    Atom::new(path.clone(), vec![x.clone(), y.clone()]),
+                    vec![Atom::new(edge, vec![x, y])],
+                ),
+            ],
+        )
+        .unwrap();
+        let conn = Connection::open_in_memory().unwrap();
+        let eval = Eval::new(conn, Mir::new(ast).unwrap()).unwrap();
+        eval.go().unwrap();
+        let m = eval.model().unwrap();
+        assert!(m
+            .get(&path)
+            .unwrap()
+            .contains(&vec![Const::Int(1), Const::Int(2)]));
+        let conn = eval.into_connection();
+        let mut q = conn.prepare("SELECT dst FROM edge WHERE src = 1;").unwrap();
+        let dst: i64 = q.query([]).unwrap().next().unwrap().unwrap().get_unwrap(0);
+        assert_eq!(2, dst);
+    }
+
+    #[test]
+    fn test_delta_tables() {
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        assert_eq!(
+            vec![(vec![Rel::new(String::from("path"))], 4)],
+            eval.go().unwrap()
+        );
+        assert_eq!(6, tuples(&eval.model().unwrap(), "path").len());
+        let conn = eval.into_connection();
+        let count = |table: &str| -> i64 {
+            conn.query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |r| r.get(0))
+                .unwrap()
+        };
+        // The last iteration derived nothing new
+        assert_eq!(0, count(r#""new:path""#));
+        assert_eq!(0, count(r#""delta:path""#));
+        assert_eq!(6, count("path"));
+    }
+
+    /// Relations whose names look like the names of the indices and
+    /// sequences of other relations
+    fn check_index_names<B: Backend>(backend: B) {
+        let prog = Mir::new(
+            parse(
+                r"
+                a(1, 2). a_1(3, 4). a_idx(5). a_seq(6). a_0_idx(7).
+                b(X, Y) :- a(X, Y).
+                b(X, Y) :- a_1(X, Y).
+                c(X) :- a_idx(X).
+                c(X) :- a_seq(X).
+                c(X) :- a_0_idx(X).
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let eval = Eval::new(backend, prog).unwrap();
+        eval.go().unwrap();
+        let m = eval.model().unwrap();
+        assert_eq!(2, tuples(&m, "b").len());
+        assert_eq!(3, tuples(&m, "c").len());
+    }
+
+    #[test]
+    fn test_index_names() {
+        check_index_names(Connection::open_in_memory().unwrap());
+    }
+
+    #[cfg(all(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn test_index_names_duckdb() {
+        check_index_names(::duckdb::Connection::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_go_after_error() {
+        // SQLite's `sum` fails on overflow
+        let prog = Mir::new(
+            parse(
+                r"
+                n(9223372036854775807). n(1).
+                total(sum(X)) :- n(X).
+                path(X, Y) :- n(X), n(Y).
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        assert!(eval.go().is_err());
+        // The failed transaction was rolled back, so the connection is usable
+        let n = Rel::new(String::from("n"));
+        eval.retract_facts(&n, [&[Const::Int(i64::MAX)][..]])
+            .unwrap();
+        eval.insert_facts(&n, [&[Const::Int(2)][..]]).unwrap();
+        eval.go().unwrap();
+        let m = eval.model().unwrap();
+        assert_eq!(vec![vec!["3"]], tuples(&m, "total"));
+        assert_eq!(4, tuples(&m, "path").len());
+    }
+
+    const TC: &str = r"
+        edge(a, b). edge(b, c). edge(c, d).
+        path(X, Y) :- edge(X, Y).
+        path(X, Z) :- path(X, Y), edge(Y, Z).
+    ";
+
+    /// `path` with the iteration in which each fact was derived
+    macro_rules! stamped_paths {
+        ($conn:expr) => {
+            $conn
+                .prepare(r#"SELECT it, "x0", "x1" FROM path"#)
+                .unwrap()
+                .query_map([], |r| {
+                    Ok((
+                        r.get::<_, i64>(0)?,
+                        r.get::<_, String>(1)?,
+                        r.get::<_, String>(2)?,
+                    ))
+                })
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect::<std::collections::BTreeSet<_>>()
+        };
+    }
+
+    #[test]
+    fn test_iterations() {
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        eval.go().unwrap();
+        let conn = eval.into_connection();
+        let stamped = stamped_paths!(conn);
+        for (it, x, y) in stamped {
+            // Paths of length `n` are derived in iteration `n`
+            let len = y.as_bytes()[0] - x.as_bytes()[0];
+            assert_eq!(i64::from(len), it, "{x} -> {y}");
+        }
+    }
+
+    #[cfg(all(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn test_duckdb_same_as_sqlite() {
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let duck = Eval::new(
+            ::duckdb::Connection::open_in_memory().unwrap(),
+            prog.clone(),
+        )
+        .unwrap();
+        let lite = Eval::new(::rusqlite::Connection::open_in_memory().unwrap(), prog).unwrap();
+        assert_eq!(lite.go().unwrap(), duck.go().unwrap());
+        assert_eq!(lite.model().unwrap(), duck.model().unwrap());
+        let (duck, lite) = (duck.into_connection(), lite.into_connection());
+        assert_eq!(stamped_paths!(lite), stamped_paths!(duck));
+    }
+
+    #[test]
+    fn test_sccs() {
+        let prog = Mir::new(
+            parse(
+                r"
+                succ(0, 1). succ(1, 2). succ(2, 3). succ(3, 4).
+                even(0).
+                even(Y) :- odd(X), succ(X, Y).
+                odd(Y) :- even(X), succ(X, Y).
+                small(X) :- even(X), X < 3.
+                big(X) :- succ(Y, X), !small(X).
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        let mut iters: Vec<(Vec<String>, usize)> = eval
+            .go()
+            .unwrap()
+            .into_iter()
+            .map(|(rels, n)| {
+                let mut rels: Vec<_> = rels.iter().map(ToString::to_string).collect();
+                rels.sort();
+                (rels, n)
+            })
+            .collect();
+        iters.sort();
+        assert_eq!(
+            vec![
+                (vec![String::from("big")], 1),
+                (vec![String::from("even"), String::from("odd")], 5),
+                (vec![String::from("small")], 1),
+            ],
+            iters
+        );
+        let m = eval.model().unwrap();
+        assert_eq!(vec![vec!["1"], vec!["3"], vec!["4"]], tuples(&m, "big"));
+    }
+
+    /// Check that [`Eval::query`] agrees with [`Eval::go`] on each query,
+    /// which is written as a fact (e.g., `path(a, X) :- .`)
+    fn check_queries(src: &str, queries: &str) {
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog.clone()).unwrap();
+        let full = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        full.go().unwrap();
+        let m = full.model().unwrap();
+        for rule in parse_rules(queries).unwrap() {
+            let q = rule.head;
+            let expected: HashSet<Vec<Const>> = m
+                .get(&q.rel)
+                .unwrap()
+                .iter()
+                .filter(|fact| {
+                    q.terms.iter().zip(fact.iter()).all(|(t, c)| match t {
+                        Term::Const(k) => k == c,
+                        Term::Var(v) => q
+                            .terms
+                            .iter()
+                            .zip(fact.iter())
+                            .all(|(t2, c2)| t2.var() != Some(v) || c2 == c),
+                        Term::Aggregate(_) => true,
+                    })
+                })
+                .cloned()
+                .collect();
+            assert_eq!(expected, eval.query(&q).unwrap(), "{q}");
+            // Temporary tables are dropped
+            assert_eq!(expected, eval.query(&q).unwrap(), "{q}");
+        }
+    }
+
+    #[test]
+    fn test_query_tc() {
+        check_queries(
+            TC,
+            r"
+            path(a, X) :- .
+            path(X, d) :- .
+            path(b, c) :- .
+            path(c, a) :- .
+            path(X, Y) :- .
+            path(X, X) :- .
+            edge(b, X) :- .
+            ",
+        );
+    }
+
+    #[test]
+    fn test_query_relevant() {
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        let q = parse_rules("path(c, X) :- .").unwrap().remove(0).head;
+        let answers = eval.query(&q).unwrap();
+        assert_eq!(
+            HashSet::from_iter([vec![
+                Const::Sym(String::from("c")),
+                Const::Sym(String::from("d"))
+            ]]),
+            answers
+        );
+        // Nothing was derived in the original tables
+        assert!(eval.model().unwrap()[&Rel::new(String::from("path"))].is_empty());
+    }
+
+    #[test]
+    fn test_query_mismatch() {
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        for (q, err) in [
+            ("path(a) :- .", "relation `path` has arity 2"),
+            (
+                "path(1, X) :- .",
+                "column 0 of relation `path` has type `symbol`, not `int`",
+            ),
+            ("nope(X) :- .", "unknown relation `nope`"),
+        ] {
+            let q = parse_rules(q).unwrap().remove(0).head;
+            assert_eq!(err, eval.query(&q).unwrap_err().to_string(), "{q}");
+        }
+    }
+
+    #[test]
+    fn test_query_after_error() {
+        // SQLite's `sum` fails on overflow
+        let prog = Mir::new(
+            parse(
+                r"
+                n(9223372036854775807). n(1).
+                total(T) :- T = sum X : { n(X) }.
+                big(T) :- total(T), T > 0.
+                ",
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        let q = parse_rules("big(X) :- .").unwrap().remove(0).head;
+        assert!(eval.query(&q).is_err());
+        // The magic tables were dropped, so the same query can run again
+        let n = Rel::new(String::from("n"));
+        eval.retract_facts(&n, [&[Const::Int(i64::MAX)][..]])
+            .unwrap();
+        assert_eq!(
+            HashSet::from_iter([vec![Const::Int(1)]]),
+            eval.query(&q).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_query_negation_aggregate() {
+        check_queries(
+            r"
+            edge(a, b). edge(b, c). edge(c, d). edge(d, b).
+            node(a). node(b). node(c). node(d). node(e).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            unreachable(X, Y) :- node(X), node(Y), !path(X, Y).
+            out(X, count(Y)) :- path(X, Y).
+            busy(X) :- out(X, N), N > 2.
+            far(X, Z) :- unreachable(X, Y), path(Y, Z), Z != X.
+            root(X) :- node(X), !edge(_, X), path(X, _).
+            fanout(X, C) :- node(X), C = count : edge(X, _).
+            ",
+            r"
+            root(X) :- .
+            root(a) :- .
+            fanout(e, C) :- .
+            fanout(X, 0) :- .
+            unreachable(a, X) :- .
+            unreachable(X, a) :- .
+            out(b, N) :- .
+            busy(X) :- .
+            far(e, X) :- .
+            far(X, b) :- .
+            ",
+        );
+    }
+
+    #[test]
+    fn test_query_arith() {
+        check_queries(
+            r"
+            nat(0).
+            nat(Y) :- nat(X), X < 10, Y = X + 1.
+            even(X) :- nat(X), H = X / 2, X = H * 2.
+            twice(X, Y) :- nat(X), Y = X * 2, even(Y).
+            ",
+            r"
+            nat(3) :- .
+            nat(X) :- .
+            even(4) :- .
+            twice(X, 8) :- .
+            twice(3, Y) :- .
+            ",
+        );
+    }
+
+    fn syms(facts: &[&[&str]]) -> Vec<Vec<Const>> {
+        facts
+            .iter()
+            .map(|f| f.iter().map(|s| Const::Sym(String::from(*s))).collect())
+            .collect()
+    }
+
+    /// Evaluate `src`, add `facts` to `rel`, and check that [`Eval::go`]
+    /// agrees with evaluating everything at once. Returns the result of the
+    /// second [`Eval::go`].
+    fn check_insert(src: &str, rel: &str, facts: &[Vec<Const>]) -> Vec<(Vec<Rel>, usize)> {
+        let rel = Rel::new(String::from(rel));
+        let mut prog = Mir::new(parse(src).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog.clone()).unwrap();
+        eval.go().unwrap();
+        eval.insert_facts(&rel, facts.iter().map(Vec::as_slice))
+            .unwrap();
+        let iters = eval.go().unwrap();
+
+        for fact in facts {
+            prog.add_fact(&rel, fact.clone());
+        }
+        let full = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        full.go().unwrap();
+        assert_eq!(full.model().unwrap(), eval.model().unwrap());
+        iters
+    }
+
+    #[test]
+    fn test_insert_facts() {
+        let iters = check_insert(TC, "edge", &syms(&[&["d", "e"]]));
+        // The paths that end in `e` are all derived in the first iteration
+        assert_eq!(vec![(vec![Rel::new(String::from("path"))], 2)], iters);
+
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        eval.go().unwrap();
+        // Nothing new
+        assert!(eval.go().unwrap().is_empty());
+        // Nothing new, either
+        let facts = syms(&[&["a", "b"]]);
+        eval.insert_facts(
+            &Rel::new(String::from("edge")),
+            facts.iter().map(Vec::as_slice),
+        )
+        .unwrap();
+        assert!(eval.go().unwrap().is_empty());
+        let facts = syms(&[&["d", "e"]]);
+        eval.insert_facts(
+            &Rel::new(String::from("edge")),
+            facts.iter().map(Vec::as_slice),
+        )
+        .unwrap();
+        eval.go().unwrap();
+        // Stamps keep increasing
+        let stamps = stamped_paths!(eval.into_connection());
+        assert!(stamps.contains(&(3, String::from("a"), String::from("d"))));
+        assert!(stamps.contains(&(5, String::from("a"), String::from("e"))));
+    }
+
+    #[test]
+    fn test_set_program() {
+        let src = r"
+            edge(a, b). edge(b, c).
+            node(a). node(b). node(c).
+            reach(X) :- edge(a, X).
+            out(X) :- node(X), !reach(X).
+            ";
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let mut e = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        e.go().unwrap();
+        assert_eq!(
+            vec![vec!["a"], vec!["c"]],
+            tuples(&e.model().unwrap(), "out")
+        );
+        // More rules, with new relations, and more facts. `out(c)` is no
+        // longer derivable.
+        let more = r"
+            edge(c, d). node(d).
+            reach(Y) :- reach(X), edge(X, Y).
+            sink(X) :- node(X), !src(X).
+            src(X) :- edge(X, Y).
+            ";
+        let rules = parse_rules(&format!("{src}{more}")).unwrap();
+        let prog = Mir::new(Ast::new(rules).unwrap()).unwrap();
+        e.set_program(prog).unwrap();
+        e.go().unwrap();
+        assert_eq!(eval(&format!("{src}{more}")), e.model().unwrap());
+        assert!(e.go().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_insert_facts_derived() {
+        // Facts of a relation with rules, some of which were already derived
+        check_insert(TC, "path", &syms(&[&["d", "a"], &["a", "c"]]));
+    }
+
+    #[test]
+    fn test_insert_facts_negation() {
+        let src = r"
+            edge(a, b). edge(b, c).
+            node(a). node(b). node(c). node(d).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            unreachable(X, Y) :- node(X), node(Y), !path(X, Y).
+            far(X, count(Y)) :- unreachable(X, Y).
+            lonely(X) :- node(X), !edge(X, X).
+            ";
+        check_insert(src, "edge", &syms(&[&["c", "d"], &["d", "d"]]));
+        check_insert(src, "node", &syms(&[&["e"]]));
+        check_insert(src, "path", &syms(&[&["a", "d"]]));
+    }
+
+    #[test]
+    fn test_insert_facts_body_aggregate() {
+        // Counts go from 0 to 1, and `isolated` loses facts
+        let src = r"
+            edge(a, b).
+            node(a). node(b). node(c).
+            out(X, C) :- node(X), C = count : { edge(X, _) }.
+            isolated(X) :- node(X), C = count : { edge(X, _) }, C = 0.
+            ";
+        check_insert(src, "edge", &syms(&[&["b", "c"]]));
+        check_insert(src, "node", &syms(&[&["d"]]));
+    }
+
+    #[test]
+    fn test_insert_facts_negation_redone() {
+        // `r` is evaluated again from scratch and loses `r(a)` without
+        // gaining any facts, so `s` has to be evaluated again, too
+        let src = r"
+            a(a). b(c). c(a).
+            r(X) :- a(X), !b(X).
+            s(X) :- c(X), !r(X).
+            ";
+        check_insert(src, "b", &syms(&[&["a"]]));
+    }
+
+    /// A fresh directory containing `files`
+    fn dir(name: &str, files: &[(&str, &str)]) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("duckalog-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for (file, contents) in files {
+            std::fs::write(dir.join(file), contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_load() {
+        let src = r"
+            .decl edge(src: symbol, dst: symbol)
+            .decl weight(src: symbol, w: number)
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            heavy(X) :- weight(X, W), W > 5.
+            ";
+        let d = dir(
+            "load",
+            &[
+                ("edge.csv", "a,b\nb,c\n"),
+                ("weight.tsv", "w\tignored\tsrc\n3\tx\ta\n7\ty\tb\n"),
+                ("more.csv", "c,d\n"),
+            ],
+        );
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        eval.load(
+            &Rel::new(String::from("edge")),
+            &Source::csv(d.join("edge.csv")),
+        )
+        .unwrap();
+        // Columns in a different order, by name
+        let source = Source::csv(d.join("weight.tsv"))
+            .with_delimiter(b'\t')
+            .with_header(true)
+            .with_columns(vec![String::from("src"), String::from("w")]);
+        eval.load(&Rel::new(String::from("weight")), &source)
+            .unwrap();
+        eval.go().unwrap();
+        let model = eval.model().unwrap();
+        assert_eq!(
+            vec![vec!["a", "b"], vec!["a", "c"], vec!["b", "c"]],
+            tuples(&model, "path")
+        );
+        assert_eq!(vec![vec!["b"]], tuples(&model, "heavy"));
+
+        // Loaded facts are propagated incrementally, like inserted ones
+        eval.load(
+            &Rel::new(String::from("edge")),
+            &Source::csv(d.join("more.csv")),
+        )
+        .unwrap();
+        eval.go().unwrap();
+        assert_eq!(6, tuples(&eval.model().unwrap(), "path").len());
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    /// Load symbols that look like other types, then a file with a bad value
+    /// after more facts than are parsed at once, which leaves the relation
+    /// unchanged
+    fn check_load_values<B: Backend>(backend: B, name: &str) {
+        let mut bad = String::new();
+        for i in 0..70000 {
+            bad.push_str(&format!("s{i},{i}\n"));
+        }
+        bad.push_str("s,x\n");
+        let d = dir(
+            name,
+            &[("r.csv", "1.50,1\n007,2\ntrue,3\n"), ("bad.csv", &bad)],
+        );
+        let prog = Mir::new(parse(".decl r(s: symbol, n: number)").unwrap()).unwrap();
+        let eval = Eval::new(backend, prog).unwrap();
+        let r = Rel::new(String::from("r"));
+        eval.load(&r, &Source::csv(d.join("r.csv"))).unwrap();
+        let facts = eval.relation(&r).unwrap();
+        assert_eq!(
+            HashSet::from_iter([
+                vec![Const::Sym(String::from("1.50")), Const::Int(1)],
+                vec![Const::Sym(String::from("007")), Const::Int(2)],
+                vec![Const::Sym(String::from("true")), Const::Int(3)],
+            ]),
+            facts
+        );
+        assert!(eval.load(&r, &Source::csv(d.join("bad.csv"))).is_err());
+        assert_eq!(facts, eval.relation(&r).unwrap());
+        // Nothing is left over to be loaded later
+        eval.load(&r, &Source::csv(d.join("r.csv"))).unwrap();
+        assert_eq!(facts, eval.relation(&r).unwrap());
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    #[test]
+    fn test_load_values() {
+        check_load_values(Connection::open_in_memory().unwrap(), "load-values");
+    }
+
+    #[cfg(all(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn test_load_values_duckdb() {
+        check_load_values(
+            ::duckdb::Connection::open_in_memory().unwrap(),
+            "load-values-duckdb",
+        );
+    }
+
+    /// Facts of the wrong arity or types are rejected by the backend, before
+    /// any of them are added
+    fn check_insert_errors<B: Backend>(backend: B) {
+        let prog = Mir::new(parse(&format!("{TC} .decl unit()")).unwrap()).unwrap();
+        let eval = Eval::new(backend, prog).unwrap();
+        let edge = Rel::new(String::from("edge"));
+        let bad = [Const::Int(7), Const::Bool(true)];
+        assert!(matches!(
+            eval.insert_facts(&edge, [&bad[..]]),
+            Err(backend::Error::FactType {
+                column: 0,
+                expected: Type::Sym,
+                found: Type::Int,
+                ..
+            })
+        ));
+        let facts = syms(&[&["x"], &["y", "z", "w"]]);
+        assert!(matches!(
+            eval.insert_facts(&edge, facts.iter().map(Vec::as_slice)),
+            Err(backend::Error::FactArity {
+                arity: 2,
+                found: 1,
+                ..
+            })
+        ));
+        // Nothing was added, not even the facts before the bad one
+        let facts = syms(&[&["x", "y"], &["z"]]);
+        assert!(eval
+            .insert_facts(&edge, facts.iter().map(Vec::as_slice))
+            .is_err());
+        assert_eq!(3, eval.relation(&edge).unwrap().len());
+        let unit = Rel::new(String::from("unit"));
+        assert!(matches!(
+            eval.insert_facts(&unit, [&[][..], &[Const::Int(1)][..]]),
+            Err(backend::Error::FactArity { found: 1, .. })
+        ));
+        assert!(eval.relation(&unit).unwrap().is_empty());
+        eval.go().unwrap();
+        assert_eq!(6, tuples(&eval.model().unwrap(), "path").len());
+    }
+
+    #[test]
+    fn test_insert_facts_errors() {
+        check_insert_errors(Connection::open_in_memory().unwrap());
+    }
+
+    #[cfg(all(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn test_insert_facts_errors_duckdb() {
+        check_insert_errors(::duckdb::Connection::open_in_memory().unwrap());
+    }
+
+    #[test]
+    fn test_retract_facts_errors() {
+        let prog = Mir::new(parse("edge(a, b). edge(a, c). edge(b, c). n(1).").unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        eval.go().unwrap();
+        let edge = Rel::new(String::from("edge"));
+        let n = Rel::new(String::from("n"));
+        let before = eval.model().unwrap();
+        let short = syms(&[&["a"]]);
+        assert!(matches!(
+            eval.retract_facts(&edge, short.iter().map(Vec::as_slice)),
+            Err(backend::Error::FactArity {
+                arity: 2,
+                found: 1,
+                ..
+            })
+        ));
+        assert!(matches!(
+            eval.retract_facts(&edge, [&[][..]]),
+            Err(backend::Error::FactArity { found: 0, .. })
+        ));
+        // SQLite would compare `1` and `"1"` equal
+        let wrong = syms(&[&["1"]]);
+        assert!(matches!(
+            eval.retract_facts(&n, wrong.iter().map(Vec::as_slice)),
+            Err(backend::Error::FactType {
+                expected: Type::Int,
+                found: Type::Sym,
+                ..
+            })
+        ));
+        // Nothing was removed, not even the facts before the bad one
+        let facts = syms(&[&["a", "b"], &["a"]]);
+        assert!(eval
+            .retract_facts(&edge, facts.iter().map(Vec::as_slice))
+            .is_err());
+        assert_eq!(before, eval.model().unwrap());
+    }
+
+    #[test]
+    fn test_load_errors() {
+        let src = ".decl edge(src: symbol, dst: number)";
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        let edge = Rel::new(String::from("edge"));
+        let d = dir("load-errors", &[("edge.csv", "a,1,x\n")]);
+        let path = d.join("edge.csv");
+        assert!(matches!(
+            eval.load(&edge, &Source::csv(&path)),
+            Err(backend::Error::SourceArity {
+                expected: 2,
+                found: 3,
+                ..
+            })
+        ));
+        let source =
+            Source::csv(&path).with_columns(vec![String::from("column0"), String::from("dst")]);
+        assert!(matches!(
+            eval.load(&edge, &source),
+            Err(backend::Error::SourceColumn { column, .. }) if column == "dst"
+        ));
+        #[cfg(feature = "sqlite")]
+        {
+            let source = Source::csv(&path)
+                .with_columns(vec![String::from("column0"), String::from("column2")]);
+            assert!(matches!(
+                eval.load(&edge, &source),
+                Err(backend::Error::SourceValue { line: 1, value, .. }) if value == "x"
+            ));
+            assert!(matches!(
+                eval.load(&edge, &Source::parquet(&path)),
+                Err(backend::Error::SourceFormat { .. })
+            ));
+        }
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    /// The lines of a file, sorted, because facts are exported in any order
+    fn sorted_lines(file: &std::path::Path) -> Vec<String> {
+        let contents = std::fs::read_to_string(file).unwrap();
+        let mut lines: Vec<String> = contents.lines().map(String::from).collect();
+        lines.sort();
+        lines
+    }
+
+    #[test]
+    fn test_export() {
+        let src = r#"
+            edge(a, b). edge(b, "c d").
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            "#;
+        let eval = Eval::new(
+            Connection::open_in_memory().unwrap(),
+            Mir::new(parse(src).unwrap()).unwrap(),
+        )
+        .unwrap();
+        eval.go().unwrap();
+        let path = Rel::new(String::from("path"));
+        let d = dir("export", &[]);
+
+        let csv = d.join("export.csv");
+        eval.export(&path, &csv, ExportFormat::Csv).unwrap();
+        assert_eq!(vec!["a,b", "a,c d", "b,c d", "x0,x1"], sorted_lines(&csv));
+        // What's exported can be loaded again
+        let source = Source::csv(&csv).with_header(true);
+        eval.load(&Rel::new(String::from("edge")), &source).unwrap();
+        eval.go().unwrap();
+        assert_eq!(
+            vec![vec!["a", r#""c d""#], vec!["a", "b"], vec!["b", r#""c d""#]],
+            tuples(&eval.model().unwrap(), "edge")
+        );
+
+        let json = d.join("export.json");
+        eval.export(&Rel::new(String::from("edge")), &json, ExportFormat::Json)
+            .unwrap();
+        let mut lines = sorted_lines(&json);
+        lines.retain(|l| l.contains(r#""x0":"a""#));
+        assert_eq!(
+            vec![r#"{"x0":"a","x1":"b"}"#, r#"{"x0":"a","x1":"c d"}"#],
+            lines
+        );
+
+        #[cfg(feature = "sqlite")]
+        assert!(matches!(
+            eval.export(&path, d.join("export.parquet"), ExportFormat::Parquet),
+            Err(backend::Error::ExportFormat { .. })
+        ));
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    #[cfg(all(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn test_export_duckdb_same_as_sqlite() {
+        let src = r#"
+            r(a, 1, 1.5, true). r("c d", -2, 0.25, false). r("1.50", 3, 2.0, true).
+            unit.
+            "#;
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let duck = Eval::new(
+            ::duckdb::Connection::open_in_memory().unwrap(),
+            prog.clone(),
+        )
+        .unwrap();
+        let lite = Eval::new(::rusqlite::Connection::open_in_memory().unwrap(), prog).unwrap();
+        duck.go().unwrap();
+        lite.go().unwrap();
+        let d = dir("export-duckdb", &[]);
+        for rel in ["r", "unit"] {
+            let rel = Rel::new(String::from(rel));
+            for (format, ext) in [(ExportFormat::Csv, "csv"), (ExportFormat::Json, "json")] {
+                let duck_file = d.join(format!("{rel}-duckdb.{ext}"));
+                let lite_file = d.join(format!("{rel}-sqlite.{ext}"));
+                duck.export(&rel, &duck_file, format).unwrap();
+                lite.export(&rel, &lite_file, format).unwrap();
+                assert_eq!(
+                    sorted_lines(&lite_file),
+                    sorted_lines(&duck_file),
+                    "{rel}.{ext}"
+                );
+            }
+        }
+        std::fs::remove_dir_all(d).unwrap();
+    }
+
+    /// Evaluate `src`, remove `facts` from `rel`, and check that the model
+    /// is the same as evaluating the program without them
+    fn check_retract(src: &str, rel: &str, facts: &[Vec<Const>]) {
+        let rel = Rel::new(String::from(rel));
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog.clone()).unwrap();
+        eval.go().unwrap();
+        eval.retract_facts(&rel, facts.iter().map(Vec::as_slice))
+            .unwrap();
+
+        let mut rest = prog.clone();
+        rest.clear_facts();
+        for (r, fs) in prog.facts() {
+            for fact in fs {
+                if *r != rel || !facts.contains(fact) {
+                    rest.add_fact(r, fact.clone());
+                }
+            }
+        }
+        let full = Eval::new(Connection::open_in_memory().unwrap(), rest).unwrap();
+        full.go().unwrap();
+        assert_eq!(full.model().unwrap(), eval.model().unwrap());
+    }
+
+    #[test]
+    fn test_retract_facts() {
+        check_retract(TC, "edge", &syms(&[&["b", "c"]]));
+        check_retract(TC, "edge", &syms(&[&["a", "b"], &["c", "d"]]));
+        // Not a fact
+        check_retract(TC, "edge", &syms(&[&["a", "c"]]));
+        check_retract(TC, "path", &syms(&[&["a", "b"]]));
+    }
+
+    #[test]
+    fn test_retract_facts_cycle() {
+        // Every path is derived in several ways, most of them circular
+        let src = r"
+            edge(a, b). edge(b, c). edge(c, a). edge(c, d). edge(b, d).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), path(Y, Z).
+            ";
+        check_retract(src, "edge", &syms(&[&["c", "a"]]));
+        check_retract(src, "edge", &syms(&[&["b", "c"]]));
+        check_retract(src, "edge", &syms(&[&["c", "d"]]));
+    }
+
+    #[test]
+    fn test_retract_facts_given_and_derived() {
+        let src = r"
+            edge(a, b). edge(b, c).
+            path(a, c).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            ";
+        check_retract(src, "path", &syms(&[&["a", "c"]]));
+        check_retract(src, "edge", &syms(&[&["b", "c"]]));
+    }
+
+    #[test]
+    fn test_retract_facts_negation() {
+        let src = r"
+            edge(a, b). edge(b, c). edge(c, d).
+            node(a). node(b). node(c). node(d).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- path(X, Y), edge(Y, Z).
+            unreachable(X, Y) :- node(X), node(Y), !path(X, Y).
+            far(X, count(Y)) :- unreachable(X, Y).
+            ";
+        check_retract(src, "edge", &syms(&[&["b", "c"]]));
+        check_retract(src, "node", &syms(&[&["a"]]));
+    }
+
+    #[test]
+    fn test_retract_facts_body_aggregate() {
+        let src = r"
+            edge(a, b). edge(b, c).
+            node(a). node(b). node(c).
+            out(X, C) :- node(X), C = count : { edge(X, _) }.
+            isolated(X) :- node(X), C = count : { edge(X, _) }, C = 0.
+            ";
+        check_retract(src, "edge", &syms(&[&["b", "c"]]));
+    }
+
+    #[test]
+    fn test_retract_then_insert() {
+        let prog = Mir::new(parse(TC).unwrap()).unwrap();
+        let e = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        let edge = Rel::new(String::from("edge"));
+        let bc = syms(&[&["b", "c"]]);
+        // Before evaluating
+        e.retract_facts(&edge, bc.iter().map(Vec::as_slice))
+            .unwrap();
+        e.go().unwrap();
+        let m = e.model().unwrap();
+        assert_eq!(vec![vec!["a", "b"], vec!["c", "d"]], tuples(&m, "path"));
+
+        e.insert_facts(&edge, bc.iter().map(Vec::as_slice)).unwrap();
+        e.go().unwrap();
+        assert_eq!(eval(TC), e.model().unwrap());
+    }
 }
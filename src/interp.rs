@@ -0,0 +1,621 @@
+//! A semi-naive evaluator over in-memory, hash-indexed relations.
+//!
+//! [`Interp`] computes the same model as [`crate::eval::Eval`], without SQL.
+//! It's meant for small programs, and as a reference for testing the SQL
+//! backends.
+
+use std::cmp::Ordering;
+
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+
+use crate::ast::{
+    AggFunc, Aggregate, ArithOp, Atom, CmpOp, Comparison, Const, Expr, Float, Rel, RelDecl, Rule,
+    Term, Var,
+};
+use crate::mir::Mir;
+
+/// The facts of one relation, in the order they were derived
+#[derive(Debug, Default)]
+struct Relation {
+    tuples: Vec<Vec<Const>>,
+    /// The iteration in which each tuple was derived (non-decreasing)
+    its: Vec<usize>,
+    set: HashSet<Vec<Const>>,
+    /// For each column, the indices of the tuples with each value
+    indices: Vec<HashMap<Const, Vec<usize>>>,
+    /// For [`Rel::is_total`] relations, the result for groups without facts
+    zero: Option<Const>,
+}
+
+impl Relation {
+    fn new(decl: &RelDecl) -> Self {
+        Self {
+            indices: vec![HashMap::default(); decl.arity()],
+            zero: decl
+                .rel
+                .is_total()
+                .then(|| Const::zero(decl.cols.last().expect("aggregate result").ty)),
+            ..Self::default()
+        }
+    }
+
+    /// Returns whether the tuple was new
+    fn insert(&mut self, tuple: Vec<Const>, it: usize) -> bool {
+        if self.set.contains(&tuple) {
+            return false;
+        }
+        let i = self.tuples.len();
+        for (c, index) in tuple.iter().zip(&mut self.indices) {
+            index.entry(c.clone()).or_default().push(i);
+        }
+        self.set.insert(tuple.clone());
+        self.tuples.push(tuple);
+        self.its.push(it);
+        true
+    }
+
+    /// The indices of the tuples derived in iteration `it`
+    fn derived_in(&self, it: usize) -> std::ops::Range<usize> {
+        let start = self.its.partition_point(|i| *i < it);
+        let end = self.its.partition_point(|i| *i <= it);
+        start..end
+    }
+}
+
+/// Values of the variables of a rule, see [`Vars`]
+type Env = Vec<Option<Const>>;
+
+/// Numbers the variables of a rule, for use as indices into an [`Env`]
+struct Vars<'a>(HashMap<&'a Var, usize>);
+
+impl<'a> Vars<'a> {
+    fn new(rule: &'a Rule) -> Self {
+        let mut vars = HashMap::default();
+        let mut add = |v: &'a Var| {
+            let n = vars.len();
+            vars.entry(v).or_insert(n);
+        };
+        for lit in &rule.body {
+            if let Some(atom) = lit.atom() {
+                atom.terms.iter().filter_map(Term::var).for_each(&mut add);
+            }
+            if let Some(cmp) = lit.cmp() {
+                cmp.vars().into_iter().for_each(&mut add);
+            }
+        }
+        Self(vars)
+    }
+
+    fn get(&self, v: &Var) -> usize {
+        self.0[v]
+    }
+
+    fn value<'e>(&self, env: &'e Env, v: &Var) -> &'e Const {
+        env[self.get(v)]
+            .as_ref()
+            .expect("Mir rules are range-restricted")
+    }
+}
+
+/// Built-in comparisons, and atoms of [`Rel::is_total`] relations, in an
+/// order where each one's variables are bound
+enum Builtin<'a> {
+    /// An equation that binds a variable, see [`Comparison::binds`]
+    Bind(&'a Var, &'a Expr),
+    Filter(&'a Comparison),
+    /// Matches the facts of a `count` or `sum`, or 0 if there are none
+    Total(&'a Atom),
+}
+
+/// See [`Builtin`]. Body literal `delta` is read like other atoms, like in
+/// [`crate::eval::Eval`].
+fn builtins(rule: &Rule, delta: Option<usize>) -> Vec<Builtin<'_>> {
+    let mut totals = Vec::new();
+    let mut bound: std::collections::HashSet<&Var> = std::collections::HashSet::new();
+    for (i, lit) in rule.body.iter().enumerate() {
+        match lit.positive() {
+            Some(atom) if atom.rel.is_total() && delta != Some(i) => totals.push(atom),
+            Some(atom) => bound.extend(atom.terms.iter().filter_map(Term::var)),
+            None => (),
+        }
+    }
+    let mut builtins = Vec::new();
+    let mut pending: Vec<&Comparison> = rule.comparisons().collect();
+    while !pending.is_empty() || !totals.is_empty() {
+        let before = pending.len() + totals.len();
+        totals.retain(|atom| {
+            let (result, groups) = atom.terms.split_last().expect("aggregate result");
+            if groups
+                .iter()
+                .all(|t| t.var().is_none_or(|v| bound.contains(v)))
+            {
+                builtins.push(Builtin::Total(atom));
+                bound.extend(result.var());
+                false
+            } else {
+                true
+            }
+        });
+        pending.retain(|cmp| {
+            if let Some((v, e)) = cmp.binds(&bound) {
+                builtins.push(Builtin::Bind(v, e));
+                bound.insert(v);
+                false
+            } else if cmp.vars().iter().all(|v| bound.contains(v)) {
+                builtins.push(Builtin::Filter(cmp));
+                false
+            } else {
+                true
+            }
+        });
+        assert!(
+            pending.len() + totals.len() < before,
+            "Mir rules are range-restricted"
+        );
+    }
+    builtins
+}
+
+/// The value of an arithmetic expression, or `None` if it's undefined (e.g.,
+/// division by zero), like `NULL` in SQL
+fn eval_expr(vars: &Vars<'_>, env: &Env, expr: &Expr) -> Option<Const> {
+    match expr {
+        Expr::Const(c) => Some(c.clone()),
+        Expr::Var(v) => Some(vars.value(env, v).clone()),
+        Expr::Binary(op, l, r) => {
+            let l = eval_expr(vars, env, l)?;
+            let r = eval_expr(vars, env, r)?;
+            match (l, r) {
+                (Const::Int(x), Const::Int(y)) => Some(Const::Int(match op {
+                    ArithOp::Add => x.checked_add(y)?,
+                    ArithOp::Sub => x.checked_sub(y)?,
+                    ArithOp::Mul => x.checked_mul(y)?,
+                    ArithOp::Div => x.checked_div(y)?,
+                })),
+                // Arithmetic on an `int` and a `float` is a `float`
+                (Const::Int(x), Const::Float(y)) => float(*op, x as f64, y.0),
+                (Const::Float(x), Const::Int(y)) => float(*op, x.0, y as f64),
+                (Const::Float(Float(x)), Const::Float(Float(y))) => float(*op, x, y),
+                _ => unreachable!("arithmetic is well-typed in Mir"),
+            }
+        }
+    }
+}
+
+fn float(op: ArithOp, x: f64, y: f64) -> Option<Const> {
+    Some(Const::Float(Float(match op {
+        ArithOp::Add => x + y,
+        ArithOp::Sub => x - y,
+        ArithOp::Mul => x * y,
+        ArithOp::Div if y == 0.0 => return None,
+        ArithOp::Div => x / y,
+    })))
+}
+
+fn eval_cmp(op: CmpOp, l: &Const, r: &Const) -> bool {
+    let ord = match (l, r) {
+        // Compare an `int` and a `float` as numbers, like SQL
+        (Const::Int(x), Const::Float(y)) => Float(*x as f64).cmp(y),
+        (Const::Float(x), Const::Int(y)) => x.cmp(&Float(*y as f64)),
+        _ => l.cmp(r),
+    };
+    match op {
+        CmpOp::Eq => ord == Ordering::Equal,
+        CmpOp::Ne => ord != Ordering::Equal,
+        CmpOp::Lt => ord == Ordering::Less,
+        CmpOp::Le => ord != Ordering::Greater,
+        CmpOp::Gt => ord == Ordering::Greater,
+        CmpOp::Ge => ord != Ordering::Less,
+    }
+}
+
+/// The value of an aggregate over a (non-empty) group of assignments
+fn eval_aggregate(vars: &Vars<'_>, group: &[&Env], agg: &Aggregate) -> Option<Const> {
+    let mut vals = group.iter().map(|env| vars.value(env, agg.var()));
+    match agg.func() {
+        AggFunc::Count => Some(Const::Int(group.len() as i64)),
+        AggFunc::Min => vals.min().cloned(),
+        AggFunc::Max => vals.max().cloned(),
+        AggFunc::Sum => match vals.next()? {
+            Const::Int(x) => {
+                let mut sum = *x;
+                for v in vals {
+                    let Const::Int(y) = v else {
+                        unreachable!("aggregates are well-typed in Mir")
+                    };
+                    sum = sum.checked_add(*y)?;
+                }
+                Some(Const::Int(sum))
+            }
+            Const::Float(Float(x)) => {
+                let mut sum = *x;
+                for v in vals {
+                    let Const::Float(Float(y)) = v else {
+                        unreachable!("aggregates are well-typed in Mir")
+                    };
+                    sum += y;
+                }
+                Some(Const::Float(Float(sum)))
+            }
+            _ => unreachable!("aggregates are well-typed in Mir"),
+        },
+    }
+}
+
+/// Evaluates a [`Mir`] program in memory
+#[derive(Debug)]
+pub struct Interp {
+    prog: Mir,
+    /// Every relation in `prog`, see [`Mir::decls`]
+    rels: HashMap<Rel, Relation>,
+}
+
+impl Interp {
+    /// Clear facts from the embedded [`Mir`] program.
+    pub fn clear_facts(&mut self) {
+        self.prog.clear_facts()
+    }
+
+    /// Create a new evaluator.
+    ///
+    /// If it makes sense for your time/space trade-off, you can call
+    /// [`Interp::clear_facts`] after this.
+    pub fn new(prog: Mir) -> Self {
+        let mut rels: HashMap<Rel, Relation> = prog
+            .decls()
+            .into_iter()
+            .map(|(rel, decl): (Rel, RelDecl)| (rel, Relation::new(&decl)))
+            .collect();
+        for (rel, facts) in prog.facts() {
+            let r = rels.get_mut(rel).unwrap();
+            for fact in facts {
+                r.insert(fact.clone(), 0);
+            }
+        }
+        Self { prog, rels }
+    }
+
+    /// Extend each assignment in `envs` with the values of `atom`'s variables
+    /// in each matching tuple. If `delta` is `Some(it)`, only uses the tuples
+    /// from iteration `it`.
+    fn join(&self, vars: &Vars<'_>, envs: Vec<Env>, atom: &Atom, delta: Option<usize>) -> Vec<Env> {
+        let rel = &self.rels[&atom.rel];
+        let range = match delta {
+            Some(it) => rel.derived_in(it),
+            None => 0..rel.tuples.len(),
+        };
+        let mut out = Vec::new();
+        for env in envs {
+            let value = |t: &Term| match t {
+                Term::Const(c) => Some(c.clone()),
+                Term::Var(v) => env[vars.get(v)].clone(),
+                Term::Aggregate(_) => unreachable!("aggregate in body"),
+            };
+            // Use the index on the first column with a known value
+            let candidates: Vec<usize> = match atom
+                .terms
+                .iter()
+                .enumerate()
+                .find_map(|(i, t)| value(t).map(|c| (i, c)))
+            {
+                Some((i, c)) => rel.indices[i]
+                    .get(&c)
+                    .map(|ts| ts.iter().copied().filter(|t| range.contains(t)).collect())
+                    .unwrap_or_default(),
+                None => range.clone().collect(),
+            };
+            'tuples: for t in candidates {
+                let mut next = env.clone();
+                for (term, c) in atom.terms.iter().zip(&rel.tuples[t]) {
+                    match term {
+                        Term::Const(d) if c != d => continue 'tuples,
+                        Term::Const(_) => (),
+                        Term::Var(v) => {
+                            let slot = &mut next[vars.get(v)];
+                            match slot {
+                                Some(d) if c != d => continue 'tuples,
+                                Some(_) => (),
+                                None => *slot = Some(c.clone()),
+                            }
+                        }
+                        Term::Aggregate(_) => unreachable!("aggregate in body"),
+                    }
+                }
+                out.push(next);
+            }
+        }
+        out
+    }
+
+    /// The tuples derived by one rule, reading body literal `delta` (if any)
+    /// from iteration `it - 1`, like the queries of [`crate::eval::Eval`].
+    fn rule(&self, rule: &Rule, it: usize, delta: Option<usize>) -> Vec<Vec<Const>> {
+        let vars = Vars::new(rule);
+        let mut envs = vec![vec![None; vars.0.len()]];
+        for (i, lit) in rule.body.iter().enumerate() {
+            match lit.positive() {
+                Some(atom) if atom.rel.is_total() && delta != Some(i) => (),
+                Some(atom) => {
+                    let d = if delta == Some(i) { Some(it - 1) } else { None };
+                    envs = self.join(&vars, envs, atom, d);
+                }
+                None => (),
+            }
+        }
+        for builtin in builtins(rule, delta) {
+            envs = match builtin {
+                Builtin::Total(atom) => {
+                    let zero = self.rels[&atom.rel].zero.as_ref().unwrap();
+                    let result = atom.terms.last().unwrap();
+                    let mut out = Vec::with_capacity(envs.len());
+                    for env in envs {
+                        let matches = self.join(&vars, vec![env.clone()], atom, None);
+                        if !matches.is_empty() {
+                            out.extend(matches);
+                            continue;
+                        }
+                        let mut env = env;
+                        let ok = match result {
+                            Term::Const(c) => c == zero,
+                            Term::Var(v) => match &env[vars.get(v)] {
+                                Some(c) => c == zero,
+                                None => {
+                                    env[vars.get(v)] = Some(zero.clone());
+                                    true
+                                }
+                            },
+                            Term::Aggregate(_) => unreachable!("aggregate in body"),
+                        };
+                        if ok {
+                            out.push(env);
+                        }
+                    }
+                    out
+                }
+                Builtin::Bind(v, e) => envs
+                    .into_iter()
+                    .filter_map(|mut env| {
+                        env[vars.get(v)] = Some(eval_expr(&vars, &env, e)?);
+                        Some(env)
+                    })
+                    .collect(),
+                Builtin::Filter(cmp) => envs
+                    .into_iter()
+                    .filter(|env| {
+                        match (
+                            eval_expr(&vars, env, cmp.lhs()),
+                            eval_expr(&vars, env, cmp.rhs()),
+                        ) {
+                            (Some(l), Some(r)) => eval_cmp(cmp.op(), &l, &r),
+                            _ => false,
+                        }
+                    })
+                    .collect(),
+            };
+        }
+        let ground = |env: &Env, atom: &Atom| -> Vec<Const> {
+            atom.terms
+                .iter()
+                .map(|t| match t {
+                    Term::Const(c) => c.clone(),
+                    Term::Var(v) => vars.value(env, v).clone(),
+                    Term::Aggregate(_) => unreachable!("aggregate in body"),
+                })
+                .collect()
+        };
+        let local = rule.local();
+        for lit in &rule.body {
+            if lit.is_negative() {
+                let atom = lit.atom().unwrap();
+                if lit.vars().iter().any(|v| local.contains(v)) {
+                    // Wildcards match anything, see [`Rule::local`]
+                    envs.retain(|env| self.join(&vars, vec![env.clone()], atom, None).is_empty());
+                } else {
+                    let set = &self.rels[&atom.rel].set;
+                    envs.retain(|env| !set.contains(&ground(env, atom)));
+                }
+            }
+        }
+
+        if !rule.is_aggregate() {
+            return envs.iter().map(|env| ground(env, &rule.head)).collect();
+        }
+        // Aggregate over the distinct assignments (set semantics), grouped
+        // by the other terms of the head
+        let envs: HashSet<Env> = envs.into_iter().collect();
+        let mut groups: HashMap<Vec<&Const>, Vec<&Env>> = HashMap::default();
+        for env in &envs {
+            let key = rule
+                .head
+                .terms
+                .iter()
+                .filter_map(|t| match t {
+                    Term::Var(v) => Some(vars.value(env, v)),
+                    _ => None,
+                })
+                .collect();
+            groups.entry(key).or_default().push(env);
+        }
+        let mut tuples = Vec::with_capacity(groups.len());
+        'groups: for group in groups.values() {
+            let mut tuple = Vec::with_capacity(rule.head.terms.len());
+            for term in &rule.head.terms {
+                tuple.push(match term {
+                    Term::Const(c) => c.clone(),
+                    Term::Var(v) => vars.value(group[0], v).clone(),
+                    Term::Aggregate(a) => match eval_aggregate(&vars, group, a) {
+                        Some(c) => c,
+                        None => continue 'groups,
+                    },
+                });
+            }
+            tuples.push(tuple);
+        }
+        tuples
+    }
+
+    /// Evaluate the program to a fixpoint, one strongly connected component
+    /// at a time (see [`Mir::sccs`]). Returns the relations of each component
+    /// and the number of iterations it took, like [`crate::eval::Eval::go`].
+    pub fn go(&mut self) -> Vec<(Vec<Rel>, usize)> {
+        let mut iters = Vec::new();
+        // Stamps new facts, see [`Relation::derived_in`]
+        let mut it = 0;
+        for scc in self.prog.sccs() {
+            let rels: HashSet<&Rel> = scc.rels.iter().copied().collect();
+            let mut n = 0;
+            loop {
+                n += 1;
+                it += 1;
+                // As in SQL, new facts are only visible in the next iteration
+                let mut new = Vec::new();
+                for rule in &scc.rules {
+                    let deltas: Vec<Option<usize>> = if n == 1 {
+                        vec![None]
+                    } else {
+                        rule.body
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, lit)| {
+                                lit.positive().is_some_and(|a| rels.contains(&a.rel))
+                            })
+                            .map(|(i, _)| Some(i))
+                            .collect()
+                    };
+                    for delta in deltas {
+                        new.push((&rule.head.rel, self.rule(rule, it, delta)));
+                    }
+                }
+                let mut changed = false;
+                for (rel, tuples) in new {
+                    let rel = self.rels.get_mut(rel).unwrap();
+                    for tuple in tuples {
+                        changed |= rel.insert(tuple, it);
+                    }
+                }
+                if !changed || !scc.recursive {
+                    break;
+                }
+            }
+            iters.push((scc.rels.into_iter().cloned().collect(), n));
+        }
+        iters
+    }
+
+    /// The minimal Herbrand model (after calling [`Interp::go`]).
+    ///
+    /// Includes every relation, even those with no facts.
+    pub fn model(&self) -> HashMap<Rel, HashSet<Vec<Const>>> {
+        self.rels
+            .iter()
+            .map(|(rel, r)| (rel.clone(), r.set.clone()))
+            .collect()
+    }
+
+    pub fn into_program(self) -> Mir {
+        self.prog
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(all(feature = "duckdb", not(feature = "sqlite")))]
+    use duckdb::Connection;
+    #[cfg(feature = "sqlite")]
+    use rusqlite::Connection;
+
+    use crate::eval::Eval;
+    use crate::parse::parse;
+
+    use super::*;
+
+    fn interp(src: &str) -> HashMap<Rel, HashSet<Vec<Const>>> {
+        let mut interp = Interp::new(Mir::new(parse(src).unwrap()).unwrap());
+        interp.go();
+        interp.model()
+    }
+
+    /// Compare to [`Eval`]
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    fn check(src: &str) {
+        let prog = Mir::new(parse(src).unwrap()).unwrap();
+        let eval = Eval::new(Connection::open_in_memory().unwrap(), prog).unwrap();
+        eval.go().unwrap();
+        assert_eq!(eval.model().unwrap(), interp(src));
+    }
+
+    #[test]
+    fn test_tc() {
+        let m = interp(
+            r"
+            edge(a, b). edge(b, c). edge(c, a). edge(d, d).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+            ",
+        );
+        let path = &m[&Rel::new(String::from("path"))];
+        assert_eq!(10, path.len());
+        assert!(path.contains(&vec![
+            Const::Sym(String::from("a")),
+            Const::Sym(String::from("a"))
+        ]));
+        assert!(!path.contains(&vec![
+            Const::Sym(String::from("a")),
+            Const::Sym(String::from("d"))
+        ]));
+    }
+
+    #[cfg(any(feature = "duckdb", feature = "sqlite"))]
+    #[test]
+    fn test_same_as_eval() {
+        check("r(). s() :- r(). t() :- !s(). u() :- !t().");
+        check("p(1, 2). p(2, 3). p(3, 1). q(X, Z) :- p(X, Y), q(Y, Z). q(X, Y) :- p(X, Y).");
+        check(
+            r"
+            node(a). node(b). node(c). node(d).
+            edge(a, b). edge(b, c).
+            reach(a).
+            reach(Y) :- reach(X), edge(X, Y).
+            unreach(X) :- node(X), !reach(X).
+            sink(X) :- node(X), !edge(X, _).
+            ",
+        );
+        check(
+            r"
+            edge(a, b, 1). edge(a, c, 2). edge(b, c, 3). edge(c, c, 4).
+            deg(X, count(Y)) :- edge(X, Y, W).
+            total(sum(W)) :- edge(X, Y, W).
+            lightest(X, min(W)) :- edge(X, Y, W).
+            heaviest(max(W)) :- edge(X, Y, W).
+            node(a). node(b). node(c). node(d).
+            out(X, C) :- node(X), C = count : { edge(X, _, _) }.
+            mass(X, S) :- node(X), S = sum W : edge(X, _, W), S < 4.
+            isolated(X) :- node(X), C = count : { edge(X, _, _) }, C = 0.
+            ",
+        );
+        check(
+            r"
+            n(0). n(1). n(2). n(3). x(1.5). x(0.0).
+            half(N, M) :- n(N), M = N / 2.
+            inv(X, Y) :- n(X), Y = 6 / X.
+            finv(X, Y) :- x(X), Y = 3.0 / X.
+            lt(X, Y) :- n(X), n(Y), X < Y, X + 1 != Y.
+            count(M) :- n(N), M = N + 1, M < 4.
+            ",
+        );
+        check(
+            r"
+            n(1). n(2). x(0.5).
+            mixed(N, Y) :- n(N), x(X), Y = N * X + 1.
+            lt(N, X) :- n(N), x(X), X + 1 < N.
+            ",
+        );
+        check(
+            r"
+            edge(a, b). edge(a, c). edge(b, c). node(a). node(b). node(c).
+            n(X, C) :- node(X), C = count : { edge(X, _) }.
+            out(S) :- S = sum C : { n(X, C), !edge(X, a) }.
+            ",
+        );
+    }
+}
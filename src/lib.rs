@@ -1,3 +1,12 @@
 pub mod ast;
+pub mod backend;
+pub mod diag;
+#[cfg(test)]
+mod differential;
 pub mod eval;
+pub mod interp;
+pub(crate) mod magic;
 pub mod mir;
+pub mod parse;
+pub mod span;
+pub(crate) mod strat;
@@ -0,0 +1,222 @@
+//! Magic-sets rewriting, for goal-directed queries (see
+//! [`crate::eval::Eval::query`]).
+//!
+//! For each relation `p` and adornment `a` (which columns are bound) reachable
+//! from the query, `p@a` holds the facts of `p` that are relevant to the
+//! query, and `magic:p@a` holds the values of the bound columns that are
+//! relevant. Bindings are passed left-to-right through rule bodies.
+//!
+//! Magic sets don't mix well with negation and aggregation, so relations that
+//! are negated or aggregated over, and relations defined with aggregates, are
+//! computed in full by their original rules, along with their dependencies.
+
+use std::collections::VecDeque;
+
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+
+use crate::ast::{Atom, Literal, Rel, RelDecl, Rule, Term, Type, Var};
+use crate::mir::Mir;
+
+/// Which columns are bound
+type Adornment = Vec<bool>;
+
+/// The result of [`rewrite`]
+#[derive(Debug)]
+pub(crate) struct Magic {
+    /// Includes the seed fact for the query
+    pub(crate) rules: Vec<Rule>,
+    /// Declarations of the new relations
+    pub(crate) decls: Vec<RelDecl>,
+    /// The relation that holds the answers to the query (and maybe more)
+    pub(crate) answer: Rel,
+}
+
+fn adornment_name(a: &[bool]) -> String {
+    a.iter().map(|b| if *b { 'b' } else { 'f' }).collect()
+}
+
+fn adorned(rel: &Rel, a: &[bool]) -> Rel {
+    Rel::new(format!("{rel}@{}", adornment_name(a)))
+}
+
+fn magic(rel: &Rel, a: &[bool]) -> Rel {
+    Rel::new(format!("magic:{rel}@{}", adornment_name(a)))
+}
+
+/// The terms of `atom` in bound columns
+fn bound_terms(atom: &Atom, a: &[bool]) -> Vec<Term> {
+    atom.terms
+        .iter()
+        .zip(a)
+        .filter(|(_, b)| **b)
+        .map(|(t, _)| t.clone())
+        .collect()
+}
+
+fn adornment(atom: &Atom, bound: &HashSet<&Var>) -> Adornment {
+    atom.terms
+        .iter()
+        .map(|t| match t {
+            Term::Const(_) => true,
+            Term::Var(v) => bound.contains(v),
+            Term::Aggregate(_) => false,
+        })
+        .collect()
+}
+
+/// Relations that must be computed in full, before the rest
+fn full_rels(mir: &Mir) -> HashSet<&Rel> {
+    let mut full = HashSet::default();
+    for rule in mir.rules() {
+        if rule.is_aggregate() {
+            full.insert(&rule.head.rel);
+        }
+        for lit in &rule.body {
+            if let Some(atom) = lit.atom() {
+                if lit.is_negative() || rule.is_aggregate() {
+                    full.insert(&atom.rel);
+                }
+            }
+        }
+    }
+    full
+}
+
+/// Rewrite `mir` to compute the answers to `query`. The types of `query`'s
+/// constants must match `decls`.
+pub(crate) fn rewrite(mir: &Mir, decls: &HashMap<Rel, RelDecl>, query: &Atom) -> Magic {
+    let mut defs: HashMap<&Rel, Vec<&Rule>> = HashMap::default();
+    for rule in mir.rules() {
+        defs.entry(&rule.head.rel).or_default().push(rule);
+    }
+    let full = full_rels(mir);
+    let adornable = |rel: &Rel| defs.contains_key(rel) && !full.contains(rel);
+
+    let mut rules = Vec::new();
+    let mut new_decls = Vec::new();
+    // Relations used by the rewritten rules that must be computed in full
+    let mut needed: Vec<&Rel> = Vec::new();
+    let mut seen: HashSet<(Rel, Adornment)> = HashSet::default();
+    let mut queue: VecDeque<(Rel, Adornment)> = VecDeque::new();
+
+    let bound = HashSet::default();
+    let a = adornment(query, &bound);
+    let answer = if adornable(&query.rel) {
+        // The seed
+        let seed = Atom::new(magic(&query.rel, &a), bound_terms(query, &a));
+        rules.push(Rule::new(seed, Vec::new()));
+        seen.insert((query.rel.clone(), a.clone()));
+        queue.push_back((query.rel.clone(), a.clone()));
+        adorned(&query.rel, &a)
+    } else {
+        needed.push(&query.rel);
+        query.rel.clone()
+    };
+
+    while let Some((rel, a)) = queue.pop_front() {
+        let decl = &decls[&rel];
+        let types = decl.types();
+        let magic_types: Vec<Type> = types
+            .iter()
+            .zip(&a)
+            .filter(|(_, b)| **b)
+            .map(|(t, _)| *t)
+            .collect();
+        new_decls.push(RelDecl::inferred(adorned(&rel, &a), types));
+        new_decls.push(RelDecl::inferred(magic(&rel, &a), magic_types));
+
+        // The facts of `rel` (and anything already derived) are relevant if
+        // they match the bound columns
+        let vars: Vec<Term> = (0..a.len())
+            .map(|i| Term::Var(Var::new_unchecked(format!("V{i}"))))
+            .collect();
+        let head = Atom::new(adorned(&rel, &a), vars.clone());
+        let guard = Atom::new(magic(&rel, &a), bound_terms(&head, &a));
+        rules.push(Rule::new(head, vec![guard, Atom::new(rel.clone(), vars)]));
+
+        for rule in &defs[&rel] {
+            let guard = Atom::new(magic(&rel, &a), bound_terms(&rule.head, &a));
+            let local = rule.local();
+            let mut bound: HashSet<&Var> = rule
+                .head
+                .terms
+                .iter()
+                .zip(&a)
+                .filter(|(_, b)| **b)
+                .filter_map(|(t, _)| t.var())
+                .collect();
+            let mut body = vec![Literal::Atom(guard)];
+            for lit in &rule.body {
+                match lit {
+                    Literal::Atom(atom) if adornable(&atom.rel) => {
+                        let b = adornment(atom, &bound);
+                        // Pass the bindings to `atom` from the literals
+                        // before it. Comparisons and negations that come
+                        // before the variables they use are skipped, so the
+                        // rule is range-restricted.
+                        let head = Atom::new(magic(&atom.rel, &b), bound_terms(atom, &b));
+                        let premises = body
+                            .iter()
+                            .filter(|l| match l {
+                                Literal::Atom(_) => true,
+                                Literal::Not(a) => a.terms.iter().all(|t| match t {
+                                    Term::Var(v) => bound.contains(v) || local.contains(v),
+                                    _ => true,
+                                }),
+                                Literal::Cmp(c) => c.vars().iter().all(|v| bound.contains(v)),
+                            })
+                            .cloned()
+                            .collect();
+                        rules.push(Rule::new_with_literals(head, premises));
+                        if seen.insert((atom.rel.clone(), b.clone())) {
+                            queue.push_back((atom.rel.clone(), b.clone()));
+                        }
+                        bound.extend(atom.terms.iter().filter_map(Term::var));
+                        body.push(Literal::Atom(Atom::new(
+                            adorned(&atom.rel, &b),
+                            atom.terms.clone(),
+                        )));
+                    }
+                    Literal::Atom(atom) => {
+                        needed.push(&atom.rel);
+                        bound.extend(atom.terms.iter().filter_map(Term::var));
+                        body.push(lit.clone());
+                    }
+                    Literal::Not(atom) => {
+                        needed.push(&atom.rel);
+                        body.push(lit.clone());
+                    }
+                    Literal::Cmp(cmp) => {
+                        let std_bound: std::collections::HashSet<&Var> =
+                            bound.iter().copied().collect();
+                        if let Some((v, _)) = cmp.binds(&std_bound) {
+                            bound.insert(v);
+                        }
+                        body.push(lit.clone());
+                    }
+                }
+            }
+            let head = Atom::new(adorned(&rel, &a), rule.head.terms.clone());
+            rules.push(Rule::new_with_literals(head, body));
+        }
+    }
+
+    // The original rules of the relations computed in full, and of their
+    // dependencies
+    let mut done: HashSet<&Rel> = HashSet::default();
+    while let Some(rel) = needed.pop() {
+        if !done.insert(rel) {
+            continue;
+        }
+        for rule in defs.get(rel).into_iter().flatten() {
+            rules.push((*rule).clone());
+            needed.extend(rule.body.iter().filter_map(|l| l.atom()).map(|a| &a.rel));
+        }
+    }
+
+    Magic {
+        rules,
+        decls: new_decls,
+        answer,
+    }
+}
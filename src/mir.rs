@@ -1,18 +1,115 @@
 use fxhash::{FxBuildHasher, FxHashMap as HashMap, FxHashSet as HashSet};
 
-use crate::ast::{Ast, Const, Rel, Rule};
+use crate::ast::{
+    self, fmt_cycle, fmt_vars, infer_types, Aggregate, Ast, Comparison, Const, Expr, Rel, RelDecl,
+    Rule, Term, Type, Var,
+};
+use crate::strat::{self, Cycle, Graph};
 
 #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, thiserror::Error)]
 pub enum Error {
+    #[error("aggregate `{aggregate}` outside of a rule head")]
+    AggregateBody { aggregate: Aggregate },
+    #[error("aggregation through recursion: {}", fmt_cycle(.cycle))]
+    AggregateCycle { cycle: Vec<Rel> },
+    #[error("cannot compute `{aggregate}` of values of type `{ty}`")]
+    AggregateType { aggregate: Aggregate, ty: Type },
+    #[error("arithmetic on `{ty}` in `{expr}`")]
+    Arith { expr: Expr, ty: Type },
     #[error("relation `{relation}` used with multiple arities: `{arity1}`, `{arity2}`")]
     Arity {
         relation: Rel,
         arity1: usize,
         arity2: usize,
     },
-    // TODO(lb, low): improve error
-    #[error("ungrounded variable")]
-    Ungrounded,
+    #[error("comparison `{cmp}` between `{type1}` and `{type2}`")]
+    Cmp {
+        cmp: Box<Comparison>,
+        type1: Type,
+        type2: Type,
+    },
+    #[error("invalid column name `{name}` in declaration of relation `{relation}`")]
+    Column { relation: Rel, name: String },
+    #[error("negation through recursion: {}", fmt_cycle(.cycle))]
+    Negation { cycle: Vec<Rel> },
+    #[error("relation `{relation}` declared more than once")]
+    Redeclared { relation: Rel },
+    #[error(
+        "column {column} of relation `{relation}` used with multiple types: `{type1}`, `{type2}`"
+    )]
+    Type {
+        relation: Rel,
+        column: usize,
+        type1: Type,
+        type2: Type,
+    },
+    #[error("ungrounded {} in rule `{rule}`", fmt_vars(.vars))]
+    Ungrounded { rule: Box<Rule>, vars: Vec<Var> },
+}
+
+impl From<Cycle<'_>> for Error {
+    fn from(c: Cycle<'_>) -> Self {
+        if c.aggregate {
+            Error::AggregateCycle { cycle: c.rels }
+        } else {
+            Error::Negation { cycle: c.rels }
+        }
+    }
+}
+
+/// The same error, without its source locations
+impl From<ast::Error> for Error {
+    fn from(e: ast::Error) -> Self {
+        match e {
+            ast::Error::AggregateBody { aggregate, .. } => Error::AggregateBody { aggregate },
+            ast::Error::AggregateCycle { cycle, .. } => Error::AggregateCycle { cycle },
+            ast::Error::Arity {
+                relation,
+                arity1,
+                arity2,
+                ..
+            } => Error::Arity {
+                relation,
+                arity1,
+                arity2,
+            },
+            ast::Error::Column { relation, name, .. } => Error::Column { relation, name },
+            ast::Error::Negation { cycle, .. } => Error::Negation { cycle },
+            ast::Error::Redeclared { relation, .. } => Error::Redeclared { relation },
+            ast::Error::Type {
+                relation,
+                column,
+                type1,
+                type2,
+                ..
+            } => Error::Type {
+                relation,
+                column,
+                type1,
+                type2,
+            },
+            ast::Error::AggregateType { aggregate, ty, .. } => {
+                Error::AggregateType { aggregate, ty }
+            }
+            ast::Error::Arith { expr, ty, .. } => Error::Arith { expr, ty },
+            ast::Error::Cmp {
+                cmp, type1, type2, ..
+            } => Error::Cmp { cmp, type1, type2 },
+            ast::Error::Ungrounded { rule, vars } => Error::Ungrounded { rule, vars },
+        }
+    }
+}
+
+/// A strongly connected component of the predicate dependency graph, see
+/// [`Mir::sccs`].
+#[derive(Clone, Debug)]
+pub struct Scc<'a> {
+    /// The relations in the heads of `rules`
+    pub rels: Vec<&'a Rel>,
+    pub rules: Vec<&'a Rule>,
+    /// Whether a rule uses a relation of this component in its body. If not,
+    /// evaluating each rule once suffices.
+    pub recursive: bool,
 }
 
 /// Mid-level IR.
@@ -20,7 +117,13 @@ pub enum Error {
 pub struct Mir {
     facts: HashMap<Rel, HashSet<Vec<Const>>>,
     /// Invariant: Each [`Rule`] has a non-empty body
+    ///
+    /// Invariant: Each [`Rule`] is range-restricted, see [`Rule::ungrounded`]
+    ///
+    /// Invariant: The rules are stratified, see [`Mir::strata`]
     rules: HashSet<Rule>,
+    /// Declared in the [`Ast`], or inferred from it. See [`Mir::decls`].
+    decls: HashMap<Rel, RelDecl>,
 }
 
 impl Mir {
@@ -31,26 +134,12 @@ impl Mir {
             .insert(consts);
     }
 
-    // TODO: This doesn't count atoms that are *only* used in bodies...
     pub fn arities(&self) -> HashMap<Rel, usize> {
-        let mut arities = HashMap::with_capacity_and_hasher(
-            self.facts.len(), // lower bound
-            FxBuildHasher::default(),
-        );
-        for (rel, consts) in &self.facts {
-            arities.insert(rel.clone(), consts.iter().next().unwrap().len());
-        }
-        for rule in &self.rules {
-            let rel = &rule.head.rel;
-            match arities.get(rel).copied() {
-                None => {
-                    arities.insert(rel.clone(), rule.head.terms.len());
-                }
-                Some(arity) => {
-                    debug_assert_eq!(arity, rule.head.terms.len());
-                }
-            }
-        }
+        let arities: HashMap<_, _> = self
+            .types()
+            .into_iter()
+            .map(|(rel, tys)| (rel, tys.len()))
+            .collect();
         debug_assert!(arities.len() >= self.facts.len());
         arities
     }
@@ -69,17 +158,35 @@ impl Mir {
         Ok(prog)
     }
 
+    /// Like [`Mir::new`], but without checking that the rules are valid (see
+    /// [`Mir::valid`]). Still fails if the types of the relations can't be
+    /// inferred, or a fact isn't ground.
     pub fn new_unchecked(ast: Ast) -> Result<Self, Error> {
+        // Facts must be ground to be lowered, rules are checked by Mir::valid
+        for rule in ast.rules.iter().filter(|r| r.is_fact()) {
+            rule.range_restricted()?;
+        }
+        Self::lower(ast)
+    }
+
+    fn lower(ast: Ast) -> Result<Self, Error> {
+        let types = infer_types(&ast.decls, &ast.rules)?;
+        let mut decls = HashMap::with_capacity_and_hasher(types.len(), FxBuildHasher::default());
+        for decl in ast.decls {
+            decls.insert(decl.rel.clone(), decl);
+        }
+        for (rel, tys) in types {
+            decls
+                .entry(rel.clone())
+                .or_insert_with(|| RelDecl::inferred(rel, tys));
+        }
         let mut facts =
             HashMap::with_capacity_and_hasher(ast.rules.len(), FxBuildHasher::default());
         let mut rules =
             HashSet::with_capacity_and_hasher(ast.rules.len(), FxBuildHasher::default());
         for rule in ast.rules {
             if rule.is_fact() {
-                let fact = match rule.head.ground() {
-                    None => return Err(Error::Ungrounded),
-                    Some(f) => f,
-                };
+                let fact = rule.head.ground().expect("range-restricted fact");
                 facts
                     .entry(fact.rel)
                     .or_insert_with(|| {
@@ -92,15 +199,132 @@ impl Mir {
         }
         facts.shrink_to_fit();
         rules.shrink_to_fit();
-        Ok(Self { facts, rules })
+        Ok(Self {
+            facts,
+            rules,
+            decls,
+        })
     }
 
     pub fn rules(&self) -> impl Iterator<Item = &Rule> {
         self.rules.iter()
     }
 
+    /// The rules, grouped into strata in evaluation order. Relations negated
+    /// in a stratum are computed entirely by earlier strata.
+    pub fn strata(&self) -> Vec<Vec<&Rule>> {
+        strat::strata(&self.rules).expect("stratified program")
+    }
+
+    /// The rules, grouped by the strongly connected components of the
+    /// predicate dependency graph, in evaluation order: each component comes
+    /// after the components it depends on. Relations that aren't in the head
+    /// of any rule are omitted.
+    pub fn sccs(&self) -> Vec<Scc<'_>> {
+        let graph = Graph::new(&self.rules);
+        let mut ids: HashMap<&Rel, usize> = HashMap::default();
+        let mut sccs: Vec<Scc<'_>> = Vec::new();
+        for rels in graph.sccs() {
+            for rel in &rels {
+                ids.insert(rel, sccs.len());
+            }
+            sccs.push(Scc {
+                rels: Vec::new(),
+                rules: Vec::new(),
+                recursive: false,
+            });
+        }
+        for rule in &self.rules {
+            let id = ids[&rule.head.rel];
+            let scc = &mut sccs[id];
+            if !scc.rels.contains(&&rule.head.rel) {
+                scc.rels.push(&rule.head.rel);
+            }
+            scc.recursive |= rule
+                .body
+                .iter()
+                .filter_map(|lit| lit.atom())
+                .any(|atom| ids[&atom.rel] == id);
+            scc.rules.push(rule);
+        }
+        sccs.retain(|scc| !scc.rules.is_empty());
+        sccs
+    }
+
+    /// Declarations of every relation, including those that weren't declared
+    /// in the [`Ast`]. Relations that only appear in facts added with
+    /// [`Mir::add_fact`] get the types of their first fact.
+    pub fn decls(&self) -> HashMap<Rel, RelDecl> {
+        let mut decls = self.decls.clone();
+        for (rel, consts) in &self.facts {
+            decls.entry(rel.clone()).or_insert_with(|| {
+                let fact = consts.iter().next().unwrap();
+                RelDecl::inferred(rel.clone(), fact.iter().map(Const::ty).collect())
+            });
+        }
+        decls
+    }
+
+    /// The type of each column of each relation, see [`Mir::decls`].
+    pub fn types(&self) -> HashMap<Rel, Vec<Type>> {
+        self.decls()
+            .into_iter()
+            .map(|(rel, decl)| (rel, decl.types()))
+            .collect()
+    }
+
     pub fn valid(&self) -> Result<(), Error> {
-        // TODO(lb, low): Actually check some things
+        let mut arities = HashMap::with_capacity_and_hasher(
+            self.decls.len(), // lower bound
+            FxBuildHasher::default(),
+        );
+        for (rel, decl) in &self.decls {
+            arities.insert(rel.clone(), decl.arity());
+        }
+        let mut check = |rel: &Rel, arity: usize| match arities.get(rel).copied() {
+            None => {
+                arities.insert(rel.clone(), arity);
+                Ok(())
+            }
+            Some(arity1) if arity1 == arity => Ok(()),
+            Some(arity1) => Err(Error::Arity {
+                relation: rel.clone(),
+                arity1,
+                arity2: arity,
+            }),
+        };
+        let types = self.types();
+        for (rel, consts) in &self.facts {
+            let tys = types.get(rel).unwrap();
+            for fact in consts {
+                check(rel, fact.len())?;
+                for (column, (c, ty)) in fact.iter().zip(tys).enumerate() {
+                    if c.ty() != *ty {
+                        return Err(Error::Type {
+                            relation: rel.clone(),
+                            column,
+                            type1: *ty,
+                            type2: c.ty(),
+                        });
+                    }
+                }
+            }
+        }
+        for rule in &self.rules {
+            check(&rule.head.rel, rule.head.terms.len())?;
+            for atom in rule.body.iter().filter_map(|l| l.atom()) {
+                check(&atom.rel, atom.terms.len())?;
+                for term in &atom.terms {
+                    if let Term::Aggregate(a) = term {
+                        return Err(Error::AggregateBody {
+                            aggregate: a.clone(),
+                        });
+                    }
+                }
+            }
+            rule.range_restricted()?;
+        }
+        strat::strata(&self.rules)?;
         Ok(())
     }
 }
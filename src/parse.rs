@@ -0,0 +1,1522 @@
+//! Parser for Datalog source text.
+//!
+//! ```text
+//! // Comments start with `//` or `%`, or are delimited by `/*` and `*/`.
+//! .decl edge(x: symbol, y: symbol)
+//! .input edge(IO=file, filename="edge.csv", delimiter=",")
+//! edge(a, b).
+//! path(X, Y) :- edge(X, Y).
+//! path(X, Z) :- edge(X, Y), path(Y, Z).
+//! unreachable(X) :- node(X), !path(a, X).
+//! out_degree(X, count(Y)) :- edge(X, Y).
+//! sinks(C) :- C = count : { node(X), !source(X) }.
+//! hops(X, Y, 1) :- edge(X, Y).
+//! hops(X, Z, N) :- hops(X, Y, M), edge(Y, Z), X != Z, M < 10, N = M + 1.
+//! ```
+
+use std::fmt::Display;
+use std::iter::Peekable;
+use std::str::Chars;
+
+use crate::ast::{
+    self, AggFunc, Aggregate, ArithOp, Ast, Atom, CmpOp, Column, Comparison, Const, Expr, Float,
+    IoParams, Literal, Rel, RelDecl, Rule, Term, Type, Var,
+};
+use crate::diag::Diagnostic;
+use crate::span::{Pos, Span};
+
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, thiserror::Error)]
+pub enum Error {
+    #[error("{span}: nothing to count, the aggregate has no variables")]
+    Aggregate { span: Span },
+    #[error("{span}: variable `{var}` of the aggregate is not bound outside of it")]
+    AggregateGroup { var: Var, span: Span },
+    #[error("{span}: unexpected character `{c}`")]
+    Char { c: char, span: Span },
+    #[error("{span}: unterminated comment")]
+    Comment { span: Span },
+    #[error("{span}: invalid constant `{name}`")]
+    Const { name: String, span: Span },
+    #[error("{span}: unknown directive `.{name}`")]
+    Directive { name: String, span: Span },
+    #[error("{span}: invalid escape sequence `\\{c}`")]
+    Escape { c: char, span: Span },
+    #[error("{span}: expected {expected}, found {found}")]
+    Expected {
+        expected: &'static str,
+        found: String,
+        span: Span,
+    },
+    #[error("{span}: invalid number `{text}`")]
+    Number { text: String, span: Span },
+    #[error("{span}: unterminated string")]
+    String { span: Span },
+    #[error("{span}: unknown type `{name}`")]
+    Type { name: String, span: Span },
+    #[error("{span}: relation `{relation}` is not declared")]
+    Undeclared { relation: Rel, span: Span },
+    #[error("{span}: invalid variable `{name}`")]
+    Var { name: String, span: Span },
+    /// Boxed, because it's much larger than the other variants
+    #[error(transparent)]
+    Ast(Box<ast::Error>),
+}
+
+impl From<ast::Error> for Error {
+    fn from(e: ast::Error) -> Self {
+        Error::Ast(Box::new(e))
+    }
+}
+
+impl Error {
+    pub fn diagnostic(&self) -> Diagnostic {
+        let span = match self {
+            Error::Aggregate { span } => *span,
+            Error::AggregateGroup { span, .. } => *span,
+            Error::Char { span, .. } => *span,
+            Error::Comment { span } => *span,
+            Error::Const { span, .. } => *span,
+            Error::Directive { span, .. } => *span,
+            Error::Escape { span, .. } => *span,
+            Error::Expected { span, .. } => *span,
+            Error::Number { span, .. } => *span,
+            Error::String { span } => *span,
+            Error::Type { span, .. } => *span,
+            Error::Undeclared { span, .. } => *span,
+            Error::Var { span, .. } => *span,
+            Error::Ast(e) => return e.diagnostic(),
+        };
+        // Drop the position prefix, it's shown in the snippet
+        let msg = self.to_string();
+        let msg = msg.split_once(": ").map(|(_, m)| m).unwrap_or(&msg);
+        Diagnostic::new(String::from(msg)).with_primary(Some(span), String::new())
+    }
+}
+
+// ------------------------------------------------------------------
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Tok {
+    Colon,
+    Comma,
+    Eof,
+    /// Digits, with a `.` or exponent
+    Float(String),
+    Ident(String),
+    If,
+    /// Digits
+    Int(String),
+    LBrace,
+    /// `=`
+    Eq,
+    /// `>=`
+    Ge,
+    /// `>`
+    Gt,
+    /// `<=`
+    Le,
+    LParen,
+    /// `<`
+    Lt,
+    Minus,
+    /// `!=`
+    Ne,
+    Not,
+    Period,
+    Plus,
+    RBrace,
+    Slash,
+    Star,
+    RParen,
+    Str(String),
+}
+
+impl Tok {
+    /// Whether this token is a comparison or arithmetic operator
+    fn binary_op(&self) -> bool {
+        matches!(
+            self,
+            Tok::Eq
+                | Tok::Ge
+                | Tok::Gt
+                | Tok::Le
+                | Tok::Lt
+                | Tok::Minus
+                | Tok::Ne
+                | Tok::Plus
+                | Tok::Slash
+                | Tok::Star
+        )
+    }
+}
+
+impl Display for Tok {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Tok::Colon => write!(f, "`:`"),
+            Tok::Comma => write!(f, "`,`"),
+            Tok::Eof => write!(f, "end of input"),
+            Tok::Float(s) => write!(f, "`{s}`"),
+            Tok::Ident(s) => write!(f, "`{s}`"),
+            Tok::If => write!(f, "`:-`"),
+            Tok::Int(s) => write!(f, "`{s}`"),
+            Tok::LBrace => write!(f, "`{{`"),
+            Tok::LParen => write!(f, "`(`"),
+            Tok::Eq => write!(f, "`=`"),
+            Tok::Ge => write!(f, "`>=`"),
+            Tok::Gt => write!(f, "`>`"),
+            Tok::Le => write!(f, "`<=`"),
+            Tok::Lt => write!(f, "`<`"),
+            Tok::Minus => write!(f, "`-`"),
+            Tok::Ne => write!(f, "`!=`"),
+            Tok::Not => write!(f, "`!`"),
+            Tok::Plus => write!(f, "`+`"),
+            Tok::Slash => write!(f, "`/`"),
+            Tok::Star => write!(f, "`*`"),
+            Tok::Period => write!(f, "`.`"),
+            Tok::RBrace => write!(f, "`}}`"),
+            Tok::RParen => write!(f, "`)`"),
+            Tok::Str(s) => write!(f, "{s:?}"),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Lexer<'a> {
+    chars: Peekable<Chars<'a>>,
+    pos: Pos,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.chars().peekable(),
+            pos: Pos::default(),
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.pos.line += 1;
+            self.pos.column = 1;
+        } else {
+            self.pos.column += 1;
+        }
+        Some(c)
+    }
+
+    fn skip_trivia(&mut self) -> Result<(), Error> {
+        loop {
+            match self.chars.peek() {
+                Some(c) if c.is_whitespace() => {
+                    self.bump();
+                }
+                Some('%') => self.skip_line(),
+                Some('/') => {
+                    let start = self.pos;
+                    let mut ahead = self.chars.clone();
+                    ahead.next();
+                    match ahead.next() {
+                        Some('/') => self.skip_line(),
+                        Some('*') => {
+                            self.bump();
+                            self.bump();
+                            self.skip_block(start)?;
+                        }
+                        _ => return Ok(()),
+                    }
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.bump() {
+            if c == '\n' {
+                break;
+            }
+        }
+    }
+
+    fn skip_block(&mut self, start: Pos) -> Result<(), Error> {
+        loop {
+            match self.bump() {
+                None => {
+                    return Err(Error::Comment {
+                        span: Span::new(start, self.pos),
+                    })
+                }
+                Some('*') if self.chars.peek() == Some(&'/') => {
+                    self.bump();
+                    return Ok(());
+                }
+                Some(_) => (),
+            }
+        }
+    }
+
+    /// `yes` if the next character is `c` (consuming it), else `no`
+    fn then(&mut self, c: char, yes: Tok, no: Tok) -> Tok {
+        if self.chars.peek() == Some(&c) {
+            self.bump();
+            yes
+        } else {
+            no
+        }
+    }
+
+    fn next(&mut self) -> Result<(Tok, Span), Error> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        let c = match self.bump() {
+            None => return Ok((Tok::Eof, Span::new(start, start))),
+            Some(c) => c,
+        };
+        let tok = match c {
+            ',' => Tok::Comma,
+            '(' => Tok::LParen,
+            '{' => Tok::LBrace,
+            '}' => Tok::RBrace,
+            ')' => Tok::RParen,
+            '.' => Tok::Period,
+            '-' => Tok::Minus,
+            '+' => Tok::Plus,
+            '*' => Tok::Star,
+            // Comments were skipped by `skip_trivia`
+            '/' => Tok::Slash,
+            '=' => Tok::Eq,
+            '!' => self.then('=', Tok::Ne, Tok::Not),
+            '<' => self.then('=', Tok::Le, Tok::Lt),
+            '>' => self.then('=', Tok::Ge, Tok::Gt),
+            ':' => self.then('-', Tok::If, Tok::Colon),
+            '"' => Tok::Str(self.string(start)?),
+            c if c.is_ascii_digit() => self.number(c),
+            c if is_ident_start(c) => {
+                let mut s = String::from(c);
+                while let Some(c) = self.chars.peek().copied() {
+                    if !is_ident_char(c) {
+                        break;
+                    }
+                    s.push(c);
+                    self.bump();
+                }
+                Tok::Ident(s)
+            }
+            c => {
+                return Err(Error::Char {
+                    c,
+                    span: Span::new(start, self.pos),
+                })
+            }
+        };
+        Ok((tok, Span::new(start, self.pos)))
+    }
+}
+
+impl<'a> Lexer<'a> {
+    fn digits(&mut self, s: &mut String) {
+        while let Some(c) = self.chars.peek().copied() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            s.push(c);
+            self.bump();
+        }
+    }
+
+    /// `123`, `1.5`, `1e-7`, `1.5E+10`
+    fn number(&mut self, first: char) -> Tok {
+        let mut s = String::from(first);
+        self.digits(&mut s);
+        let mut float = false;
+        let mut ahead = self.chars.clone();
+        if ahead.next() == Some('.') && ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+            float = true;
+            s.push('.');
+            self.bump();
+            self.digits(&mut s);
+        }
+        let mut ahead = self.chars.clone();
+        if let Some(e @ ('e' | 'E')) = ahead.next() {
+            let sign = ahead.clone().next().filter(|c| *c == '+' || *c == '-');
+            if sign.is_some() {
+                ahead.next();
+            }
+            if ahead.next().is_some_and(|c| c.is_ascii_digit()) {
+                float = true;
+                s.push(e);
+                self.bump();
+                if let Some(sign) = sign {
+                    s.push(sign);
+                    self.bump();
+                }
+                self.digits(&mut s);
+            }
+        }
+        if float {
+            Tok::Float(s)
+        } else {
+            Tok::Int(s)
+        }
+    }
+
+    /// Called after the opening `"`
+    fn string(&mut self, start: Pos) -> Result<String, Error> {
+        let mut s = String::new();
+        loop {
+            let escape = self.pos;
+            match self.bump() {
+                None | Some('\n') => {
+                    return Err(Error::String {
+                        span: Span::new(start, self.pos),
+                    })
+                }
+                Some('"') => return Ok(s),
+                Some('\\') => match self.bump() {
+                    Some('"') => s.push('"'),
+                    Some('\'') => s.push('\''),
+                    Some('\\') => s.push('\\'),
+                    Some('0') => s.push('\0'),
+                    Some('n') => s.push('\n'),
+                    Some('r') => s.push('\r'),
+                    Some('t') => s.push('\t'),
+                    Some('u') => s.push(self.unicode(escape)?),
+                    Some(c) => {
+                        return Err(Error::Escape {
+                            c,
+                            span: Span::new(escape, self.pos),
+                        })
+                    }
+                    None => {
+                        return Err(Error::String {
+                            span: Span::new(start, self.pos),
+                        })
+                    }
+                },
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    /// `\u{7FFF}`, called after the `u`
+    fn unicode(&mut self, escape: Pos) -> Result<char, Error> {
+        let mut hex = String::new();
+        if self.chars.peek() == Some(&'{') {
+            self.bump();
+            while let Some(c) = self.chars.peek().copied() {
+                if !c.is_ascii_hexdigit() {
+                    break;
+                }
+                hex.push(c);
+                self.bump();
+            }
+            if self.chars.peek() == Some(&'}') {
+                self.bump();
+                if let Some(c) = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    return Ok(c);
+                }
+            }
+        }
+        Err(Error::Escape {
+            c: 'u',
+            span: Span::new(escape, self.pos),
+        })
+    }
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_alphabetic() || c == '_'
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// ------------------------------------------------------------------
+
+/// An aggregate in a rule body, e.g., `C = count : { edge(X, _) }`, before
+/// it's replaced by an atom (see [`lower`])
+struct BodyAggregate {
+    result: Var,
+    func: AggFunc,
+    /// `None` for `count`
+    var: Option<Var>,
+    body: Vec<Item>,
+    span: Span,
+}
+
+enum Item {
+    Literal(Literal),
+    Aggregate(BodyAggregate),
+}
+
+impl Item {
+    /// The variables used in this item, including those in the bodies of
+    /// aggregates
+    fn vars(&self) -> Vec<&Var> {
+        match self {
+            Item::Literal(lit) => lit.vars(),
+            Item::Aggregate(agg) => {
+                let mut vars = vec![&agg.result];
+                vars.extend(agg.var.iter());
+                vars.extend(agg.body.iter().flat_map(Item::vars));
+                vars
+            }
+        }
+    }
+}
+
+/// Replace the aggregates in a rule body with atoms of new relations (see
+/// [`Rel::aggregate`]), and add the rules that define them to `rules`. The
+/// variables of an aggregate's body that are used outside of it, in
+/// `outside` or elsewhere in the body, form the groups. As in Soufflé, the
+/// rest of the body must bind them: the aggregate is computed for each of
+/// their values, even those without matches.
+fn lower(outside: &[&Var], items: Vec<Item>, rules: &mut Vec<Rule>) -> Result<Vec<Literal>, Error> {
+    let vars: Vec<Vec<&Var>> = items.iter().map(Item::vars).collect();
+    let mut groups = Vec::with_capacity(items.len());
+    for (i, item) in items.iter().enumerate() {
+        let mut group: Vec<Var> = Vec::new();
+        if let Item::Aggregate(agg) = item {
+            let used = |v: &Var| {
+                outside.contains(&v)
+                    || vars.iter().enumerate().any(|(j, vs)| {
+                        j != i
+                            && match &items[j] {
+                                Item::Literal(_) => vs.contains(&v),
+                                Item::Aggregate(other) => other.result == *v,
+                            }
+                    })
+            };
+            for v in agg.body.iter().flat_map(Item::vars) {
+                if used(v) && !group.contains(v) && *v != agg.result {
+                    group.push(v.clone());
+                }
+            }
+        }
+        groups.push(group);
+    }
+    let mut lits = Vec::with_capacity(items.len());
+    let mut spans = Vec::new();
+    for (item, group) in items.into_iter().zip(groups) {
+        let agg = match item {
+            Item::Literal(lit) => {
+                lits.push(lit);
+                continue;
+            }
+            Item::Aggregate(agg) => agg,
+        };
+        // `count` counts the distinct matches of the body, like `COUNT` of
+        // any of its variables (see crate::eval::aggregate_query)
+        let var = match agg.var {
+            Some(v) => v,
+            None => match agg.body.iter().flat_map(Item::vars).next() {
+                None => return Err(Error::Aggregate { span: agg.span }),
+                Some(v) => v.clone(),
+            },
+        };
+        let mut inner: Vec<&Var> = group.iter().collect();
+        inner.push(&var);
+        let body = lower(&inner, agg.body, rules)?;
+        let mut terms: Vec<Term> = group.iter().cloned().map(Term::Var).collect();
+        terms.push(Term::Aggregate(Aggregate::new(agg.func, var.clone())));
+        // Aggregates with the same definition share a relation
+        let def = Rule::new_with_literals(
+            Atom::new(Rel::new(String::new()), terms.clone()),
+            body.clone(),
+        );
+        let rel = Rel::aggregate(agg.func, fxhash::hash64(&def));
+        let head = Atom::new(rel.clone(), terms).with_span(agg.span);
+        rules.push(Rule::new_with_literals(head, body).with_span(agg.span));
+        let mut terms: Vec<Term> = group.into_iter().map(Term::Var).collect();
+        terms.push(Term::Var(agg.result));
+        spans.push((lits.len(), agg.span));
+        lits.push(Literal::Atom(Atom::new(rel, terms).with_span(agg.span)));
+    }
+    let rule = Rule::new_with_literals(Atom::new(Rel::new(String::new()), Vec::new()), lits);
+    let bound = rule.bound();
+    for (i, span) in spans {
+        let atom = rule.body[i].atom().unwrap();
+        let groups = &atom.terms[..atom.terms.len() - 1];
+        if let Some(var) = groups
+            .iter()
+            .filter_map(Term::var)
+            .find(|v| !bound.contains(v))
+        {
+            return Err(Error::AggregateGroup {
+                var: var.clone(),
+                span,
+            });
+        }
+    }
+    Ok(rule.body)
+}
+
+struct Parser<'a> {
+    lexer: Lexer<'a>,
+    tok: Tok,
+    span: Span,
+    /// The span of the last token consumed by [`Parser::advance`]
+    last: Span,
+    /// The number of `_`s in the current rule
+    wildcards: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Result<Self, Error> {
+        let mut lexer = Lexer::new(src);
+        let (tok, span) = lexer.next()?;
+        Ok(Self {
+            lexer,
+            tok,
+            span,
+            last: span,
+            wildcards: 0,
+        })
+    }
+
+    /// The `n` tokens after the current one
+    fn peek(&self, n: usize) -> Result<Vec<Tok>, Error> {
+        let mut lexer = self.lexer.clone();
+        (0..n).map(|_| Ok(lexer.next()?.0)).collect()
+    }
+
+    fn advance(&mut self) -> Result<(Tok, Span), Error> {
+        let (tok, span) = self.lexer.next()?;
+        let prev_tok = std::mem::replace(&mut self.tok, tok);
+        let prev_span = std::mem::replace(&mut self.span, span);
+        self.last = prev_span;
+        Ok((prev_tok, prev_span))
+    }
+
+    fn expected(&self, expected: &'static str) -> Error {
+        Error::Expected {
+            expected,
+            found: self.tok.to_string(),
+            span: self.span,
+        }
+    }
+
+    fn expect(&mut self, tok: Tok, expected: &'static str) -> Result<Span, Error> {
+        if self.tok == tok {
+            Ok(self.advance()?.1)
+        } else {
+            Err(self.expected(expected))
+        }
+    }
+
+    fn ident(&mut self, expected: &'static str) -> Result<(String, Span), Error> {
+        if let Tok::Ident(_) = self.tok {
+            if let (Tok::Ident(s), span) = self.advance()? {
+                return Ok((s, span));
+            }
+        }
+        Err(self.expected(expected))
+    }
+
+    fn number(&mut self, neg: Option<Span>) -> Result<Const, Error> {
+        let (tok, span) = self.advance()?;
+        let span = neg.map_or(span, |s| s.to(span));
+        let (mut text, float) = match tok {
+            Tok::Int(s) => (s, false),
+            Tok::Float(s) => (s, true),
+            _ => unreachable!(),
+        };
+        if neg.is_some() {
+            text.insert(0, '-');
+        }
+        let c = if float {
+            text.parse()
+                .ok()
+                .filter(|x: &f64| x.is_finite())
+                .map(|x| Const::Float(Float(x)))
+        } else {
+            text.parse().ok().map(Const::Int)
+        };
+        c.ok_or(Error::Number { text, span })
+    }
+
+    fn term(&mut self) -> Result<Term, Error> {
+        match self.tok {
+            Tok::Int(_) | Tok::Float(_) => return Ok(Term::Const(self.number(None)?)),
+            Tok::Minus => {
+                let (_, neg) = self.advance()?;
+                if !matches!(self.tok, Tok::Int(_) | Tok::Float(_)) {
+                    return Err(self.expected("a number"));
+                }
+                return Ok(Term::Const(self.number(Some(neg))?));
+            }
+            Tok::Str(_) => {
+                if let (Tok::Str(s), _) = self.advance()? {
+                    return Ok(Term::Const(Const::Sym(s)));
+                }
+            }
+            _ => (),
+        }
+        let (name, span) = self.ident("a term")?;
+        if let (Some(func), Tok::LParen) = (AggFunc::new(&name), &self.tok) {
+            self.advance()?;
+            let (var, span) = self.ident("a variable")?;
+            if !Var::valid(&var) {
+                return Err(Error::Var { name: var, span });
+            }
+            self.expect(Tok::RParen, "`)`")?;
+            return Ok(Term::Aggregate(Aggregate::new(
+                func,
+                Var::new_unchecked(var),
+            )));
+        }
+        self.named_term(name, span)
+    }
+
+    /// A variable or bare constant. Each `_` is a fresh variable.
+    fn named_term(&mut self, name: String, span: Span) -> Result<Term, Error> {
+        if name == "_" {
+            self.wildcards += 1;
+            // Not a valid variable name, so it can't clash with others
+            Ok(Term::Var(Var::new_unchecked(format!(
+                "_{}",
+                self.wildcards
+            ))))
+        } else if name == "true" || name == "false" {
+            Ok(Term::Const(Const::Bool(name == "true")))
+        } else if Var::valid(&name) {
+            Ok(Term::Var(Var::new_unchecked(name)))
+        } else if Const::valid(&name) {
+            Ok(Term::Const(Const::new_unchecked(name)))
+        } else if name.starts_with('_') {
+            Err(Error::Var { name, span })
+        } else {
+            Err(Error::Const { name, span })
+        }
+    }
+
+    fn atom(&mut self) -> Result<Atom, Error> {
+        let (name, span) = self.ident("a relation name")?;
+        self.atom_named(name, span)
+    }
+
+    fn atom_named(&mut self, name: String, mut span: Span) -> Result<Atom, Error> {
+        let mut terms = Vec::new();
+        if self.tok == Tok::LParen {
+            self.advance()?;
+            if self.tok != Tok::RParen {
+                terms.push(self.term()?);
+                while self.tok == Tok::Comma {
+                    self.advance()?;
+                    terms.push(self.term()?);
+                }
+            }
+            span = span.to(self.expect(Tok::RParen, "`,` or `)`")?);
+        }
+        Ok(Atom::new(Rel::new(name), terms).with_span(span))
+    }
+
+    /// `r(..)`, `!r(..)`, or `not r(..)`. `not` is only a keyword when
+    /// followed by a relation name, so `not` on its own is a nullary atom.
+    fn literal(&mut self) -> Result<Literal, Error> {
+        match self.tok {
+            Tok::Not => {
+                self.advance()?;
+                return Ok(Literal::Not(self.atom()?));
+            }
+            Tok::Ident(_) => (),
+            _ => return Ok(Literal::Cmp(self.comparison(None)?)),
+        }
+        let (name, span) = self.ident("a relation name")?;
+        if name == "not" && matches!(self.tok, Tok::Ident(_)) {
+            return Ok(Literal::Not(self.atom()?));
+        }
+        if Var::valid(&name) || self.tok.binary_op() {
+            let first = Self::operand_term(self.named_term(name, span)?, span)?;
+            return Ok(Literal::Cmp(self.comparison(Some((first, span)))?));
+        }
+        Ok(Literal::Atom(self.atom_named(name, span)?))
+    }
+
+    /// A literal, or an aggregate like `C = count : { edge(X, _) }` or
+    /// `S = sum Y : edge(X, Y)`
+    fn item(&mut self) -> Result<Item, Error> {
+        if matches!(&self.tok, Tok::Ident(name) if Var::valid(name)) {
+            if let [Tok::Eq, Tok::Ident(func), Tok::Colon | Tok::Ident(_)] = &self.peek(3)?[..] {
+                if let Some(func) = AggFunc::new(func) {
+                    return Ok(Item::Aggregate(self.aggregate(func)?));
+                }
+            }
+        }
+        Ok(Item::Literal(self.literal()?))
+    }
+
+    /// Items separated by commas
+    fn items(&mut self) -> Result<Vec<Item>, Error> {
+        let mut items = vec![self.item()?];
+        while self.tok == Tok::Comma {
+            self.advance()?;
+            items.push(self.item()?);
+        }
+        Ok(items)
+    }
+
+    /// See [`Parser::item`]
+    fn aggregate(&mut self, func: AggFunc) -> Result<BodyAggregate, Error> {
+        let (result, start) = self.ident("a variable")?;
+        self.advance()?; // `=`
+        self.advance()?; // the function
+        let var = if func == AggFunc::Count {
+            None
+        } else {
+            let (var, span) = self.ident("a variable")?;
+            if !Var::valid(&var) {
+                return Err(Error::Var { name: var, span });
+            }
+            Some(Var::new_unchecked(var))
+        };
+        self.expect(Tok::Colon, "`:`")?;
+        let body = if self.tok == Tok::LBrace {
+            self.advance()?;
+            let items = self.items()?;
+            self.expect(Tok::RBrace, "`,` or `}`")?;
+            items
+        } else {
+            vec![self.item()?]
+        };
+        Ok(BodyAggregate {
+            result: Var::new_unchecked(result),
+            func,
+            var,
+            body,
+            span: start.to(self.last),
+        })
+    }
+
+    /// `e1 op e2`, where the first operand of `e1` may already be parsed
+    fn comparison(&mut self, first: Option<(Expr, Span)>) -> Result<Comparison, Error> {
+        let start = first.as_ref().map_or(self.span, |(_, s)| *s);
+        let lhs = self.expr(first.map(|(e, _)| e))?;
+        let op = match self.tok {
+            Tok::Eq => CmpOp::Eq,
+            Tok::Ne => CmpOp::Ne,
+            Tok::Lt => CmpOp::Lt,
+            Tok::Le => CmpOp::Le,
+            Tok::Gt => CmpOp::Gt,
+            Tok::Ge => CmpOp::Ge,
+            _ => return Err(self.expected("a comparison operator")),
+        };
+        self.advance()?;
+        let rhs = self.expr(None)?;
+        Ok(Comparison::new(op, lhs, rhs).with_span(start.to(self.last)))
+    }
+
+    /// Sums of products
+    fn expr(&mut self, first: Option<Expr>) -> Result<Expr, Error> {
+        let mut lhs = self.product(first)?;
+        loop {
+            let op = match self.tok {
+                Tok::Plus => ArithOp::Add,
+                Tok::Minus => ArithOp::Sub,
+                _ => return Ok(lhs),
+            };
+            self.advance()?;
+            let rhs = self.product(None)?;
+            lhs = Expr::binary(op, lhs, rhs);
+        }
+    }
+
+    fn product(&mut self, first: Option<Expr>) -> Result<Expr, Error> {
+        let mut lhs = match first {
+            Some(e) => e,
+            None => self.operand()?,
+        };
+        loop {
+            let op = match self.tok {
+                Tok::Star => ArithOp::Mul,
+                Tok::Slash => ArithOp::Div,
+                _ => return Ok(lhs),
+            };
+            self.advance()?;
+            let rhs = self.operand()?;
+            lhs = Expr::binary(op, lhs, rhs);
+        }
+    }
+
+    fn operand(&mut self) -> Result<Expr, Error> {
+        if self.tok == Tok::LParen {
+            self.advance()?;
+            let e = self.expr(None)?;
+            self.expect(Tok::RParen, "an operator or `)`")?;
+            return Ok(e);
+        }
+        let span = self.span;
+        let term = self.term()?;
+        Self::operand_term(term, span)
+    }
+
+    fn operand_term(term: Term, span: Span) -> Result<Expr, Error> {
+        match term {
+            Term::Const(c) => Ok(Expr::Const(c)),
+            Term::Var(v) => Ok(Expr::Var(v)),
+            Term::Aggregate(a) => Err(Error::Expected {
+                expected: "a variable, constant, or `(`",
+                found: format!("`{a}`"),
+                span,
+            }),
+        }
+    }
+
+    /// A rule, then the rules for the aggregates in its body (see [`lower`])
+    fn rule(&mut self) -> Result<Vec<Rule>, Error> {
+        self.wildcards = 0;
+        let head = self.atom()?;
+        let mut items = Vec::new();
+        if self.tok == Tok::If {
+            self.advance()?;
+            if self.tok != Tok::Period {
+                items = self.items()?;
+            }
+        }
+        let end = self.expect(
+            Tok::Period,
+            if items.is_empty() {
+                "`:-` or `.`"
+            } else {
+                "`,` or `.`"
+            },
+        )?;
+        let span = head.span().unwrap_or_default().to(end);
+        let mut rules = Vec::new();
+        let outside: Vec<&Var> = head.terms.iter().filter_map(Term::var).collect();
+        let body = lower(&outside, items, &mut rules)?;
+        rules.insert(0, Rule::new_with_literals(head, body).with_span(span));
+        Ok(rules)
+    }
+
+    fn rules(&mut self) -> Result<Vec<Rule>, Error> {
+        let mut rules = Vec::new();
+        while self.tok != Tok::Eof {
+            rules.extend(self.rule()?);
+        }
+        Ok(rules)
+    }
+
+    /// `number` and `unsigned` are Soufflé's names for integers
+    fn ty(&mut self) -> Result<Type, Error> {
+        let (name, span) = self.ident("a type")?;
+        match name.as_str() {
+            "bool" => Ok(Type::Bool),
+            "int" | "number" | "unsigned" => Ok(Type::Int),
+            "float" => Ok(Type::Float),
+            "symbol" => Ok(Type::Sym),
+            _ => Err(Error::Type { name, span }),
+        }
+    }
+
+    /// `.decl r(x: symbol, y: int)`, after the `.decl`
+    fn decl(&mut self, start: Span) -> Result<RelDecl, Error> {
+        let (name, _) = self.ident("a relation name")?;
+        self.expect(Tok::LParen, "`(`")?;
+        let mut cols = Vec::new();
+        while self.tok != Tok::RParen {
+            if !cols.is_empty() {
+                self.expect(Tok::Comma, "`,` or `)`")?;
+            }
+            let (col, _) = self.ident("a column name")?;
+            self.expect(Tok::Colon, "`:`")?;
+            cols.push(Column::new(col, self.ty()?));
+        }
+        let end = self.advance()?.1;
+        Ok(RelDecl::new(Rel::new(name), cols).with_span(start.to(end)))
+    }
+
+    /// `.input r(IO=file, filename="r.csv")`, after the `.input` or
+    /// `.output`. Values may be strings, names, or numbers.
+    fn io(&mut self, start: Span) -> Result<(Rel, IoParams, Span), Error> {
+        let (name, mut span) = self.ident("a relation name")?;
+        let mut params = IoParams::new();
+        if self.tok == Tok::LParen {
+            self.advance()?;
+            while self.tok != Tok::RParen {
+                if !params.is_empty() {
+                    self.expect(Tok::Comma, "`,` or `)`")?;
+                }
+                let (key, _) = self.ident("a parameter name")?;
+                self.expect(Tok::Eq, "`=`")?;
+                let value = match self.advance()? {
+                    (Tok::Str(s) | Tok::Ident(s) | Tok::Int(s) | Tok::Float(s), _) => s,
+                    (tok, span) => {
+                        return Err(Error::Expected {
+                            expected: "a parameter value",
+                            found: tok.to_string(),
+                            span,
+                        })
+                    }
+                };
+                params.insert(key, value);
+            }
+            span = self.advance()?.1;
+        }
+        Ok((Rel::new(name), params, start.to(span)))
+    }
+
+    /// Rules, and `.decl`, `.input`, and `.output` directives, which needn't
+    /// end with a `.`
+    fn program(&mut self) -> Result<(Vec<RelDecl>, Vec<Rule>), Error> {
+        let mut decls: Vec<RelDecl> = Vec::new();
+        // Whether each is an `.input`, and its relation and parameters
+        let mut io = Vec::new();
+        let mut rules = Vec::new();
+        while self.tok != Tok::Eof {
+            if self.tok != Tok::Period {
+                rules.extend(self.rule()?);
+                continue;
+            }
+            let start = self.advance()?.1;
+            let (name, span) = self.ident("a directive")?;
+            match name.as_str() {
+                "decl" => decls.push(self.decl(start)?),
+                "input" | "output" => io.push((name == "input", self.io(start)?)),
+                _ => {
+                    return Err(Error::Directive {
+                        name,
+                        span: start.to(span),
+                    })
+                }
+            }
+        }
+        for (input, (rel, params, span)) in io {
+            let decl = match decls.iter_mut().find(|d| d.rel == rel) {
+                None => {
+                    return Err(Error::Undeclared {
+                        relation: rel,
+                        span,
+                    })
+                }
+                Some(decl) => decl,
+            };
+            if input {
+                decl.input = Some(params);
+            } else {
+                decl.output = Some(params);
+            }
+        }
+        Ok((decls, rules))
+    }
+}
+
+/// Parse and validate a program, including `.decl`, `.input`, and `.output`
+/// directives in the style of Soufflé:
+///
+/// ```text
+/// .decl edge(x: symbol, y: symbol)
+/// .input edge(IO=file, filename="edge.csv", delimiter=",")
+/// .decl path(x: symbol, y: symbol)
+/// .output path
+/// ```
+///
+/// Types are `symbol`, `int` (or `number`), `float`, and `bool`. The
+/// parameters of `.input` and `.output` are kept in [`RelDecl::input`] and
+/// [`RelDecl::output`].
+pub fn parse(src: &str) -> Result<Ast, Error> {
+    let (decls, rules) = Parser::new(src)?.program()?;
+    Ok(Ast::new_with_decls(decls, rules)?)
+}
+
+/// Parse a sequence of rules, without validating them.
+pub fn parse_rules(src: &str) -> Result<Vec<Rule>, Error> {
+    Parser::new(src)?.rules()
+}
+
+// ------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(line: usize, column: usize) -> Pos {
+        Pos { line, column }
+    }
+
+    #[test]
+    fn parse_tc() {
+        let ast = parse(
+            r"
+            // Transitive closure
+            edge(a, b).
+            edge(b, c).
+            path(X, Y) :- edge(X, Y).
+            path(X, Z) :- edge(X, Y), path(Y, Z).
+            ",
+        )
+        .unwrap();
+        let rules: Vec<_> = ast.rules.iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            vec![
+                "edge(a, b) :- .",
+                "edge(b, c) :- .",
+                "path(X, Y) :- edge(X, Y).",
+                "path(X, Z) :- edge(X, Y), path(Y, Z).",
+            ],
+            rules
+        );
+    }
+
+    #[test]
+    fn parse_nullary() {
+        let ast = parse("r. s :- r(). % comment").unwrap();
+        let rules: Vec<_> = ast.rules.iter().map(|r| r.to_string()).collect();
+        assert_eq!(vec!["r() :- .", "s() :- r()."], rules);
+    }
+
+    #[test]
+    fn parse_comments() {
+        let rules = parse_rules("/* a\n * block */ r(c). // line\n% line\ns(d).").unwrap();
+        assert_eq!(2, rules.len());
+    }
+
+    #[test]
+    fn parse_round_trip() {
+        let src = "path(X, Z) :- edge(X, Y), path(Y, Z).\nedge(a, b) :- .";
+        let rules = parse_rules(src).unwrap();
+        let printed: Vec<_> = rules.iter().map(|r| r.to_string()).collect();
+        assert_eq!(src, printed.join("\n"));
+    }
+
+    #[test]
+    fn parse_negation() {
+        let rules = parse_rules("r(X) :- s(X), !t(X), not u(X, a).\nnot.\nv :- not.").unwrap();
+        assert_eq!("r(X) :- s(X), !t(X), !u(X, a).", rules[0].to_string());
+        assert!(!rules[0].body[0].is_negative());
+        assert!(rules[0].body[1].is_negative());
+        assert!(rules[0].body[2].is_negative());
+        // `not` is an ordinary relation name when it isn't followed by one
+        assert_eq!("v() :- not().", rules[2].to_string());
+        assert!(!rules[2].body[0].is_negative());
+        // Wildcards in negated atoms needn't be bound
+        let ast = parse("s(X) :- n(X), !e(X, _).").unwrap();
+        assert_eq!("s(X) :- n(X), !e(X, _).", ast.rules[0].to_string());
+    }
+
+    #[test]
+    fn parse_aggregates() {
+        let rules = parse_rules("deg(X, count(Y)) :- edge(X, Y).\ncount(a).").unwrap();
+        assert_eq!("deg(X, count(Y)) :- edge(X, Y).", rules[0].to_string());
+        assert!(rules[0].is_aggregate());
+        // `count` is an ordinary relation name when it isn't a term
+        assert_eq!("count(a) :- .", rules[1].to_string());
+        assert!(matches!(
+            parse_rules("r(sum(a)) :- s(a)."),
+            Err(Error::Var { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_body_aggregates() {
+        let rules = parse_rules("n(X, C) :- node(X), C = count : { e(X, _), !f(_) }.").unwrap();
+        assert_eq!(2, rules.len());
+        let rel = rules[1].head().rel();
+        assert!(rel.is_aggregate());
+        assert_eq!(
+            format!("n(X, C) :- node(X), {rel}(X, C)."),
+            rules[0].to_string()
+        );
+        assert_eq!(
+            format!("{rel}(X, count(X)) :- e(X, _), !f(_)."),
+            rules[1].to_string()
+        );
+        // Variables only used in the aggregate aren't groups
+        let rules = parse_rules("s(T) :- T = sum Y : e(X, Y).").unwrap();
+        assert_eq!(
+            format!("{}(sum(Y)) :- e(X, Y).", rules[1].head().rel()),
+            rules[1].to_string()
+        );
+        // The same aggregate gets the same relation
+        let rules = parse_rules("r(C) :- C = count : e(X). s(C) :- C = count : e(X).").unwrap();
+        assert_eq!(rules[1], rules[3]);
+        // `count` is still a constant
+        let rules = parse_rules("r(X) :- s(X), X = count.").unwrap();
+        assert_eq!("r(X) :- s(X), X = count.", rules[0].to_string());
+        assert!(matches!(
+            parse_rules("r(C) :- C = count : e(a)."),
+            Err(Error::Aggregate { .. })
+        ));
+        assert!(matches!(
+            parse_rules("r(C) :- C = sum : e(X)."),
+            Err(Error::Expected { .. })
+        ));
+        // As in Soufflé, groups are bound outside of the aggregate
+        assert!(matches!(
+            parse_rules("n(X, C) :- C = count : { edge(X, _) }."),
+            Err(Error::AggregateGroup { .. })
+        ));
+        assert!(matches!(
+            parse_rules("n(X, C) :- D = min Y : e(X, Y), C = max Y : e(X, Y)."),
+            Err(Error::AggregateGroup { .. })
+        ));
+        assert!(parse_rules("n(X, C) :- e(X, _), C = count : { e(X, _) }.").is_ok());
+        assert!(parse_rules("n(Y, C) :- e(X, _), Y = X, C = count : e(Y, _).").is_ok());
+    }
+
+    #[test]
+    fn render_err_aggregate() {
+        let src = "r(X, count(Y)) :- s(X, Y).\ns(X, Y) :- r(X, Y).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: aggregation through recursion: `r` -> `s` -> `r`
+ --> prog.dl:1:19
+  |
+1 | r(X, count(Y)) :- s(X, Y).
+  |                   ^^^^^^^ `r` aggregates over itself through this atom
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+        let src = "r(X) :- s(X, count(X)).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            "aggregate `count(X)` outside of a rule head",
+            err.to_string()
+        );
+        let src = "r(sum(X)) :- s(X).\ns(a).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            "cannot compute `sum(X)` of values of type `symbol`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_comparisons() {
+        let rules = parse_rules(
+            r#"
+            r(X) :- s(X, Y), X != Y, X < 10, Y >= -1.5, a = X, "b c" <= X.
+            r(M) :- s(N, P), M = (N + 1) * 2 - N / 3, N > P.
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            r#"r(X) :- s(X, Y), X != Y, X < 10, Y >= -1.5, a = X, "b c" <= X."#,
+            rules[0].to_string()
+        );
+        assert_eq!(
+            "r(M) :- s(N, P), M = ((N + 1) * 2) - (N / 3), N > P.",
+            rules[1].to_string()
+        );
+        // Printed with explicit parentheses, which parse the same way
+        let again = parse_rules(&rules[1].to_string()).unwrap();
+        assert_eq!(rules[1], again[0]);
+        assert_eq!(
+            Some(Span::new(pos(3, 30), pos(3, 53))),
+            rules[1].body[1].span()
+        );
+    }
+
+    #[test]
+    fn parse_err_comparison() {
+        assert!(matches!(
+            parse_rules("r(X) :- s(X), X."),
+            Err(Error::Expected {
+                expected: "a comparison operator",
+                ..
+            })
+        ));
+        assert!(matches!(
+            parse_rules("r(X) :- s(X), X = count(X)."),
+            Err(Error::Expected { .. })
+        ));
+    }
+
+    #[test]
+    fn render_err_builtin() {
+        let src = "r(X) :- s(X), X < 1, X = a.\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: comparison `X = a` between `int` and `symbol`
+ --> prog.dl:1:22
+  |
+1 | r(X) :- s(X), X < 1, X = a.
+  |                      ^^^^^ `int` compared with `symbol`
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+        let err = parse("r(X) :- s(X, Y), X = Y + 1, s(a, b).").unwrap_err();
+        assert_eq!("arithmetic on `symbol` in `Y + 1`", err.to_string());
+        let err = parse("r(X) :- s(Y), X < Y.").unwrap_err();
+        assert_eq!(
+            "ungrounded variable `X` in rule `r(X) :- s(Y), X < Y.`",
+            err.to_string()
+        );
+    }
+
+    #[test]
+    fn parse_typed_consts() {
+        let rules =
+            parse_rules(r#"r(1, -2, 1.5, -1e-7, true, false, "o'brien", "a\"b\n")."#).unwrap();
+        assert_eq!(
+            vec![
+                Term::Const(Const::Int(1)),
+                Term::Const(Const::Int(-2)),
+                Term::Const(Const::Float(Float(1.5))),
+                Term::Const(Const::Float(Float(-1e-7))),
+                Term::Const(Const::Bool(true)),
+                Term::Const(Const::Bool(false)),
+                Term::Const(Const::Sym(String::from("o'brien"))),
+                Term::Const(Const::Sym(String::from("a\"b\n"))),
+            ],
+            rules[0].head.terms
+        );
+        // Printing and re-parsing is the identity
+        let printed = rules[0].to_string();
+        assert_eq!(rules, parse_rules(&printed).unwrap());
+    }
+
+    #[test]
+    fn parse_err_number() {
+        assert!(matches!(
+            parse_rules("r(99999999999999999999)."),
+            Err(Error::Number { .. })
+        ));
+        // SQL has no literal for infinity, see [`crate::backend::literal`]
+        assert!(matches!(
+            parse_rules("p(1e999) :- q(X)."),
+            Err(Error::Number { .. })
+        ));
+        assert!(matches!(
+            parse_rules("r(-1e999)."),
+            Err(Error::Number { .. })
+        ));
+        assert!(matches!(parse_rules("r(-x)."), Err(Error::Expected { .. })));
+    }
+
+    #[test]
+    fn parse_err_string() {
+        assert_eq!(
+            Err(Error::String {
+                span: Span::new(pos(1, 3), pos(1, 7)),
+            }),
+            parse_rules("r(\"abc")
+        );
+        assert!(matches!(
+            parse_rules(r#"r("\q")."#),
+            Err(Error::Escape { c: 'q', .. })
+        ));
+    }
+
+    #[test]
+    fn render_err_type() {
+        let src = "r(1).\nr(a).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: column 0 of relation `r` used with multiple types: `int`, `symbol`
+ --> prog.dl:2:1
+  |
+2 | r(a).
+  | ^^^^ used as `symbol`
+  |
+note: first used as `int`
+ --> prog.dl:1:1
+  |
+1 | r(1).
+  | ^^^^
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+    }
+
+    #[test]
+    fn parse_err_period() {
+        assert_eq!(
+            Err(Error::Expected {
+                expected: "`,` or `.`",
+                found: String::from("end of input"),
+                span: Span::new(pos(2, 13), pos(2, 13)),
+            }),
+            parse_rules("r(a).\ns(X) :- r(X)")
+        );
+    }
+
+    #[test]
+    fn parse_err_char() {
+        assert_eq!(
+            Err(Error::Char {
+                c: '$',
+                span: Span::new(pos(1, 3), pos(1, 4)),
+            }),
+            parse_rules("r($).")
+        );
+    }
+
+    #[test]
+    fn parse_err_var() {
+        assert!(matches!(parse_rules("r(_x)."), Err(Error::Var { .. })));
+    }
+
+    #[test]
+    fn parse_err_comment() {
+        assert_eq!(
+            Err(Error::Comment {
+                span: Span::new(pos(1, 7), pos(1, 13)),
+            }),
+            parse_rules("r(a).\t/* ...")
+        );
+    }
+
+    #[test]
+    fn parse_spans() {
+        let rules = parse_rules("r(a).\n  s(X) :-\n    r(X).").unwrap();
+        assert_eq!(Some(Span::new(pos(1, 1), pos(1, 6))), rules[0].span());
+        assert_eq!(Some(Span::new(pos(1, 1), pos(1, 5))), rules[0].head.span());
+        assert_eq!(Some(Span::new(pos(2, 3), pos(3, 10))), rules[1].span());
+        assert_eq!(
+            Some(Span::new(pos(3, 5), pos(3, 9))),
+            rules[1].body[0].span()
+        );
+    }
+
+    #[test]
+    fn render_err_arity() {
+        let src = "r(a).\ns(X) :- r(X, X).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: relation `r` used with multiple arities: `1`, `2`
+ --> prog.dl:2:9
+  |
+2 | s(X) :- r(X, X).
+  |         ^^^^^^^ used with arity 2
+  |
+note: first used with arity 1
+ --> prog.dl:1:1
+  |
+1 | r(a).
+  | ^^^^
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+    }
+
+    #[test]
+    fn render_err_ungrounded() {
+        let src = "r(a).\ns(X, Y) :- r(X).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: ungrounded variable `Y` in rule `s(X, Y) :- r(X).`
+ --> prog.dl:2:1
+  |
+2 | s(X, Y) :- r(X).
+  | ^^^^^^^ variable `Y` not bound in the body
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+    }
+
+    #[test]
+    fn render_err_ungrounded_negation() {
+        let src = "n(a).\ns(X) :- n(X), !e(X, Y), !e(_, X).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: ungrounded variable `Y` in rule `s(X) :- n(X), !e(X, Y), !e(_, X).`
+ --> prog.dl:2:16
+  |
+2 | s(X) :- n(X), !e(X, Y), !e(_, X).
+  |                ^^^^^^^ variable `Y` not bound in the body
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+    }
+
+    #[test]
+    fn render_err_negation() {
+        let src = "p(X) :- r(X), !q(X).\nq(X) :- p(X).\n";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: negation through recursion: `p` -> `q` -> `p`
+ --> prog.dl:1:16
+  |
+1 | p(X) :- r(X), !q(X).
+  |                ^^^^ `p` depends on itself through this negation
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+    }
+
+    #[test]
+    fn render_err_char() {
+        let src = "r($).";
+        let err = parse(src).unwrap_err();
+        assert_eq!(
+            r"error: unexpected character `$`
+ --> prog.dl:1:3
+  |
+1 | r($).
+  |   ^
+",
+            err.diagnostic().render("prog.dl", src)
+        );
+    }
+
+    #[test]
+    fn parse_err_arity() {
+        assert!(matches!(
+            parse("r(a). r(a, b)."),
+            Err(Error::Ast(e)) if matches!(*e, ast::Error::Arity { .. })
+        ));
+    }
+
+    #[test]
+    fn parse_directives() {
+        let ast = parse(include_str!("../examples/tc.dl")).unwrap();
+        assert_eq!(
+            r#".decl edge(X: symbol, Y: symbol)
+.input edge(IO="file", filename="edge.csv", rfc4180="true")
+.decl path(X: symbol, Y: symbol)
+.output path(IO="file", filename="path.csv", rfc4180="true")
+path(X, Y) :- edge(X, Y).
+path(X, Z) :- path(X, Y), edge(Y, Z).
+"#,
+            ast.to_string()
+        );
+        // Printed declarations parse the same way
+        assert_eq!(ast, parse(&ast.to_string()).unwrap());
+
+        let ast = parse(
+            r#"
+            .output r
+            .decl r(n: number, x: float, b: bool)
+            .input s(delimiter="\t") .decl s(x: unsigned)
+            r(N, 1.5, true) :- s(N).
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            ".decl r(n: int, x: float, b: bool)\n.output r",
+            ast.decls()[0].to_string()
+        );
+        assert_eq!("\t", ast.decls()[1].input().unwrap()["delimiter"]);
+        assert_eq!(None, ast.decls()[1].output());
+        let ast = parse(".decl e(x: int) .input e .output e(IO=stdout)").unwrap();
+        assert_eq!(
+            ".decl e(x: int)\n.input e\n.output e(IO=\"stdout\")",
+            ast.decls()[0].to_string()
+        );
+    }
+
+    #[test]
+    fn parse_err_directives() {
+        assert!(matches!(
+            parse(".include \"x.dl\""),
+            Err(Error::Directive { .. })
+        ));
+        assert!(matches!(
+            parse(".decl r(x: string)"),
+            Err(Error::Type { .. })
+        ));
+        assert!(matches!(
+            parse(".decl r(x symbol)"),
+            Err(Error::Expected { .. })
+        ));
+        assert!(matches!(parse(".input r"), Err(Error::Undeclared { .. })));
+        assert!(matches!(
+            parse(".decl r(x: int) r(a)."),
+            Err(Error::Ast(e)) if matches!(*e, ast::Error::Type { .. })
+        ));
+    }
+}
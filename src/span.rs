@@ -0,0 +1,79 @@
+use std::fmt::Display;
+
+/// A position in source text. Lines and columns start at 1, columns count
+/// characters (not bytes).
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Pos {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Default for Pos {
+    fn default() -> Self {
+        Self { line: 1, column: 1 }
+    }
+}
+
+impl Display for Pos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A range of source text. `end` is exclusive.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Span {
+    pub start: Pos,
+    pub end: Pos,
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.start)
+    }
+}
+
+impl Span {
+    pub fn new(start: Pos, end: Pos) -> Self {
+        Self { start, end }
+    }
+
+    /// The smallest span containing both `self` and `other`.
+    pub fn to(self, other: Span) -> Self {
+        Self {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// An optional [`Span`] attached to a piece of syntax.
+///
+/// Locations are metadata: all `Loc`s compare equal and hash identically, so
+/// that the same rule parsed from two places is still the same rule.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Loc(pub Option<Span>);
+
+impl PartialEq for Loc {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+impl Eq for Loc {}
+
+impl PartialOrd for Loc {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Loc {
+    fn cmp(&self, _other: &Self) -> std::cmp::Ordering {
+        std::cmp::Ordering::Equal
+    }
+}
+
+impl std::hash::Hash for Loc {
+    fn hash<H: std::hash::Hasher>(&self, _state: &mut H) {}
+}
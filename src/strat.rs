@@ -0,0 +1,259 @@
+//! Predicate dependency graphs and stratification.
+
+use fxhash::{FxHashMap as HashMap, FxHashSet as HashSet};
+
+use crate::ast::{Atom, Rel, Rule};
+
+/// A dependency of a relation on another.
+#[derive(Clone, Copy, Debug)]
+struct Dep<'a> {
+    on: usize,
+    /// Negative dependencies must be fully computed first. So must all
+    /// dependencies of rules with aggregates, and the results of aggregates
+    /// in rule bodies, which are 0 for `count` and `sum` of nothing.
+    negative: bool,
+    /// Whether this dependency comes from a rule with an aggregate, or is on
+    /// the results of one
+    aggregate: bool,
+    /// The body atom that gives rise to this dependency
+    atom: &'a Atom,
+}
+
+/// Relation `p` depends on `q` if there's a rule with `p` in the head and `q`
+/// in the body.
+#[derive(Debug)]
+pub(crate) struct Graph<'a> {
+    rels: Vec<&'a Rel>,
+    ids: HashMap<&'a Rel, usize>,
+    deps: Vec<Vec<Dep<'a>>>,
+}
+
+/// A cycle through a negative or aggregate dependency, see [`Graph::stratify`].
+#[derive(Debug)]
+pub(crate) struct Cycle<'a> {
+    /// Starts and ends with the same relation
+    pub(crate) rels: Vec<Rel>,
+    /// The negated atom, or the body atom of an aggregating rule
+    pub(crate) atom: &'a Atom,
+    /// Whether the cycle goes through an aggregate rather than a negation
+    pub(crate) aggregate: bool,
+}
+
+impl<'a> Graph<'a> {
+    pub(crate) fn new(rules: impl IntoIterator<Item = &'a Rule>) -> Self {
+        let mut graph = Self {
+            rels: Vec::new(),
+            ids: HashMap::default(),
+            deps: Vec::new(),
+        };
+        for rule in rules {
+            let head = graph.id(&rule.head.rel);
+            for lit in &rule.body {
+                if let Some(atom) = lit.atom() {
+                    let on = graph.id(&atom.rel);
+                    let aggregate = rule.is_aggregate() || atom.rel.is_aggregate();
+                    graph.deps[head].push(Dep {
+                        on,
+                        negative: lit.is_negative() || aggregate,
+                        aggregate,
+                        atom,
+                    });
+                }
+            }
+        }
+        graph
+    }
+
+    fn id(&mut self, rel: &'a Rel) -> usize {
+        if let Some(id) = self.ids.get(rel) {
+            return *id;
+        }
+        let id = self.rels.len();
+        self.rels.push(rel);
+        self.ids.insert(rel, id);
+        self.deps.push(Vec::new());
+        id
+    }
+
+    /// Strongly connected components, in topological order: each component
+    /// comes after the components it depends on.
+    ///
+    /// Tarjan's algorithm, made iterative so that long chains of rules don't
+    /// overflow the stack.
+    pub(crate) fn sccs(&self) -> Vec<Vec<&'a Rel>> {
+        let n = self.rels.len();
+        let mut index = vec![usize::MAX; n];
+        let mut low = vec![0; n];
+        let mut on_stack = vec![false; n];
+        let mut stack = Vec::new();
+        let mut sccs = Vec::new();
+        let mut next = 0;
+        for root in 0..n {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            // (node, index of the next dependency to visit)
+            let mut work = vec![(root, 0)];
+            index[root] = next;
+            low[root] = next;
+            next += 1;
+            stack.push(root);
+            on_stack[root] = true;
+            while let Some((v, i)) = work.pop() {
+                if let Some(dep) = self.deps[v].get(i) {
+                    work.push((v, i + 1));
+                    let w = dep.on;
+                    if index[w] == usize::MAX {
+                        index[w] = next;
+                        low[w] = next;
+                        next += 1;
+                        stack.push(w);
+                        on_stack[w] = true;
+                        work.push((w, 0));
+                    } else if on_stack[w] {
+                        low[v] = low[v].min(index[w]);
+                    }
+                    continue;
+                }
+                // Done with `v`
+                if let Some((parent, _)) = work.last() {
+                    low[*parent] = low[*parent].min(low[v]);
+                }
+                if low[v] == index[v] {
+                    let mut scc = Vec::new();
+                    loop {
+                        let w = stack.pop().unwrap();
+                        on_stack[w] = false;
+                        scc.push(self.rels[w]);
+                        if w == v {
+                            break;
+                        }
+                    }
+                    sccs.push(scc);
+                }
+            }
+        }
+        sccs
+    }
+
+    /// Assign each relation a stratum, such that each relation's stratum is
+    /// at least that of its dependencies, and strictly greater than that of its
+    /// negative dependencies.
+    pub(crate) fn stratify(&self) -> Result<HashMap<&'a Rel, usize>, Cycle<'a>> {
+        let mut strata: HashMap<&'a Rel, usize> = HashMap::default();
+        for scc in self.sccs() {
+            let members: HashSet<usize> = scc.iter().map(|r| self.ids[r]).collect();
+            let mut stratum = 0;
+            for v in &members {
+                for dep in &self.deps[*v] {
+                    if members.contains(&dep.on) {
+                        if dep.negative {
+                            return Err(self.cycle(&members, *v, *dep));
+                        }
+                    } else {
+                        let s = strata[self.rels[dep.on]] + usize::from(dep.negative);
+                        stratum = stratum.max(s);
+                    }
+                }
+            }
+            for rel in scc {
+                strata.insert(rel, stratum);
+            }
+        }
+        Ok(strata)
+    }
+
+    /// Find a cycle `from`, `dep.on`, ..., `from` within an SCC.
+    fn cycle(&self, members: &HashSet<usize>, from: usize, dep: Dep<'a>) -> Cycle<'a> {
+        let mut prev: HashMap<usize, usize> = HashMap::default();
+        let mut queue = std::collections::VecDeque::from([dep.on]);
+        let mut seen = HashSet::from_iter([dep.on]);
+        while let Some(v) = queue.pop_front() {
+            if v == from {
+                break;
+            }
+            for d in &self.deps[v] {
+                if members.contains(&d.on) && seen.insert(d.on) {
+                    prev.insert(d.on, v);
+                    queue.push_back(d.on);
+                }
+            }
+        }
+        // Walk back from `from` to `dep.on`
+        let mut path = vec![from];
+        let mut v = from;
+        while v != dep.on {
+            v = prev[&v];
+            path.push(v);
+        }
+        path.push(from);
+        path.reverse();
+        let rels = path.into_iter().map(|v| self.rels[v].clone()).collect();
+        Cycle {
+            rels,
+            atom: dep.atom,
+            aggregate: dep.aggregate,
+        }
+    }
+}
+
+/// Group rules by the stratum of their heads, in evaluation order.
+pub(crate) fn strata<'a>(
+    rules: impl IntoIterator<Item = &'a Rule> + Clone,
+) -> Result<Vec<Vec<&'a Rule>>, Cycle<'a>> {
+    let graph = Graph::new(rules.clone());
+    let strata = graph.stratify()?;
+    let mut grouped: Vec<Vec<&'a Rule>> = Vec::new();
+    for rule in rules {
+        let s = strata[&rule.head.rel];
+        if grouped.len() <= s {
+            grouped.resize_with(s + 1, Vec::new);
+        }
+        grouped[s].push(rule);
+    }
+    grouped.retain(|rules| !rules.is_empty());
+    Ok(grouped)
+}
+
+// ------------------------------------------------------------------
+
+#[cfg(test)]
+mod tests {
+    use crate::parse::parse_rules;
+
+    use super::*;
+
+    #[test]
+    fn strata_order() {
+        let rules = parse_rules(
+            r"
+            c(X) :- b(X), !a(X).
+            b(X) :- a(X), b(X).
+            b(X) :- e(X).
+            a(X) :- e(X), !d(X).
+            ",
+        )
+        .unwrap();
+        let strata = strata(&rules).unwrap();
+        let heads: Vec<Vec<String>> = strata
+            .iter()
+            .map(|s| s.iter().map(|r| r.head.rel.to_string()).collect())
+            .collect();
+        assert_eq!(vec![vec!["b", "b", "a"], vec!["c"]], heads);
+    }
+
+    #[test]
+    fn strata_body_aggregate() {
+        // `isolated` must wait for all the counts, including the missing ones
+        let rules = parse_rules(
+            r"
+            isolated(X) :- node(X), C = count : { edge(X, _) }, C = 0.
+            ",
+        )
+        .unwrap();
+        let strata = strata(&rules).unwrap();
+        assert_eq!(2, strata.len());
+        assert!(strata[0][0].head.rel.is_aggregate());
+        assert_eq!("isolated", strata[1][0].head.rel.to_string());
+    }
+}